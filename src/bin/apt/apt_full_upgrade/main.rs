@@ -1,11 +1,49 @@
+use pika_unixsocket_tools::apt_helper_args::{usage, AptHelperArgs, EXIT_CODE_USAGE};
+use pika_unixsocket_tools::apt_helper_error::{
+    exit_with_error, AptHelperError, AptHelperErrorKind,
+};
 use pika_unixsocket_tools::apt_install_progress_socket::AptInstallProgressSocket;
 use pika_unixsocket_tools::apt_update_progress_socket::AptUpdateProgressSocket;
-use pika_unixsocket_tools::pika_unixsocket_tools::*;
-use rust_apt::cache::Upgrade;
+use pika_unixsocket_tools::conffile_conflicts::{
+    find_conffile_conflicts, DPKG_CONFFILE_OPTIONS, DPKG_INFO_DIR,
+};
+use pika_unixsocket_tools::debconf_passthrough::{
+    bind_debconf_socket, serve_debconf, write_dpkg_wrapper,
+};
+use pika_unixsocket_tools::kernels::old_kernels;
+use pika_unixsocket_tools::offline_upgrade::{
+    stage_offline_upgrade, take_offline_upgrade_plan, OfflineUpgradePlan, OfflineUpgradeResult,
+};
+use pika_unixsocket_tools::phased_updates::PhasingPolicy;
+use pika_unixsocket_tools::pika_unixsocket_tools::ProgressSocketClient;
+use pika_unixsocket_tools::progress_protocol::{
+    ChangeAction, ConflictAnswer, PlannedChange, ProgressMessage, ProgressReply,
+};
+use pika_unixsocket_tools::session_socket::{invoking_uid, validate_session_socket_dir};
+use pika_unixsocket_tools::snapshots::SnapshotRecord;
+use pika_unixsocket_tools::system_cleanup::{
+    autoremovable_packages, cached_archives, clean_cached_archives, total_size,
+};
+use pika_unixsocket_tools::transaction_history::{
+    user_name, HistoryEntry, HistoryPackage, HistoryRecorder, TransactionKind, SYSTEM_HISTORY_FILE,
+};
+use pika_unixsocket_tools::update_discovery::{is_excluded, APT_ARCHIVES_DIR};
+use pika_unixsocket_tools::update_holds::{ExclusionsFile, UpdateHolds};
+use rust_apt::cache::{Cache, PackageSort, Upgrade};
+use rust_apt::config::Config;
 use rust_apt::new_cache;
 use rust_apt::progress::{AcquireProgress, InstallProgress};
-use std::env;
-use tokio::runtime::Runtime;
+use rust_apt::util::cmp_versions;
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// How long a configuration file question may stay unanswered before it is left for later.
+const CONFFILE_ANSWER_TIMEOUT: Duration = Duration::from_secs(30 * 60);
 
 // Init translations for current crate.
 #[macro_use]
@@ -13,59 +51,313 @@ extern crate rust_i18n;
 i18n!("locales", fallback = "en_US");
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    rust_i18n::set_locale(&args[1]);
-
-    let percent_socket_path = "/tmp/pika_apt_upgrade_percent.sock";
-    let status_socket_path = "/tmp/pika_apt_upgrade_status.sock";
-    let speed_socket_path = "/tmp/pika_apt_upgrade_speed.sock";
-
-    let json_file_path = "/tmp/pika-apt-exclusions.json";
-    let mut excluded_updates_vec: Vec<String> = Vec::new();
-
-    if std::path::Path::new(json_file_path).exists() {
-        let data = std::fs::read_to_string(json_file_path).expect("Unable to read file");
-        let json: serde_json::Value =
-            serde_json::from_str(&data).expect("JSON was not well-formatted");
-
-        if let serde_json::Value::Array(exclusions) = &json["exclusions"] {
-            for exclusion in exclusions {
-                match exclusion["package"].as_str() {
-                    Some(v) => {
-                        excluded_updates_vec.push(v.to_owned());
-                    }
-                    None => {}
+    let args = match AptHelperArgs::from_env() {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, usage("apt_full_upgrade"));
+            exit(EXIT_CODE_USAGE)
+        }
+    };
+    if args.help {
+        println!("{}", usage("apt_full_upgrade"));
+        return;
+    }
+    rust_i18n::set_locale(&args.locale());
+
+    // The GUI hands us its private session directory, anything else is not trusted
+    let socket_client = RefCell::new(
+        match &args.socket_dir {
+            Some(socket_dir) => match validate_session_socket_dir(socket_dir, invoking_uid()) {
+                Ok(socket_path) => ProgressSocketClient::new(&socket_path.to_string_lossy()),
+                Err(e) => {
+                    eprintln!("Not reporting progress: {}", e);
+                    ProgressSocketClient::disconnected()
                 }
+            },
+            None => ProgressSocketClient::disconnected(),
+        }
+        .with_fallback_log("/var/log/pikman-update-manager/apt_full_upgrade.log")
+        .with_console_output(args.console_output()),
+    );
+    if let Err(e) = args.check_authorized() {
+        exit_with_error(
+            &socket_client,
+            AptHelperError::new(AptHelperErrorKind::NotAuthorized, e),
+        )
+    }
+    if args.offline {
+        socket_client
+            .borrow_mut()
+            .add_observer(OfflineUpgradeResult::default());
+    }
+
+    // Offline the staged plan says what to leave out, it was written by root
+    let (exclusions_file, include_phased_updates, snapshot, user, remove_old_kernels) =
+        if args.offline {
+            match take_offline_upgrade_plan() {
+                Ok(plan) => (
+                    plan.exclusions_file,
+                    plan.include_phased_updates,
+                    plan.snapshot,
+                    plan.user,
+                    plan.remove_old_kernels,
+                ),
+                Err(e) => exit_with_error(
+                    &socket_client,
+                    AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::BadExclusionsFile),
+                ),
+            }
+        } else {
+            match &args.exclusions_file {
+                Some(path) => (
+                    read_exclusions_file(path, &socket_client),
+                    args.include_phased_updates,
+                    args.snapshot,
+                    user_name(invoking_uid()),
+                    args.remove_old_kernels,
+                ),
+                None => (
+                    ExclusionsFile::default(),
+                    args.include_phased_updates,
+                    args.snapshot,
+                    user_name(invoking_uid()),
+                    args.remove_old_kernels,
+                ),
+            }
+        };
+    // Holds could keep back exactly what fixes the system, a repair ignores them, and a
+    // cleanup upgrades nothing they could apply to
+    let update_holds = if args.repair || args.cleanup {
+        UpdateHolds::default()
+    } else {
+        UpdateHolds::load()
+    };
+    // A repair installs whatever the resolver needs, phased or not
+    PhasingPolicy::new(include_phased_updates || args.repair).apply();
+
+    // APT refuses to install anything until dpkg finished what it started
+    if args.repair && !args.dry_run {
+        configure_unfinished_packages(&socket_client);
+    }
+
+    let apt_cache = match new_cache!() {
+        Ok(t) => t,
+        Err(e) => exit_with_error(
+            &socket_client,
+            AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::Unknown),
+        ),
+    };
+
+    if args.repair {
+        // Like apt-get --fix-broken install, the resolve below finds what fixes them
+        for pkg in apt_cache.packages(&PackageSort::default().installed()) {
+            if pkg.is_now_broken() {
+                pkg.mark_install(true, true);
             }
         }
+    } else if !args.cleanup {
+        if let Err(e) = apt_cache.upgrade(Upgrade::FullUpgrade) {
+            exit_with_error(
+                &socket_client,
+                AptHelperError::from_apt(
+                    e.to_string(),
+                    AptHelperErrorKind::UnresolvableDependencies,
+                ),
+            )
+        }
     }
 
-    let apt_cache = new_cache!().unwrap();
+    // After the upgrade is marked, so the removals end up in the same plan
+    let mut removals: Vec<String> = Vec::new();
+    if args.autoremove {
+        removals.extend(
+            autoremovable_packages(&apt_cache)
+                .into_iter()
+                .map(|t| t.name),
+        );
+    }
+    if let Some(keep) = remove_old_kernels {
+        for kernel in old_kernels(&apt_cache, keep as usize) {
+            removals.extend(kernel.packages);
+        }
+    }
+    for full_name in &removals {
+        if let Some(pkg) = apt_cache.get(full_name) {
+            pkg.mark_delete(false);
+        }
+    }
 
-    apt_cache.upgrade(Upgrade::FullUpgrade).unwrap();
+    // Excluded and held packages stay as they are, whatever depends on them is held back
+    let mut selected: Vec<(String, bool)> = Vec::new();
+    let mut kept: Vec<String> = Vec::new();
+    for change in apt_cache.get_changes(false) {
+        let full_name = format!("{}:{}", change.name(), change.arch());
+        let candidate = change.candidate();
+        let candidate_version = candidate.as_ref().map(|t| t.version().to_owned());
+        if is_excluded(change.name(), change.arch(), &exclusions_file.exclusions)
+            || update_holds.holds_back(change.name(), candidate_version.as_deref(), cmp_versions)
+        {
+            kept.push(full_name);
+        } else {
+            selected.push((full_name, change.marked_delete()));
+        }
+    }
+
+    // Kept-back packages the user asked for, the resolver would leave them alone otherwise.
+    // The file comes from the user and this may run without a password, so it can only
+    // upgrade what is installed, never pull in something new.
+    let forced: Vec<String> = exclusions_file
+        .forced_upgrades
+        .iter()
+        .map(|t| format!("{}:{}", t.package, t.arch))
+        .filter(|full_name| !selected.iter().any(|(t, _)| t == full_name))
+        .collect();
+    for full_name in &forced {
+        if !apt_cache
+            .get(full_name)
+            .is_some_and(|pkg| pkg.is_installed() && pkg.is_upgradable())
+        {
+            exit_with_error(
+                &socket_client,
+                AptHelperError::new(
+                    AptHelperErrorKind::BadExclusionsFile,
+                    format!("{} is not an installed package with an upgrade", full_name),
+                ),
+            )
+        }
+    }
+    selected.extend(forced.iter().map(|full_name| (full_name.to_owned(), false)));
 
-    let apt_upgrade_cache = if excluded_updates_vec.is_empty() {
+    let apt_upgrade_cache = if kept.is_empty() {
         apt_cache
     } else {
-        let apt_upgrade_cache = new_cache!().unwrap();
-        for change in apt_cache.get_changes(false) {
-            if !excluded_updates_vec
-                .iter()
-                .any(|e| change.name().contains(e))
-            {
-                let pkg = apt_upgrade_cache.get(change.name()).unwrap();
-                if change.marked_upgrade() || change.marked_install() || change.marked_downgrade() {
-                    pkg.mark_install(true, false);
-                } else if change.marked_delete() {
-                    pkg.mark_delete(false);
-                }
+        let apt_upgrade_cache = match new_cache!() {
+            Ok(t) => t,
+            Err(e) => exit_with_error(
+                &socket_client,
+                AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::Unknown),
+            ),
+        };
+        for full_name in &kept {
+            if let Some(pkg) = apt_upgrade_cache.get(full_name) {
+                pkg.mark_keep();
                 pkg.protect();
             }
         }
+        for (full_name, marked_delete) in &selected {
+            let pkg = match apt_upgrade_cache.get(full_name) {
+                Some(t) => t,
+                None => continue,
+            };
+            if *marked_delete {
+                pkg.mark_delete(false);
+            } else {
+                pkg.mark_install(true, false);
+            }
+        }
         apt_upgrade_cache
     };
 
-    apt_upgrade_cache.resolve(true).unwrap();
+    for full_name in &forced {
+        if let Some(pkg) = apt_upgrade_cache.get(full_name) {
+            pkg.mark_install(true, true);
+        }
+    }
+
+    if let Err(e) = apt_upgrade_cache.resolve(true) {
+        exit_with_error(
+            &socket_client,
+            AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::UnresolvableDependencies),
+        )
+    }
+
+    let changed: Vec<String> = apt_upgrade_cache
+        .get_changes(false)
+        .map(|change| format!("{}:{}", change.name(), change.arch()))
+        .collect();
+    let held_back: Vec<String> = selected
+        .into_iter()
+        .map(|(full_name, _)| full_name)
+        .filter(|full_name| !changed.contains(full_name))
+        .collect();
+
+    let transaction_plan = transaction_plan(&apt_upgrade_cache, held_back);
+    let no_changes = matches!(
+        &transaction_plan,
+        ProgressMessage::TransactionPlan { changes, .. } if changes.is_empty()
+    );
+    socket_client.borrow_mut().send(&transaction_plan);
+    if args.clean_cache {
+        socket_client.borrow_mut().send(&ProgressMessage::Status {
+            message: strfmt::strfmt(
+                &t!("apt_cleanup_cache_to_clean").to_string(),
+                &std::collections::HashMap::from([(
+                    "SIZE".to_string(),
+                    pretty_bytes::converter::convert(total_size(&cached_archives(Path::new(
+                        APT_ARCHIVES_DIR,
+                    ))) as f64),
+                )]),
+            )
+            .unwrap(),
+        });
+    }
+
+    if (no_changes && !args.clean_cache) || args.dry_run {
+        socket_client
+            .borrow_mut()
+            .send(&ProgressMessage::Finished { exit_code: 0 });
+        return;
+    }
+
+    if !args.assume_yes && !args.offline && !confirm_transaction() {
+        exit_with_error(
+            &socket_client,
+            AptHelperError::new(
+                AptHelperErrorKind::Aborted,
+                t!("apt_helper_cli_aborted").to_string(),
+            ),
+        )
+    }
+
+    // Only the cache to clean, dpkg has nothing to do
+    if no_changes {
+        clean_cache(&socket_client);
+        socket_client
+            .borrow_mut()
+            .send(&ProgressMessage::Finished { exit_code: 0 });
+        return;
+    }
+
+    let planned_changes = match &transaction_plan {
+        ProgressMessage::TransactionPlan { changes, .. } => changes.clone(),
+        _ => Vec::new(),
+    };
+    let installed_packages: Vec<String> = planned_changes
+        .iter()
+        .filter(|t| t.action != ChangeAction::Remove)
+        .map(|t| format!("{}:{}", t.package, t.arch))
+        .collect();
+    // Prefetching and staging change nothing yet, the run that installs gets recorded
+    let history_entry = if !args.download_only && !args.stage_offline {
+        let mut history_entry = HistoryEntry::new(
+            TransactionKind::Apt,
+            user.clone(),
+            planned_changes.iter().map(HistoryPackage::from).collect(),
+        );
+        history_entry.exclusions = exclusions_file.clone();
+        history_entry.offline = args.offline;
+        history_entry.repair = args.repair;
+        let history_entry = Rc::new(RefCell::new(history_entry));
+        socket_client
+            .borrow_mut()
+            .add_observer(HistoryRecorder::new(
+                history_entry.clone(),
+                Path::new(SYSTEM_HISTORY_FILE),
+            ));
+        Some(history_entry)
+    } else {
+        None
+    };
 
     let hit_strfmt_trans_str = t!("apt_update_str_hit").to_string();
     let fetch_strfmt_trans_str = t!("apt_update_str_fetch").to_string();
@@ -74,39 +366,377 @@ fn main() {
     let error_strfmt_trans_str = t!("apt_install_str_error").to_string();
 
     let mut acquire_progress = AcquireProgress::new(AptUpdateProgressSocket::new(
-        percent_socket_path,
-        status_socket_path,
-        speed_socket_path,
+        &socket_client,
         &hit_strfmt_trans_str,
         &fetch_strfmt_trans_str,
         &done_strfmt_trans_str,
         &fail_strfmt_trans_str,
     ));
     let mut install_progress = InstallProgress::new(AptInstallProgressSocket::new(
-        percent_socket_path,
-        status_socket_path,
+        &socket_client,
         error_strfmt_trans_str,
     ));
 
-    apt_upgrade_cache.resolve(true).unwrap();
-
     match apt_upgrade_cache.get_archives(&mut acquire_progress) {
         Ok(_) => {}
-        Err(e) => {
-            panic!("{}", e.to_string())
+        Err(e) => exit_with_error(
+            &socket_client,
+            AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::NetworkFailure),
+        ),
+    };
+
+    if args.stage_offline {
+        let plan = OfflineUpgradePlan::new(
+            exclusions_file,
+            include_phased_updates,
+            snapshot,
+            user,
+            remove_old_kernels,
+        );
+        if let Err(e) = stage_offline_upgrade(&plan) {
+            exit_with_error(
+                &socket_client,
+                AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::Unknown),
+            )
         }
+        socket_client.borrow_mut().send(&ProgressMessage::Status {
+            message: t!("apt_offline_upgrade_staged").to_string(),
+        });
+    }
+
+    if args.download_only || args.stage_offline {
+        socket_client
+            .borrow_mut()
+            .send(&ProgressMessage::Finished { exit_code: 0 });
+        return;
+    }
+
+    // The archives are in place, so the snapshot is as close to the install as it gets
+    if let Some(snapshot_backend) = snapshot {
+        socket_client.borrow_mut().send(&ProgressMessage::Status {
+            message: t!("apt_snapshot_creating").to_string(),
+        });
+        let description = strfmt::strfmt(
+            &t!("apt_snapshot_description").to_string(),
+            &std::collections::HashMap::from([(
+                "NUM".to_string(),
+                planned_changes.len().to_string(),
+            )]),
+        )
+        .unwrap();
+        match snapshot_backend.backend().create(&description) {
+            Ok(id) => {
+                if let Err(e) =
+                    SnapshotRecord::new(id.clone(), snapshot_backend, planned_changes).append()
+                {
+                    eprintln!("Couldn't record snapshot {}: {}", id, e);
+                }
+                if let Some(history_entry) = &history_entry {
+                    history_entry.borrow_mut().snapshot_id = Some(id.clone());
+                }
+                socket_client.borrow_mut().send(&ProgressMessage::Status {
+                    message: strfmt::strfmt(
+                        &t!("apt_snapshot_created").to_string(),
+                        &std::collections::HashMap::from([("ID".to_string(), id)]),
+                    )
+                    .unwrap(),
+                });
+            }
+            Err(e) => exit_with_error(
+                &socket_client,
+                AptHelperError::new(AptHelperErrorKind::SnapshotFailed, e),
+            ),
+        }
+    }
+
+    // rust-apt has no hook for dpkg's conffile question, so dpkg keeps changed files and the
+    // user is asked once it is done
+    let apt_config = Config::new();
+    for option in DPKG_CONFFILE_OPTIONS {
+        apt_config.set("DPkg::Options::", option);
+    }
+    let install_started = chrono::offset::Local::now().timestamp();
+
+    // Offline nobody can answer, the wrapper's noninteractive frontend stays in place
+    let debconf_files = match socket_client.borrow().socket_path() {
+        Some(progress_socket_path) if !args.offline => {
+            start_debconf_passthrough(&apt_config, progress_socket_path.to_owned())
+        }
+        _ => vec![],
     };
 
-    match apt_upgrade_cache.do_install(&mut install_progress) {
+    let install_result = apt_upgrade_cache.do_install(&mut install_progress);
+    for debconf_file in debconf_files {
+        let _ = std::fs::remove_file(debconf_file);
+    }
+    match install_result {
         Ok(_) => {}
+        Err(e) => exit_with_error(
+            &socket_client,
+            AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::Unknown),
+        ),
+    };
+
+    resolve_conffile_conflicts(
+        &socket_client,
+        history_entry.as_deref(),
+        &installed_packages,
+        install_started,
+    );
+    // The archives were needed up to here, the prefetch may have just downloaded them
+    if args.deletes_cached_archives() {
+        clean_cache(&socket_client);
+    }
+
+    socket_client
+        .borrow_mut()
+        .send(&ProgressMessage::Finished { exit_code: 0 });
+}
+
+/// Deletes the downloaded archives and says how much space that freed.
+fn clean_cache(socket_client: &RefCell<ProgressSocketClient>) {
+    match clean_cached_archives(Path::new(APT_ARCHIVES_DIR)) {
+        Ok(freed) => socket_client.borrow_mut().send(&ProgressMessage::Status {
+            message: strfmt::strfmt(
+                &t!("apt_cleanup_cache_cleaned").to_string(),
+                &std::collections::HashMap::from([(
+                    "SIZE".to_string(),
+                    pretty_bytes::converter::convert(freed as f64),
+                )]),
+            )
+            .unwrap(),
+        }),
+        Err(e) => exit_with_error(
+            socket_client,
+            AptHelperError::new(AptHelperErrorKind::Unknown, e.to_string()),
+        ),
+    }
+}
+
+/// Points debconf's passthrough frontend at a socket whose questions are forwarded to the
+/// GUI. Only dpkg gets the frontend, through a wrapper APT runs in its place. Returns the
+/// files to remove afterwards.
+fn start_debconf_passthrough(apt_config: &Config, progress_socket_path: String) -> Vec<PathBuf> {
+    let (debconf_listener, debconf_socket_path) = match bind_debconf_socket() {
+        Ok(t) => t,
         Err(e) => {
-            Runtime::new()
-                .unwrap()
-                .block_on(send_failed_to_socket(percent_socket_path));
-            Runtime::new()
-                .unwrap()
-                .block_on(send_failed_to_socket(status_socket_path));
-            panic!("{}", e.to_string())
+            eprintln!("Debconf questions will get their defaults: {}", e);
+            return vec![];
+        }
+    };
+    let dpkg_path = apt_config.find("Dir::Bin::dpkg", "/usr/bin/dpkg");
+    let dpkg_wrapper_path = match write_dpkg_wrapper(&debconf_socket_path, &dpkg_path) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Debconf questions will get their defaults: {}", e);
+            let _ = std::fs::remove_file(debconf_socket_path);
+            return vec![];
+        }
+    };
+    apt_config.set("Dir::Bin::dpkg", &dpkg_wrapper_path.to_string_lossy());
+    std::thread::spawn(move || serve_debconf(debconf_listener, &progress_socket_path));
+    vec![debconf_socket_path, dpkg_wrapper_path]
+}
+
+/// Asks the GUI about every changed configuration file that got a new version, the ones
+/// nobody answers for are left as `.dpkg-dist` and noted in the history.
+fn resolve_conffile_conflicts(
+    socket_client: &RefCell<ProgressSocketClient>,
+    history_entry: Option<&RefCell<HistoryEntry>>,
+    installed_packages: &[String],
+    since: i64,
+) {
+    let mut socket_client = socket_client.borrow_mut();
+    for conflict in find_conffile_conflicts(installed_packages, Path::new(DPKG_INFO_DIR), since) {
+        socket_client.send(&ProgressMessage::ConflictPrompt {
+            package: conflict.package.to_owned(),
+            path: conflict.path.to_owned(),
+            diff: conflict.diff(),
+        });
+        let answer = loop {
+            match socket_client.wait_for_reply(CONFFILE_ANSWER_TIMEOUT) {
+                Some(ProgressReply::ConflictAnswer { path, answer }) if path == conflict.path => {
+                    break answer
+                }
+                Some(_) => continue,
+                None => break ConflictAnswer::MergeLater,
+            }
+        };
+        let answer = match conflict.apply(answer) {
+            Ok(_) => answer,
+            Err(e) => {
+                eprintln!("Couldn't replace {}: {}", conflict.path, e);
+                ConflictAnswer::MergeLater
+            }
+        };
+        if answer == ConflictAnswer::MergeLater {
+            if let Some(history_entry) = history_entry {
+                history_entry
+                    .borrow_mut()
+                    .unresolved_conffiles
+                    .push(format!("{}: {}", conflict.package, conflict.path));
+            }
         }
+        let status = match answer {
+            ConflictAnswer::Keep => t!("apt_conffile_kept"),
+            ConflictAnswer::Replace => t!("apt_conffile_replaced"),
+            ConflictAnswer::MergeLater => t!("apt_conffile_merge_later"),
+        };
+        socket_client.send(&ProgressMessage::Status {
+            message: strfmt::strfmt(
+                &status.to_string(),
+                &std::collections::HashMap::from([("PATH".to_string(), conflict.path)]),
+            )
+            .unwrap(),
+        });
+    }
+}
+
+/// Runs `dpkg --configure -a`, forwarding its output as status messages.
+fn configure_unfinished_packages(socket_client: &RefCell<ProgressSocketClient>) {
+    socket_client.borrow_mut().send(&ProgressMessage::Status {
+        message: t!("apt_repair_configuring").to_string(),
+    });
+    let dpkg_error = |message: String| -> ! {
+        exit_with_error(
+            socket_client,
+            AptHelperError::new(AptHelperErrorKind::DpkgInterrupted, message),
+        )
+    };
+    let (pipe_reader, pipe_writer) = match os_pipe::pipe() {
+        Ok(t) => t,
+        Err(e) => dpkg_error(e.to_string()),
+    };
+    let mut dpkg_args = vec!["--configure", "-a"];
+    dpkg_args.extend(DPKG_CONFFILE_OPTIONS);
+    let child = match duct::cmd("dpkg", dpkg_args)
+        .stderr_to_stdout()
+        .stdout_file(pipe_writer)
+        .unchecked()
+        .start()
+    {
+        Ok(t) => t,
+        Err(e) => dpkg_error(e.to_string()),
+    };
+    let mut last_line = String::new();
+    for line in BufReader::new(pipe_reader).lines().map_while(Result::ok) {
+        socket_client.borrow_mut().send(&ProgressMessage::Status {
+            message: line.clone(),
+        });
+        last_line = line;
+    }
+    match child.wait() {
+        Ok(output) if output.status.success() => {}
+        Ok(_) => dpkg_error(last_line),
+        Err(e) => dpkg_error(e.to_string()),
+    }
+}
+
+/// Reads the packages to leave out, the file must belong to whoever started us.
+fn read_exclusions_file(
+    path: &Path,
+    socket_client: &RefCell<ProgressSocketClient>,
+) -> ExclusionsFile {
+    let bad_exclusions_file = |message: String| -> ! {
+        exit_with_error(
+            socket_client,
+            AptHelperError::new(
+                AptHelperErrorKind::BadExclusionsFile,
+                format!("{}: {}", path.display(), message),
+            ),
+        )
+    };
+
+    // Never follow a symlink, it could point at a file the user may not read
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.is_file() => bad_exclusions_file("not a regular file".to_owned()),
+        Ok(metadata) if metadata.uid() == invoking_uid() || metadata.uid() == 0 => {}
+        Ok(_) => bad_exclusions_file("not owned by the invoking user".to_owned()),
+        Err(e) => bad_exclusions_file(e.to_string()),
+    }
+    // And not one swapped in after the check either
+    let mut file = match std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+    {
+        Ok(t) => t,
+        Err(e) => bad_exclusions_file(e.to_string()),
+    };
+    let mut data = String::new();
+    if let Err(e) = file.read_to_string(&mut data) {
+        bad_exclusions_file(e.to_string())
+    }
+    let exclusions_file = match serde_json::from_str::<ExclusionsFile>(&data) {
+        Ok(t) => t,
+        Err(e) => bad_exclusions_file(e.to_string()),
     };
+    for exclusion in &exclusions_file.exclusions {
+        if let Err(e) = exclusion.validate() {
+            bad_exclusions_file(format!("{}: {}", exclusion.package, e))
+        }
+    }
+    exclusions_file
+}
+
+fn transaction_plan(apt_upgrade_cache: &Cache, held_back: Vec<String>) -> ProgressMessage {
+    let mut changes: Vec<PlannedChange> = Vec::new();
+    let mut download_size: u64 = 0;
+    let mut installed_size_change: i64 = 0;
+
+    for change in apt_upgrade_cache.get_changes(false) {
+        let installed = change.installed();
+        let candidate = change.candidate();
+        let action = if change.marked_delete() {
+            ChangeAction::Remove
+        } else if change.marked_downgrade() {
+            ChangeAction::Downgrade
+        } else if change.marked_upgrade() && change.is_installed() {
+            ChangeAction::Upgrade
+        } else if change.marked_install() || change.marked_upgrade() {
+            ChangeAction::Install
+        } else {
+            continue;
+        };
+        if let Some(installed) = &installed {
+            installed_size_change -= installed.installed_size() as i64;
+        }
+        let mut change_download_size = 0;
+        if action != ChangeAction::Remove {
+            if let Some(candidate) = &candidate {
+                change_download_size = candidate.size();
+                installed_size_change += candidate.installed_size() as i64;
+            }
+        }
+        download_size += change_download_size;
+        changes.push(PlannedChange {
+            package: change.name().to_owned(),
+            arch: change.arch().to_owned(),
+            action,
+            current_version: installed.map(|v| v.version().to_owned()),
+            new_version: match action {
+                ChangeAction::Remove => None,
+                _ => candidate.map(|v| v.version().to_owned()),
+            },
+            download_size: change_download_size,
+        });
+    }
+
+    ProgressMessage::TransactionPlan {
+        changes,
+        download_size,
+        installed_size_change,
+        held_back,
+    }
+}
+
+/// Asks on the terminal before touching the system, like apt-get does without `-y`.
+fn confirm_transaction() -> bool {
+    eprint!("{} [y/N] ", t!("apt_helper_cli_confirm"));
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    match std::io::stdin().read_line(&mut answer) {
+        Ok(_) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+        Err(_) => false,
+    }
 }
@@ -1,9 +1,15 @@
+use pika_unixsocket_tools::apt_helper_args::{usage, AptHelperArgs, EXIT_CODE_USAGE};
+use pika_unixsocket_tools::apt_helper_error::{
+    exit_with_error, AptHelperError, AptHelperErrorKind,
+};
 use pika_unixsocket_tools::apt_update_progress_socket::AptUpdateProgressSocket;
-use pika_unixsocket_tools::pika_unixsocket_tools::*;
+use pika_unixsocket_tools::pika_unixsocket_tools::ProgressSocketClient;
+use pika_unixsocket_tools::progress_protocol::ProgressMessage;
+use pika_unixsocket_tools::session_socket::{invoking_uid, validate_session_socket_dir};
 use rust_apt::new_cache;
 use rust_apt::progress::AcquireProgress;
-use std::env;
-use tokio::runtime::Runtime;
+use std::cell::RefCell;
+use std::process::exit;
 
 // Init translations for current crate.
 #[macro_use]
@@ -11,8 +17,33 @@ extern crate rust_i18n;
 i18n!("locales", fallback = "en_US");
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    rust_i18n::set_locale(&args[1]);
+    let args = match AptHelperArgs::from_env() {
+        Ok(t)
+            if t.download_only
+                || t.stage_offline
+                || t.offline
+                || t.repair
+                || t.cleanup
+                || t.cleans_up()
+                || t.exclusions_file.is_some() =>
+        {
+            eprintln!(
+                "--download-only, --stage-offline, --offline, --repair, the cleanup options and --exclusions-file only apply to apt_full_upgrade\n\n{}",
+                usage("apt_update")
+            );
+            exit(EXIT_CODE_USAGE)
+        }
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, usage("apt_update"));
+            exit(EXIT_CODE_USAGE)
+        }
+    };
+    if args.help {
+        println!("{}", usage("apt_update"));
+        return;
+    }
+    rust_i18n::set_locale(&args.locale());
     let instance = single_instance::SingleInstance::new(
         "com.github.pikaos-linux.pikmanupdatemanager.update.manager",
     )
@@ -23,30 +54,54 @@ fn main() {
     let done_strfmt_trans_str = t!("apt_update_str_done").to_string();
     let fail_strfmt_trans_str = t!("apt_update_str_fail").to_string();
 
-    let update_cache = new_cache!().unwrap();
-    let percent_socket_path = "/tmp/pika_apt_update_percent.sock";
-    let status_socket_path = "/tmp/pika_apt_update_status.sock";
-    let speed_socket_path = "/tmp/pika_apt_update_speed.sock";
+    // The GUI hands us its private session directory, anything else is not trusted
+    let socket_client = RefCell::new(
+        match &args.socket_dir {
+            Some(socket_dir) => match validate_session_socket_dir(socket_dir, invoking_uid()) {
+                Ok(socket_path) => ProgressSocketClient::new(&socket_path.to_string_lossy()),
+                Err(e) => {
+                    eprintln!("Not reporting progress: {}", e);
+                    ProgressSocketClient::disconnected()
+                }
+            },
+            None => ProgressSocketClient::disconnected(),
+        }
+        .with_fallback_log("/var/log/pikman-update-manager/apt_update.log")
+        .with_console_output(args.console_output()),
+    );
+
+    // Refreshing the package lists has nothing to preview
+    if args.dry_run {
+        socket_client
+            .borrow_mut()
+            .send(&ProgressMessage::Finished { exit_code: 0 });
+        return;
+    }
+
+    let update_cache = match new_cache!() {
+        Ok(t) => t,
+        Err(e) => exit_with_error(
+            &socket_client,
+            AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::Unknown),
+        ),
+    };
     match update_cache.update(&mut AcquireProgress::new(AptUpdateProgressSocket::new(
-        percent_socket_path,
-        status_socket_path,
-        speed_socket_path,
+        &socket_client,
         &hit_strfmt_trans_str,
         &fetch_strfmt_trans_str,
         &done_strfmt_trans_str,
         &fail_strfmt_trans_str,
     ))) {
         Ok(_) => {}
-        Err(e) => {
-            Runtime::new()
-                .unwrap()
-                .block_on(send_failed_to_socket(percent_socket_path));
-            Runtime::new()
-                .unwrap()
-                .block_on(send_failed_to_socket(status_socket_path));
-            panic!("{}", e.to_string())
-        }
+        Err(e) => exit_with_error(
+            &socket_client,
+            AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::NetworkFailure),
+        ),
     };
 
+    socket_client
+        .borrow_mut()
+        .send(&ProgressMessage::Finished { exit_code: 0 });
+
     assert!(!instance.is_single());
 }
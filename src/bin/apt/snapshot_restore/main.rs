@@ -0,0 +1,34 @@
+use pika_unixsocket_tools::apt_helper_args::EXIT_CODE_USAGE;
+use pika_unixsocket_tools::snapshots::load_snapshot_records;
+use std::env;
+use std::process::exit;
+
+/// Rolls the system back to a snapshot taken before an upgrade. Only snapshots we
+/// recorded ourselves are accepted, the backend comes from that record.
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let id = match args.as_slice() {
+        [id] if !id.starts_with('-') => id,
+        _ => {
+            eprintln!("Usage: snapshot_restore <SNAPSHOT_ID>");
+            exit(EXIT_CODE_USAGE)
+        }
+    };
+
+    let snapshot_record = match load_snapshot_records().into_iter().find(|t| t.id == *id) {
+        Some(t) => t,
+        None => {
+            eprintln!("{} is not a snapshot taken before an upgrade", id);
+            exit(EXIT_CODE_USAGE)
+        }
+    };
+
+    if let Err(e) = snapshot_record
+        .backend
+        .backend()
+        .restore(&snapshot_record.id)
+    {
+        eprintln!("{}", e);
+        exit(1)
+    }
+}
@@ -14,6 +14,8 @@ use std::sync::Mutex;
 mod add_dialog;
 mod deb822_edit_dialog;
 mod legacy_edit_dialog;
+mod snapshots_box;
+mod update_holds_box;
 
 enum AptSourceConfig {
     Legacy(apt_legacy_tools::LegacyAptSource),
@@ -649,6 +651,69 @@ pub fn apt_manage_page(
 
     retry_interval_spinrow.add_css_class("disable-outline");
 
+    let phased_updates_box = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .halign(Align::Start)
+        .valign(Align::Center)
+        .margin_start(10)
+        .margin_end(10)
+        .margin_bottom(10)
+        .build();
+
+    let phased_updates_label = gtk::Label::builder()
+        .label(t!("phased_updates_label_label"))
+        .tooltip_text(t!("phased_updates_label_tooltip_text"))
+        .margin_start(2)
+        .margin_end(5)
+        .halign(Align::Start)
+        .valign(Align::Center)
+        .build();
+
+    let phased_updates_switch = gtk::Switch::builder()
+        .margin_end(5)
+        .halign(Align::Start)
+        .valign(Align::Center)
+        .build();
+    glib_settings
+        .bind("include-phased-updates", &phased_updates_switch, "active")
+        .build();
+
+    // The update list decides what is selected by default, so it has to be rebuilt
+    phased_updates_switch.connect_active_notify(clone!(
+        #[strong]
+        apt_retry_signal_action,
+        move |_| {
+            apt_retry_signal_action.activate(None);
+        }
+    ));
+
+    let prefetch_updates_box = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .halign(Align::Start)
+        .valign(Align::Center)
+        .margin_start(10)
+        .margin_end(10)
+        .margin_bottom(10)
+        .build();
+
+    let prefetch_updates_label = gtk::Label::builder()
+        .label(t!("prefetch_updates_label_label"))
+        .tooltip_text(t!("prefetch_updates_label_tooltip_text"))
+        .margin_start(2)
+        .margin_end(5)
+        .halign(Align::Start)
+        .valign(Align::Center)
+        .build();
+
+    let prefetch_updates_switch = gtk::Switch::builder()
+        .margin_end(5)
+        .halign(Align::Start)
+        .valign(Align::Center)
+        .build();
+    glib_settings
+        .bind("prefetch-updates", &prefetch_updates_switch, "active")
+        .build();
+
     unofficial_sources_edit_box.append(&unofficial_source_add_button);
     unofficial_sources_edit_box.append(&unofficial_source_edit_button);
     unofficial_sources_edit_box.append(&unofficial_source_remove_button);
@@ -662,6 +727,12 @@ pub fn apt_manage_page(
     retry_interval_box.append(&retry_interval_switch);
     retry_interval_box.append(&retry_interval_spinrow);
 
+    phased_updates_box.append(&phased_updates_label);
+    phased_updates_box.append(&phased_updates_switch);
+
+    prefetch_updates_box.append(&prefetch_updates_label);
+    prefetch_updates_box.append(&prefetch_updates_switch);
+
     //
 
     main_box.append(&system_mirror_label0);
@@ -674,7 +745,15 @@ pub fn apt_manage_page(
     main_box.append(&unofficial_sources_label0);
     main_box.append(&unofficial_sources_label1);
     main_box.append(&unofficial_sources_viewport);
+    main_box.append(&update_holds_box::update_holds_box(
+        &window,
+        glib_settings,
+        apt_retry_signal_action,
+    ));
+    main_box.append(&snapshots_box::snapshots_box(&window, glib_settings));
     main_box.append(&retry_interval_box);
+    main_box.append(&phased_updates_box);
+    main_box.append(&prefetch_updates_box);
 
     main_box
 }
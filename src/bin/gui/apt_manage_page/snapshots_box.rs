@@ -0,0 +1,273 @@
+use adw::prelude::*;
+use gtk::glib::{clone, MainContext};
+use gtk::*;
+use pika_unixsocket_tools::snapshots::{
+    load_snapshot_records, SnapshotBackendKind, SnapshotRecord,
+};
+use std::thread;
+
+/// Picks the snapshot tool and lists the snapshots taken before upgrades, each one can be
+/// restored.
+pub fn snapshots_box(window: &adw::ApplicationWindow, glib_settings: &gio::Settings) -> gtk::Box {
+    let snapshots_main_box = Box::builder().orientation(Orientation::Vertical).build();
+
+    let snapshots_label0 = gtk::Label::builder()
+        .label(t!("snapshots_label"))
+        .halign(gtk::Align::Start)
+        .valign(gtk::Align::Start)
+        .hexpand(true)
+        .margin_top(15)
+        .margin_start(15)
+        .margin_end(15)
+        .margin_bottom(5)
+        .build();
+    snapshots_label0.add_css_class("heading");
+
+    let snapshots_label1 = gtk::Label::builder()
+        .label(t!("snapshots_label1_label"))
+        .halign(gtk::Align::Start)
+        .valign(gtk::Align::Start)
+        .hexpand(true)
+        .wrap(true)
+        .margin_start(15)
+        .margin_end(15)
+        .build();
+
+    let snapshots_backend_box = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .halign(Align::Start)
+        .valign(Align::Center)
+        .margin_start(15)
+        .margin_end(15)
+        .margin_top(5)
+        .build();
+
+    let snapshots_backend_label = gtk::Label::builder()
+        .label(t!("snapshots_backend_label"))
+        .margin_end(5)
+        .halign(Align::Start)
+        .valign(Align::Center)
+        .build();
+
+    let backend_values: Vec<&str> = std::iter::once("none")
+        .chain(SnapshotBackendKind::ALL.iter().map(|t| t.as_str()))
+        .collect();
+    let backend_labels: Vec<String> = backend_values
+        .iter()
+        .map(|t| snapshot_backend_label(t))
+        .collect();
+    let snapshots_backend_dropdown = gtk::DropDown::from_strings(
+        &backend_labels
+            .iter()
+            .map(|t| t.as_str())
+            .collect::<Vec<&str>>(),
+    );
+    snapshots_backend_dropdown.set_valign(Align::Center);
+    snapshots_backend_dropdown.set_selected(
+        backend_values
+            .iter()
+            .position(|t| *t == glib_settings.string("pre-upgrade-snapshot"))
+            .unwrap_or(0) as u32,
+    );
+    snapshots_backend_dropdown.connect_selected_notify(clone!(
+        #[strong]
+        glib_settings,
+        move |dropdown| {
+            if let Some(value) = backend_values.get(dropdown.selected() as usize) {
+                let _ = glib_settings.set_string("pre-upgrade-snapshot", value);
+            }
+        }
+    ));
+
+    let snapshots_boxedlist = ListBox::builder()
+        .selection_mode(SelectionMode::None)
+        .build();
+    snapshots_boxedlist.add_css_class("boxed-list");
+
+    let snapshots_viewport = ScrolledWindow::builder()
+        .hexpand(true)
+        .has_frame(true)
+        .margin_top(15)
+        .margin_start(15)
+        .margin_end(15)
+        .margin_bottom(5)
+        .child(&snapshots_boxedlist)
+        .overflow(Overflow::Hidden)
+        .height_request(160)
+        .build();
+    snapshots_viewport.add_css_class("round-all-scroll-no-padding");
+
+    let snapshot_records = load_snapshot_records();
+    if snapshot_records.is_empty() {
+        snapshots_boxedlist.append(
+            &adw::ActionRow::builder()
+                .title(t!("snapshots_empty_label"))
+                .build(),
+        );
+    }
+    for snapshot_record in snapshot_records {
+        let snapshot_row = adw::ActionRow::builder()
+            .title(format!(
+                "{} ({})",
+                snapshot_record.id,
+                snapshot_backend_label(snapshot_record.backend.as_str())
+            ))
+            .subtitle(snapshot_row_subtitle(&snapshot_record))
+            .use_markup(false)
+            .build();
+        let snapshot_restore_button = Button::builder()
+            .icon_name("edit-undo-symbolic")
+            .tooltip_text(t!("snapshots_restore_button_tooltip_text"))
+            .valign(Align::Center)
+            .build();
+        snapshot_restore_button.add_css_class("flat");
+        snapshot_restore_button.connect_clicked(clone!(
+            #[strong]
+            window,
+            move |_| {
+                restore_snapshot(&window, &snapshot_record);
+            }
+        ));
+        snapshot_row.add_suffix(&snapshot_restore_button);
+        snapshots_boxedlist.append(&snapshot_row);
+    }
+
+    snapshots_backend_box.append(&snapshots_backend_label);
+    snapshots_backend_box.append(&snapshots_backend_dropdown);
+
+    snapshots_main_box.append(&snapshots_label0);
+    snapshots_main_box.append(&snapshots_label1);
+    snapshots_main_box.append(&snapshots_backend_box);
+    snapshots_main_box.append(&snapshots_viewport);
+
+    snapshots_main_box
+}
+
+fn snapshot_backend_label(backend: &str) -> String {
+    match SnapshotBackendKind::parse(backend) {
+        Some(SnapshotBackendKind::Btrfs) => t!("snapshots_backend_btrfs"),
+        Some(SnapshotBackendKind::Snapper) => t!("snapshots_backend_snapper"),
+        Some(SnapshotBackendKind::Timeshift) => t!("snapshots_backend_timeshift"),
+        None => t!("snapshots_backend_none"),
+    }
+    .to_string()
+}
+
+/// When it was taken and what the upgrade changed, like `2024-06-01 12:00, 1 packages: foo 1 → 2`.
+fn snapshot_row_subtitle(snapshot_record: &SnapshotRecord) -> String {
+    let created_at = chrono::DateTime::parse_from_rfc3339(&snapshot_record.created_at)
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|_| snapshot_record.created_at.to_owned());
+    let changes = snapshot_record
+        .changes
+        .iter()
+        .map(|t| {
+            format!(
+                "{} {} → {}",
+                t.package,
+                t.current_version.as_deref().unwrap_or("-"),
+                t.new_version.as_deref().unwrap_or("-")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    strfmt::strfmt(
+        &t!("snapshots_row_subtitle").to_string(),
+        &std::collections::HashMap::from([
+            ("DATE".to_string(), created_at),
+            ("NUM".to_string(), snapshot_record.changes.len().to_string()),
+            ("PACKAGES".to_string(), changes),
+        ]),
+    )
+    .unwrap()
+}
+
+fn restore_snapshot(window: &adw::ApplicationWindow, snapshot_record: &SnapshotRecord) {
+    let snapshot_restore_dialog = adw::MessageDialog::builder()
+        .heading(t!("snapshots_restore_dialog_heading"))
+        .body(
+            strfmt::strfmt(
+                &t!("snapshots_restore_dialog_body").to_string(),
+                &std::collections::HashMap::from([(
+                    "ID".to_string(),
+                    snapshot_record.id.to_owned(),
+                )]),
+            )
+            .unwrap(),
+        )
+        .transient_for(window)
+        .build();
+    snapshot_restore_dialog.add_response(
+        "snapshots_restore_dialog_cancel",
+        &t!("snapshots_restore_dialog_cancel_label").to_string(),
+    );
+    snapshot_restore_dialog.add_response(
+        "snapshots_restore_dialog_confirm",
+        &t!("snapshots_restore_dialog_confirm_label").to_string(),
+    );
+    snapshot_restore_dialog.set_response_appearance(
+        "snapshots_restore_dialog_confirm",
+        adw::ResponseAppearance::Destructive,
+    );
+    snapshot_restore_dialog.set_close_response("snapshots_restore_dialog_cancel");
+
+    let id = snapshot_record.id.to_owned();
+    snapshot_restore_dialog.choose(
+        None::<&gio::Cancellable>,
+        clone!(
+            #[strong]
+            window,
+            move |choice| {
+                if choice != "snapshots_restore_dialog_confirm" {
+                    return;
+                }
+                // The password prompt and the rollback itself can take minutes
+                let (restore_result_sender, restore_result_receiver) = async_channel::bounded(1);
+                let id = id.to_owned();
+                thread::spawn(move || {
+                    let result = match duct::cmd!(
+                        "pkexec",
+                        "/usr/lib/pika/pikman-update-manager/scripts/snapshot_restore",
+                        &id
+                    )
+                    .stderr_to_stdout()
+                    .stdout_capture()
+                    .unchecked()
+                    .run()
+                    {
+                        Ok(output) if output.status.success() => Ok(()),
+                        Ok(output) => Err(String::from_utf8_lossy(&output.stdout).to_string()),
+                        Err(e) => Err(e.to_string()),
+                    };
+                    let _ = restore_result_sender.send_blocking(result);
+                });
+                let restore_result_context = MainContext::default();
+                // The main loop executes the asynchronous block
+                restore_result_context.spawn_local(clone!(
+                    #[strong]
+                    window,
+                    async move {
+                        let (heading, body) = match restore_result_receiver.recv().await {
+                            Ok(Ok(_)) => (
+                                t!("snapshots_restore_done_heading").to_string(),
+                                t!("snapshots_restore_done_body").to_string(),
+                            ),
+                            Ok(Err(e)) => (t!("snapshots_restore_failed_heading").to_string(), e),
+                            Err(_) => return,
+                        };
+                        let snapshot_restore_result_dialog = adw::MessageDialog::builder()
+                            .heading(heading)
+                            .body(body)
+                            .transient_for(&window)
+                            .build();
+                        snapshot_restore_result_dialog.add_response(
+                            "snapshots_restore_result_dialog_ok",
+                            &t!("snapshots_restore_result_dialog_ok_label").to_string(),
+                        );
+                        snapshot_restore_result_dialog.present();
+                    }
+                ));
+            }
+        ),
+    );
+}
@@ -0,0 +1,303 @@
+use adw::prelude::*;
+use gtk::glib::{clone, MainContext};
+use gtk::*;
+use pika_unixsocket_tools::update_holds::{
+    Exclusion, UpdateHold, UpdateHolds, UPDATE_HOLDS_SCRIPT_PATH,
+};
+use std::thread;
+
+pub fn update_holds_box(
+    window: &adw::ApplicationWindow,
+    glib_settings: &gio::Settings,
+    apt_retry_signal_action: &gio::SimpleAction,
+) -> gtk::Box {
+    let update_holds_main_box = Box::builder().orientation(Orientation::Vertical).build();
+
+    let update_holds_label0 = gtk::Label::builder()
+        .label(t!("update_holds_label"))
+        .halign(gtk::Align::Start)
+        .valign(gtk::Align::Start)
+        .hexpand(true)
+        .margin_top(15)
+        .margin_start(15)
+        .margin_end(15)
+        .margin_bottom(5)
+        .build();
+    update_holds_label0.add_css_class("heading");
+
+    let update_holds_label1 = gtk::Label::builder()
+        .label(t!("update_holds_label1_label"))
+        .halign(gtk::Align::Start)
+        .valign(gtk::Align::Start)
+        .hexpand(true)
+        .margin_start(15)
+        .margin_end(15)
+        .build();
+
+    let update_holds_boxedlist = ListBox::builder()
+        .selection_mode(SelectionMode::None)
+        .build();
+    update_holds_boxedlist.add_css_class("boxed-list");
+
+    let update_holds_viewport = ScrolledWindow::builder()
+        .hexpand(true)
+        .has_frame(true)
+        .margin_top(15)
+        .margin_start(15)
+        .margin_end(15)
+        .child(&update_holds_boxedlist)
+        .overflow(Overflow::Hidden)
+        .height_request(160)
+        .build();
+    update_holds_viewport.add_css_class("round-all-scroll-no-padding");
+
+    let update_holds_edit_box = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .margin_start(15)
+        .margin_end(15)
+        .margin_bottom(5)
+        .build();
+    update_holds_edit_box.add_css_class("linked");
+
+    let update_holds_package_entry = gtk::Entry::builder()
+        .placeholder_text(t!("update_holds_package_entry_placeholder"))
+        .hexpand(true)
+        .build();
+
+    let update_holds_version_entry = gtk::Entry::builder()
+        .placeholder_text(t!("update_holds_version_entry_placeholder"))
+        .hexpand(true)
+        .build();
+
+    let update_holds_add_button = Button::builder()
+        .icon_name("list-add-symbolic")
+        .tooltip_text(t!("update_holds_add_button_tooltip_text"))
+        .sensitive(false)
+        .build();
+
+    let update_holds_mirror_box = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .halign(Align::Start)
+        .valign(Align::Center)
+        .margin_start(15)
+        .margin_end(15)
+        .margin_top(5)
+        .build();
+
+    let update_holds_mirror_label = gtk::Label::builder()
+        .label(t!("update_holds_mirror_label"))
+        .margin_end(5)
+        .halign(Align::Start)
+        .valign(Align::Center)
+        .build();
+
+    let update_holds_mirror_switch = gtk::Switch::builder()
+        .halign(Align::Start)
+        .valign(Align::Center)
+        .build();
+    glib_settings
+        .bind(
+            "mirror-holds-to-apt-mark",
+            &update_holds_mirror_switch,
+            "active",
+        )
+        .build();
+
+    let reload_action = gio::SimpleAction::new("apt_holds_reload", None);
+
+    reload_action.connect_activate(clone!(
+        #[strong]
+        window,
+        #[strong]
+        glib_settings,
+        #[strong]
+        apt_retry_signal_action,
+        #[weak]
+        update_holds_boxedlist,
+        move |reload_action, _| {
+            update_holds_boxedlist.remove_all();
+            let update_holds = UpdateHolds::load();
+            if update_holds.holds.is_empty() {
+                update_holds_boxedlist.append(
+                    &adw::ActionRow::builder()
+                        .title(t!("update_holds_empty_label"))
+                        .build(),
+                );
+            }
+            for update_hold in update_holds.holds {
+                let update_hold_row = adw::ActionRow::builder()
+                    .title(&update_hold.package)
+                    .subtitle(match &update_hold.until_version {
+                        Some(t) => strfmt::strfmt(
+                            &t!("update_holds_row_subtitle_until").to_string(),
+                            &std::collections::HashMap::from([(
+                                "VERSION".to_string(),
+                                t.to_owned(),
+                            )]),
+                        )
+                        .unwrap(),
+                        None => t!("update_holds_row_subtitle_forever").to_string(),
+                    })
+                    .build();
+                let update_hold_remove_button = Button::builder()
+                    .icon_name("edit-delete-symbolic")
+                    .tooltip_text(t!("update_holds_remove_button_tooltip_text"))
+                    .valign(Align::Center)
+                    .build();
+                update_hold_remove_button.add_css_class("flat");
+                update_hold_remove_button.connect_clicked(clone!(
+                    #[strong]
+                    window,
+                    #[strong]
+                    glib_settings,
+                    #[strong]
+                    reload_action,
+                    #[strong]
+                    apt_retry_signal_action,
+                    move |_| {
+                        let mut update_holds = UpdateHolds::load();
+                        update_holds.remove(&update_hold.package);
+                        save_update_holds(
+                            &window,
+                            &glib_settings,
+                            &reload_action,
+                            &apt_retry_signal_action,
+                            &update_holds,
+                            "unhold",
+                            &update_hold,
+                        );
+                    }
+                ));
+                update_hold_row.add_suffix(&update_hold_remove_button);
+                update_holds_boxedlist.append(&update_hold_row);
+            }
+        }
+    ));
+
+    update_holds_package_entry.connect_changed(clone!(
+        #[weak]
+        update_holds_add_button,
+        move |entry| {
+            update_holds_add_button.set_sensitive(!entry.text().trim().is_empty());
+        }
+    ));
+
+    update_holds_add_button.connect_clicked(clone!(
+        #[strong]
+        window,
+        #[strong]
+        glib_settings,
+        #[strong]
+        reload_action,
+        #[strong]
+        apt_retry_signal_action,
+        #[weak]
+        update_holds_package_entry,
+        #[weak]
+        update_holds_version_entry,
+        move |_| {
+            let package = update_holds_package_entry.text().trim().to_string();
+            if let Err(e) = Exclusion::parse(&package).validate() {
+                update_holds_error_dialog(&window, &format!("{}: {}", package, e));
+                return;
+            }
+            let update_hold = UpdateHold {
+                package,
+                until_version: match update_holds_version_entry.text().trim() {
+                    "" => None,
+                    t => Some(t.to_string()),
+                },
+            };
+            let mut update_holds = UpdateHolds::load();
+            update_holds.add(update_hold.clone());
+            save_update_holds(
+                &window,
+                &glib_settings,
+                &reload_action,
+                &apt_retry_signal_action,
+                &update_holds,
+                "hold",
+                &update_hold,
+            );
+            update_holds_package_entry.set_text("");
+            update_holds_version_entry.set_text("");
+        }
+    ));
+
+    reload_action.activate(None);
+
+    update_holds_edit_box.append(&update_holds_package_entry);
+    update_holds_edit_box.append(&update_holds_version_entry);
+    update_holds_edit_box.append(&update_holds_add_button);
+
+    update_holds_mirror_box.append(&update_holds_mirror_label);
+    update_holds_mirror_box.append(&update_holds_mirror_switch);
+
+    update_holds_main_box.append(&update_holds_label0);
+    update_holds_main_box.append(&update_holds_label1);
+    update_holds_main_box.append(&update_holds_viewport);
+    update_holds_main_box.append(&update_holds_edit_box);
+    update_holds_main_box.append(&update_holds_mirror_box);
+
+    update_holds_main_box
+}
+
+/// Writes the hold list and, if enabled, mirrors plain holds to `apt-mark`, then reloads
+/// the list. Runs in the background, it waits for the password prompt.
+fn save_update_holds(
+    window: &adw::ApplicationWindow,
+    glib_settings: &gio::Settings,
+    reload_action: &gio::SimpleAction,
+    apt_retry_signal_action: &gio::SimpleAction,
+    update_holds: &UpdateHolds,
+    apt_mark_command: &str,
+    update_hold: &UpdateHold,
+) {
+    let mut script_args = vec![UPDATE_HOLDS_SCRIPT_PATH.to_owned()];
+    // apt-mark has no notion of "until version X", so only plain holds are mirrored
+    if glib_settings.boolean("mirror-holds-to-apt-mark") && update_hold.until_version.is_none() {
+        script_args.push(apt_mark_command.to_owned());
+        script_args.push(update_hold.package.to_owned());
+    }
+    let update_holds_json = update_holds.to_json();
+    let (save_result_sender, save_result_receiver) = async_channel::bounded(1);
+    thread::spawn(move || {
+        let result = duct::cmd("pkexec", &script_args)
+            .stdin_bytes(update_holds_json)
+            .run()
+            .map(|_| ())
+            .map_err(|e| e.to_string());
+        let _ = save_result_sender.send_blocking(result);
+    });
+
+    let save_result_context = MainContext::default();
+    // The main loop executes the asynchronous block
+    save_result_context.spawn_local(clone!(
+        #[strong]
+        window,
+        #[strong]
+        reload_action,
+        #[strong]
+        apt_retry_signal_action,
+        async move {
+            if let Ok(Err(e)) = save_result_receiver.recv().await {
+                update_holds_error_dialog(&window, &e);
+            }
+            reload_action.activate(None);
+            apt_retry_signal_action.activate(None);
+        }
+    ));
+}
+
+fn update_holds_error_dialog(window: &adw::ApplicationWindow, body: &str) {
+    let update_holds_error_dialog = adw::MessageDialog::builder()
+        .heading(t!("update_holds_error_dialog_heading"))
+        .body(body)
+        .transient_for(window)
+        .build();
+    update_holds_error_dialog.add_response(
+        "update_holds_error_dialog_ok",
+        &t!("update_holds_error_dialog_ok_label").to_string(),
+    );
+    update_holds_error_dialog.present();
+}
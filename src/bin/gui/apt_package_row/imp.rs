@@ -4,6 +4,9 @@ use adw::*;
 use adw::{prelude::*, subclass::prelude::*};
 use glib::{clone, subclass::Signal, Properties};
 use gtk::*;
+use pika_unixsocket_tools::apt_changelog::{
+    entries_newer_than, find_references, parse_changelog, ChangelogFetcher,
+};
 use pretty_bytes::converter::convert;
 use std::env;
 
@@ -17,6 +20,10 @@ pub struct AptPackageRow {
     #[property(get, set)]
     package_arch: RefCell<String>,
     #[property(get, set)]
+    package_source_name: RefCell<String>,
+    #[property(get, set)]
+    package_new_install: RefCell<bool>,
+    #[property(get, set)]
     package_installed_version: RefCell<String>,
     #[property(get, set)]
     package_candidate_version: RefCell<String>,
@@ -25,12 +32,22 @@ pub struct AptPackageRow {
     #[property(get, set)]
     package_source_uri: RefCell<String>,
     #[property(get, set)]
+    package_origin: RefCell<String>,
+    #[property(get, set)]
+    package_archive: RefCell<String>,
+    #[property(get, set)]
+    package_update_class: RefCell<String>,
+    #[property(get, set)]
     package_maintainer: RefCell<String>,
     #[property(get, set)]
     package_size: RefCell<u64>,
     #[property(get, set)]
     package_installed_size: RefCell<u64>,
     #[property(get, set)]
+    package_installed_size_delta: RefCell<i64>,
+    #[property(get, set)]
+    package_phased_update_percentage: RefCell<u32>,
+    #[property(get, set)]
     package_marked: RefCell<bool>,
     #[property(get, set)]
     theme_changed_action: RefCell<Option<gio::SimpleAction>>,
@@ -120,6 +137,10 @@ impl ObjectImpl for AptPackageRow {
                 let package_candidate_version = obj.package_candidate_version();
                 let package_description = obj.package_description();
                 let package_source_uri = obj.package_source_uri();
+                let package_origin = obj.package_origin();
+                let package_archive = obj.package_archive();
+                let package_update_class = obj.package_update_class();
+                let package_phased_update_percentage = obj.package_phased_update_percentage();
                 let package_maintainer = obj.package_maintainer();
                 let package_size = obj.package_size();
                 let package_installed_size = obj.package_installed_size();
@@ -130,12 +151,20 @@ impl ObjectImpl for AptPackageRow {
                     &package_arch,
                     &package_installed_version,
                     &package_candidate_version,
+                    &package_origin,
+                    &package_archive,
+                    &package_update_class,
+                    package_phased_update_percentage,
                 );
                 //
                 create_expandable_content(
                     &obj,
                     &expandable_box,
                     &temp_simple_action,
+                    package_name,
+                    package_arch,
+                    package_installed_version,
+                    package_candidate_version,
                     package_description,
                     package_source_uri,
                     package_maintainer,
@@ -313,6 +342,85 @@ fn create_arch_badge(arch: &str) -> ListBox {
     boxedlist
 }
 
+fn create_update_class_badge(
+    update_class: &str,
+    package_origin: &str,
+    package_archive: &str,
+) -> ListBox {
+    let update_class_label = Label::builder()
+        .halign(Align::Start)
+        .hexpand(false)
+        .label(format!(
+            "{}: {}/{}",
+            match update_class {
+                "security" => t!("update_class_security_label"),
+                "third_party" => t!("update_class_third_party_label"),
+                _ => t!("update_class_regular_label"),
+            },
+            package_origin,
+            package_archive
+        ))
+        .margin_start(5)
+        .margin_end(5)
+        .margin_bottom(5)
+        .margin_top(5)
+        .build();
+    match update_class {
+        "security" => update_class_label.add_css_class("destructive-color-text"),
+        "third_party" => update_class_label.add_css_class("warning-color-text"),
+        _ => {}
+    }
+
+    let boxedlist = ListBox::builder()
+        .selection_mode(SelectionMode::None)
+        .halign(Align::Start)
+        .valign(Align::End)
+        .margin_start(5)
+        .margin_end(5)
+        .margin_bottom(10)
+        .build();
+
+    boxedlist.add_css_class("boxed-list");
+    boxedlist.append(&update_class_label);
+    boxedlist
+}
+
+fn create_phased_badge(package_phased_update_percentage: u32) -> ListBox {
+    let phased_label = Label::builder()
+        .halign(Align::Start)
+        .hexpand(false)
+        .label(
+            strfmt::strfmt(
+                &t!("phased_update_badge_label").to_string(),
+                &std::collections::HashMap::from([(
+                    "PERCENT".to_string(),
+                    package_phased_update_percentage.to_string(),
+                )]),
+            )
+            .unwrap(),
+        )
+        .tooltip_text(t!("phased_update_badge_tooltip_text"))
+        .margin_start(5)
+        .margin_end(5)
+        .margin_bottom(5)
+        .margin_top(5)
+        .build();
+    phased_label.add_css_class("warning-color-text");
+
+    let boxedlist = ListBox::builder()
+        .selection_mode(SelectionMode::None)
+        .halign(Align::Start)
+        .valign(Align::End)
+        .margin_start(5)
+        .margin_end(5)
+        .margin_bottom(10)
+        .build();
+
+    boxedlist.add_css_class("boxed-list");
+    boxedlist.append(&phased_label);
+    boxedlist
+}
+
 fn remove_all_children_from_box(parent: &gtk::Box) {
     while let Some(child) = parent.last_child() {
         parent.remove(&child);
@@ -325,6 +433,10 @@ fn create_prefix_content(
     package_arch: &str,
     package_installed_version: &str,
     package_candidate_version: &str,
+    package_origin: &str,
+    package_archive: &str,
+    package_update_class: &str,
+    package_phased_update_percentage: u32,
 ) {
     let package_label = Label::builder()
         .halign(Align::Start)
@@ -341,6 +453,14 @@ fn create_prefix_content(
         package_candidate_version,
     ));
     version_box.append(&create_arch_badge(package_arch));
+    version_box.append(&create_update_class_badge(
+        package_update_class,
+        package_origin,
+        package_archive,
+    ));
+    if package_phased_update_percentage < 100 {
+        version_box.append(&create_phased_badge(package_phased_update_percentage));
+    }
     prefix_box.append(&package_label);
     prefix_box.append(&version_box);
 }
@@ -349,6 +469,10 @@ fn create_expandable_content(
     apt_package_row: &impl IsA<ExpanderRow>,
     expandable_box: &gtk::Box,
     theme_changed_action: &gio::SimpleAction,
+    package_name: String,
+    package_arch: String,
+    package_installed_version: String,
+    package_candidate_version: String,
     package_description: String,
     package_source_uri: String,
     package_maintainer: String,
@@ -382,8 +506,6 @@ fn create_expandable_content(
         .build();
     let changelog_page_button = ToggleButton::builder()
         .label(t!("changelog_page_button_label"))
-        // till we find a way to implement
-        .sensitive(false)
         .group(&description_page_button)
         .build();
     expandable_page_selection_box.append(&description_page_button);
@@ -438,6 +560,23 @@ fn create_expandable_content(
         }
     ));
 
+    changelog_page_button.connect_clicked(clone!(
+        #[strong]
+        expandable_bin,
+        #[strong]
+        changelog_page_button,
+        move |_| {
+            if changelog_page_button.is_active() {
+                expandable_bin.set_child(Some(&changelog_stack_page(
+                    &package_name,
+                    &package_arch,
+                    &package_installed_version,
+                    &package_candidate_version,
+                )));
+            }
+        }
+    ));
+
     apt_package_row.connect_expanded_notify(clone!(
         #[strong]
         expandable_bin,
@@ -508,6 +647,106 @@ fn description_stack_page(package_description: &str) -> gtk::Box {
     description_content_box
 }
 
+fn changelog_stack_page(
+    package_name: &str,
+    package_arch: &str,
+    package_installed_version: &str,
+    package_candidate_version: &str,
+) -> gtk::Box {
+    let changelog_content_box = Box::builder()
+        .hexpand(true)
+        .vexpand(true)
+        .orientation(Orientation::Vertical)
+        .build();
+    let changelog_text_buffer = TextBuffer::builder()
+        .text(t!("changelog_loading_label").to_string() + "\n")
+        .build();
+    let changelog_reference_tag = TextTag::builder()
+        .name("changelog-reference")
+        .weight(700)
+        .underline(pango::Underline::Single)
+        .build();
+    changelog_text_buffer
+        .tag_table()
+        .add(&changelog_reference_tag);
+    let changelog_text_view = TextView::builder()
+        .buffer(&changelog_text_buffer)
+        .hexpand(true)
+        .vexpand(true)
+        .margin_top(0)
+        .margin_bottom(10)
+        .margin_start(15)
+        .margin_end(15)
+        .editable(false)
+        .monospace(true)
+        .build();
+    changelog_content_box.append(&changelog_text_view);
+
+    let package_name = package_name.to_owned();
+    let package_arch = package_arch.to_owned();
+    let package_candidate_version = package_candidate_version.to_owned();
+    // Freshly installed packages have nothing to compare against
+    let package_installed_version =
+        if package_installed_version == t!("installed_version_to_be_installed") {
+            None
+        } else {
+            Some(package_installed_version.to_owned())
+        };
+
+    let (changelog_sender, changelog_receiver) =
+        async_channel::unbounded::<Result<String, String>>();
+    std::thread::spawn(move || {
+        let changelog = ChangelogFetcher::default()
+            .fetch(&package_name, &package_arch, &package_candidate_version)
+            .map(|changelog| {
+                entries_newer_than(
+                    &parse_changelog(&changelog),
+                    package_installed_version.as_deref(),
+                    rust_apt::util::cmp_versions,
+                )
+                .iter()
+                .map(|entry| entry.to_text())
+                .collect::<Vec<String>>()
+                .join("\n")
+            });
+        changelog_sender
+            .send_blocking(changelog)
+            .expect("The channel needs to be open.");
+    });
+
+    let changelog_context = glib::MainContext::default();
+    changelog_context.spawn_local(clone!(
+        #[weak]
+        changelog_text_buffer,
+        async move {
+            while let Ok(changelog) = changelog_receiver.recv().await {
+                let changelog = match changelog {
+                    Ok(t) if !t.trim().is_empty() => t,
+                    Ok(_) => t!("changelog_empty_label").to_string(),
+                    Err(e) => {
+                        eprintln!("Could not fetch changelog: {}", e);
+                        strfmt::strfmt(
+                            &t!("changelog_error_label").to_string(),
+                            &std::collections::HashMap::from([("ERROR".to_string(), e)]),
+                        )
+                        .unwrap()
+                    }
+                };
+                changelog_text_buffer.set_text(&changelog);
+                for reference in find_references(&changelog) {
+                    let start = changelog_text_buffer
+                        .iter_at_offset(changelog[..reference.start].chars().count() as i32);
+                    let end = changelog_text_buffer
+                        .iter_at_offset(changelog[..reference.end].chars().count() as i32);
+                    changelog_text_buffer.apply_tag_by_name("changelog-reference", &start, &end);
+                }
+            }
+        }
+    ));
+
+    changelog_content_box
+}
+
 fn extra_info_stack_page(
     package_maintainer: &str,
     package_size: u64,
@@ -3,6 +3,7 @@ mod imp;
 use crate::apt_update_page::AptPackageSocket;
 use glib::Object;
 use gtk::glib;
+use pika_unixsocket_tools::update_discovery::UpdateClass;
 
 glib::wrapper! {
     pub struct AptPackageRow(ObjectSubclass<imp::AptPackageRow>)
@@ -15,13 +16,23 @@ impl AptPackageRow {
         Object::builder()
             .property("package-name", package.name)
             .property("package-arch", package.arch)
+            .property("package-source-name", package.source_name)
+            .property("package-new-install", package.new_install)
             .property("package-installed-version", package.installed_version)
             .property("package-candidate-version", package.candidate_version)
             .property("package-description", package.description)
             .property("package-source-uri", package.source_uri)
+            .property("package-origin", package.origin)
+            .property("package-archive", package.archive)
+            .property("package-update-class", package.update_class.as_str())
             .property("package-maintainer", package.maintainer)
             .property("package-size", package.size)
             .property("package-installed-size", package.installed_size)
+            .property("package-installed-size-delta", package.installed_size_delta)
+            .property(
+                "package-phased-update-percentage",
+                package.phased_update_percentage,
+            )
             .build()
     }
 }
@@ -32,13 +43,21 @@ impl Default for AptPackageRow {
         Self::new(AptPackageSocket {
             name: "name".to_string(),
             arch: "arch".to_string(),
+            source_name: "name".to_string(),
+            new_install: false,
             installed_version: "0.0".to_string(),
             candidate_version: "0.0".to_string(),
             description: "??".to_string(),
             source_uri: "??".to_string(),
+            origin: "??".to_string(),
+            archive: "??".to_string(),
+            label: "??".to_string(),
+            update_class: UpdateClass::Regular,
             maintainer: "??".to_string(),
             size: 0,
             installed_size: 0,
+            installed_size_delta: 0,
+            phased_update_percentage: 100,
             is_last: false,
         })
     }
@@ -0,0 +1,163 @@
+use adw::prelude::*;
+use gtk::*;
+use pika_unixsocket_tools::debconf_passthrough::split_choices;
+use pika_unixsocket_tools::progress_protocol::{
+    DebconfQuestion, DebconfQuestionKind, ProgressReply,
+};
+use std::collections::HashMap;
+
+/// Whatever holds the answer to a single question.
+enum DebconfAnswerWidget {
+    Select(adw::ComboRow, Vec<String>),
+    Multiselect(Vec<(CheckButton, String)>),
+    Boolean(adw::SwitchRow),
+    String(adw::EntryRow),
+    Password(adw::PasswordEntryRow),
+    Note,
+}
+
+impl DebconfAnswerWidget {
+    fn value(&self) -> Option<String> {
+        match self {
+            DebconfAnswerWidget::Select(combo_row, choices) => {
+                choices.get(combo_row.selected() as usize).cloned()
+            }
+            DebconfAnswerWidget::Multiselect(check_buttons) => Some(
+                check_buttons
+                    .iter()
+                    .filter(|(check_button, _)| check_button.is_active())
+                    .map(|(_, choice)| choice.replace(',', "\\,"))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            ),
+            DebconfAnswerWidget::Boolean(switch_row) => Some(switch_row.is_active().to_string()),
+            DebconfAnswerWidget::String(entry_row) => Some(entry_row.text().to_string()),
+            DebconfAnswerWidget::Password(entry_row) => Some(entry_row.text().to_string()),
+            DebconfAnswerWidget::Note => None,
+        }
+    }
+}
+
+/// Shows a page of debconf questions from a maintainer script, closing the dialog answers
+/// with what is filled in.
+pub fn apt_debconf_prompt(
+    window: &adw::ApplicationWindow,
+    upgrade_reply_sender: &async_channel::Sender<ProgressReply>,
+    id: u32,
+    title: String,
+    questions: Vec<DebconfQuestion>,
+) {
+    let apt_debconf_questions_box = Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(15)
+        .build();
+
+    let mut answer_widgets = Vec::new();
+    for question in questions {
+        let question_prefrencesgroup = adw::PreferencesGroup::builder()
+            .title(glib::markup_escape_text(&question.description))
+            .description(glib::markup_escape_text(&question.extended_description))
+            .build();
+        let answer_widget = match question.kind {
+            DebconfQuestionKind::Select => {
+                let choices = question.choices;
+                let choice_strs: Vec<&str> = choices.iter().map(|t| t.as_str()).collect();
+                let combo_row = adw::ComboRow::builder()
+                    .title(t!("apt_debconf_dialog_select_label"))
+                    .model(&StringList::new(&choice_strs))
+                    .build();
+                if let Some(position) = choices.iter().position(|t| *t == question.value) {
+                    combo_row.set_selected(position as u32);
+                }
+                question_prefrencesgroup.add(&combo_row);
+                DebconfAnswerWidget::Select(combo_row, choices)
+            }
+            DebconfQuestionKind::Multiselect => {
+                let selected = split_choices(&question.value);
+                let mut check_buttons = Vec::new();
+                for choice in question.choices {
+                    let check_button = CheckButton::builder()
+                        .active(selected.contains(&choice))
+                        .build();
+                    let choice_row = adw::ActionRow::builder()
+                        .title(&choice)
+                        .use_markup(false)
+                        .activatable_widget(&check_button)
+                        .build();
+                    choice_row.add_prefix(&check_button);
+                    question_prefrencesgroup.add(&choice_row);
+                    check_buttons.push((check_button, choice));
+                }
+                DebconfAnswerWidget::Multiselect(check_buttons)
+            }
+            DebconfQuestionKind::Boolean => {
+                let switch_row = adw::SwitchRow::builder()
+                    .title(t!("apt_debconf_dialog_boolean_label"))
+                    .active(question.value == "true")
+                    .build();
+                question_prefrencesgroup.add(&switch_row);
+                DebconfAnswerWidget::Boolean(switch_row)
+            }
+            DebconfQuestionKind::String => {
+                let entry_row = adw::EntryRow::builder()
+                    .title(t!("apt_debconf_dialog_string_label"))
+                    .text(&question.value)
+                    .build();
+                question_prefrencesgroup.add(&entry_row);
+                DebconfAnswerWidget::String(entry_row)
+            }
+            DebconfQuestionKind::Password => {
+                // debconf never hands out stored passwords, the field always starts empty
+                let entry_row = adw::PasswordEntryRow::builder()
+                    .title(t!("apt_debconf_dialog_password_label"))
+                    .build();
+                question_prefrencesgroup.add(&entry_row);
+                DebconfAnswerWidget::Password(entry_row)
+            }
+            DebconfQuestionKind::Note => DebconfAnswerWidget::Note,
+        };
+        apt_debconf_questions_box.append(&question_prefrencesgroup);
+        answer_widgets.push((question.tag, answer_widget));
+    }
+
+    let apt_debconf_questions_viewport = ScrolledWindow::builder()
+        .hexpand(true)
+        .propagate_natural_height(true)
+        .max_content_height(450)
+        .hscrollbar_policy(PolicyType::Never)
+        .child(&apt_debconf_questions_box)
+        .build();
+
+    let apt_debconf_dialog = adw::MessageDialog::builder()
+        .transient_for(window)
+        .heading(if title.is_empty() {
+            t!("apt_debconf_dialog_heading").to_string()
+        } else {
+            title
+        })
+        .body(t!("apt_debconf_dialog_body"))
+        .extra_child(&apt_debconf_questions_viewport)
+        .width_request(600)
+        .build();
+
+    apt_debconf_dialog.add_response(
+        "apt_debconf_dialog_continue",
+        &t!("apt_debconf_dialog_continue_label").to_string(),
+    );
+    apt_debconf_dialog.set_response_appearance(
+        "apt_debconf_dialog_continue",
+        adw::ResponseAppearance::Suggested,
+    );
+    apt_debconf_dialog.set_default_response(Some("apt_debconf_dialog_continue"));
+    apt_debconf_dialog.set_close_response("apt_debconf_dialog_continue");
+
+    let upgrade_reply_sender = upgrade_reply_sender.clone();
+    apt_debconf_dialog.choose(None::<&gio::Cancellable>, move |_| {
+        let values: HashMap<String, String> = answer_widgets
+            .iter()
+            .filter_map(|(tag, answer_widget)| Some((tag.to_owned(), answer_widget.value()?)))
+            .collect();
+        // The helper keeps the defaults on its own if the connection is gone
+        let _ = upgrade_reply_sender.send_blocking(ProgressReply::DebconfAnswer { id, values });
+    });
+}
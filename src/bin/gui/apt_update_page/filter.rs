@@ -0,0 +1,431 @@
+use crate::apt_package_row::AptPackageRow;
+use adw::gio::SimpleAction;
+use adw::prelude::*;
+use gtk::glib::*;
+use gtk::*;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Default, PartialEq)]
+enum AptUpdateSort {
+    #[default]
+    Name,
+    DownloadSize,
+    InstalledSizeDelta,
+}
+
+/// What the filter bar currently asks for, `None` means "all".
+#[derive(Default)]
+struct AptUpdateFilter {
+    search: String,
+    origin: Option<String>,
+    arch: Option<String>,
+    update_class: Option<String>,
+    new_install: Option<bool>,
+    sort: AptUpdateSort,
+    group_by_source: bool,
+    collapsed_sources: HashSet<String>,
+    // Sources with at least one row passing the filter, their header rows stay visible
+    matching_sources: HashSet<String>,
+}
+
+impl AptUpdateFilter {
+    fn matches(&self, apt_row: &AptPackageRow) -> bool {
+        (self.search.is_empty()
+            || apt_row
+                .package_name()
+                .to_lowercase()
+                .contains(&self.search.to_lowercase()))
+            && matches_choice(&self.origin, apt_row.package_origin())
+            && matches_choice(&self.arch, apt_row.package_arch())
+            && matches_choice(&self.update_class, apt_row.package_update_class())
+            && matches_choice(&self.new_install, apt_row.package_new_install())
+    }
+
+    fn is_visible(&self, row: &ListBoxRow) -> bool {
+        match row.downcast_ref::<AptPackageRow>() {
+            Some(apt_row) => {
+                self.matches(apt_row)
+                    && !(self.group_by_source
+                        && self
+                            .collapsed_sources
+                            .contains(&apt_row.package_source_name()))
+            }
+            None => match row.downcast_ref::<adw::ActionRow>() {
+                Some(source_row) => self.matching_sources.contains(source_row.title().as_str()),
+                None => true,
+            },
+        }
+    }
+
+    fn compare(&self, row0: &ListBoxRow, row1: &ListBoxRow) -> std::cmp::Ordering {
+        let apt_row0 = row0.downcast_ref::<AptPackageRow>();
+        let apt_row1 = row1.downcast_ref::<AptPackageRow>();
+        if self.group_by_source {
+            // Source header rows sort right before the packages built from them
+            let group_key = |row: &ListBoxRow, apt_row: Option<&AptPackageRow>| match apt_row {
+                Some(apt_row) => (apt_row.package_source_name(), 1),
+                None => match row.downcast_ref::<adw::ActionRow>() {
+                    Some(source_row) => (source_row.title().to_string(), 0),
+                    None => (String::new(), 0),
+                },
+            };
+            let ordering = group_key(row0, apt_row0).cmp(&group_key(row1, apt_row1));
+            if ordering.is_ne() {
+                return ordering;
+            }
+        }
+        let (apt_row0, apt_row1) = match (apt_row0, apt_row1) {
+            (Some(apt_row0), Some(apt_row1)) => (apt_row0, apt_row1),
+            _ => return std::cmp::Ordering::Equal,
+        };
+        let by_name = apt_row0.package_name().cmp(&apt_row1.package_name());
+        match self.sort {
+            AptUpdateSort::Name => by_name,
+            AptUpdateSort::DownloadSize => apt_row1
+                .package_size()
+                .cmp(&apt_row0.package_size())
+                .then(by_name),
+            AptUpdateSort::InstalledSizeDelta => apt_row1
+                .package_installed_size_delta()
+                .cmp(&apt_row0.package_installed_size_delta())
+                .then(by_name),
+        }
+    }
+}
+
+fn matches_choice<T: PartialEq>(choice: &Option<T>, value: T) -> bool {
+    match choice {
+        Some(t) => *t == value,
+        None => true,
+    }
+}
+
+/// Filter, sort and grouping controls for the APT update list, `filter_reload_action`
+/// refills the origin and architecture choices once every row is in.
+pub fn apt_update_filter_box(
+    packages_boxedlist: &ListBox,
+    searchbar: &SearchEntry,
+    filter_reload_action: &SimpleAction,
+) -> gtk::Box {
+    let apt_update_filter = Rc::new(RefCell::new(AptUpdateFilter::default()));
+
+    let filter_box = Box::builder()
+        .orientation(Orientation::Horizontal)
+        .hexpand(true)
+        .halign(Align::Center)
+        .margin_start(15)
+        .margin_end(15)
+        .spacing(5)
+        .sensitive(false)
+        .build();
+
+    let origin_string_list = StringList::new(&[&*t!("apt_filter_all_origins")]);
+    let origin_dropdown = DropDown::builder()
+        .model(&origin_string_list)
+        .tooltip_text(t!("apt_filter_origin_tooltip_text"))
+        .build();
+
+    let arch_string_list = StringList::new(&[&*t!("apt_filter_all_archs")]);
+    let arch_dropdown = DropDown::builder()
+        .model(&arch_string_list)
+        .tooltip_text(t!("apt_filter_arch_tooltip_text"))
+        .build();
+
+    let update_class_dropdown = DropDown::from_strings(&[
+        &*t!("apt_filter_all_update_classes"),
+        &*t!("update_class_security_label"),
+        &*t!("update_class_regular_label"),
+        &*t!("update_class_third_party_label"),
+    ]);
+    update_class_dropdown.set_tooltip_text(Some(&t!("apt_filter_update_class_tooltip_text")));
+
+    let change_kind_dropdown = DropDown::from_strings(&[
+        &*t!("apt_filter_all_change_kinds"),
+        &*t!("apt_filter_upgrades_only"),
+        &*t!("apt_filter_new_installs_only"),
+    ]);
+    change_kind_dropdown.set_tooltip_text(Some(&t!("apt_filter_change_kind_tooltip_text")));
+
+    let sort_dropdown = DropDown::from_strings(&[
+        &*t!("apt_sort_by_name"),
+        &*t!("apt_sort_by_download_size"),
+        &*t!("apt_sort_by_installed_size_delta"),
+    ]);
+    sort_dropdown.set_tooltip_text(Some(&t!("apt_sort_tooltip_text")));
+
+    let group_by_source_button = ToggleButton::builder()
+        .icon_name("view-list-symbolic")
+        .tooltip_text(t!("apt_group_by_source_tooltip_text"))
+        .build();
+
+    packages_boxedlist.set_filter_func(clone!(
+        #[strong]
+        apt_update_filter,
+        move |row| apt_update_filter.borrow().is_visible(row)
+    ));
+
+    packages_boxedlist.set_sort_func(clone!(
+        #[strong]
+        apt_update_filter,
+        move |row0, row1| apt_update_filter.borrow().compare(row0, row1).into()
+    ));
+
+    let apply_filter_action = SimpleAction::new("apt_filter_apply", None);
+    apply_filter_action.connect_activate(clone!(
+        #[strong]
+        apt_update_filter,
+        #[weak]
+        packages_boxedlist,
+        move |_, _| {
+            let matching_sources = apt_package_rows(&packages_boxedlist)
+                .iter()
+                .filter(|apt_row| apt_update_filter.borrow().matches(apt_row))
+                .map(|apt_row| apt_row.package_source_name())
+                .collect();
+            apt_update_filter.borrow_mut().matching_sources = matching_sources;
+            packages_boxedlist.invalidate_filter();
+            packages_boxedlist.invalidate_sort();
+        }
+    ));
+
+    searchbar.connect_search_changed(clone!(
+        #[strong]
+        apt_update_filter,
+        #[strong]
+        apply_filter_action,
+        move |searchbar| {
+            apt_update_filter.borrow_mut().search = searchbar.text().to_string();
+            apply_filter_action.activate(None);
+        }
+    ));
+
+    let selected_string = |dropdown: &DropDown| -> Option<String> {
+        match dropdown.selected() {
+            0 | INVALID_LIST_POSITION => None,
+            _ => dropdown
+                .selected_item()
+                .and_downcast::<StringObject>()
+                .map(|t| t.string().to_string()),
+        }
+    };
+
+    origin_dropdown.connect_selected_notify(clone!(
+        #[strong]
+        apt_update_filter,
+        #[strong]
+        apply_filter_action,
+        move |dropdown| {
+            apt_update_filter.borrow_mut().origin = selected_string(dropdown);
+            apply_filter_action.activate(None);
+        }
+    ));
+
+    arch_dropdown.connect_selected_notify(clone!(
+        #[strong]
+        apt_update_filter,
+        #[strong]
+        apply_filter_action,
+        move |dropdown| {
+            apt_update_filter.borrow_mut().arch = selected_string(dropdown);
+            apply_filter_action.activate(None);
+        }
+    ));
+
+    update_class_dropdown.connect_selected_notify(clone!(
+        #[strong]
+        apt_update_filter,
+        #[strong]
+        apply_filter_action,
+        move |dropdown| {
+            apt_update_filter.borrow_mut().update_class = match dropdown.selected() {
+                1 => Some("security".to_owned()),
+                2 => Some("regular".to_owned()),
+                3 => Some("third_party".to_owned()),
+                _ => None,
+            };
+            apply_filter_action.activate(None);
+        }
+    ));
+
+    change_kind_dropdown.connect_selected_notify(clone!(
+        #[strong]
+        apt_update_filter,
+        #[strong]
+        apply_filter_action,
+        move |dropdown| {
+            apt_update_filter.borrow_mut().new_install = match dropdown.selected() {
+                1 => Some(false),
+                2 => Some(true),
+                _ => None,
+            };
+            apply_filter_action.activate(None);
+        }
+    ));
+
+    sort_dropdown.connect_selected_notify(clone!(
+        #[strong]
+        apt_update_filter,
+        #[strong]
+        apply_filter_action,
+        move |dropdown| {
+            apt_update_filter.borrow_mut().sort = match dropdown.selected() {
+                1 => AptUpdateSort::DownloadSize,
+                2 => AptUpdateSort::InstalledSizeDelta,
+                _ => AptUpdateSort::Name,
+            };
+            apply_filter_action.activate(None);
+        }
+    ));
+
+    group_by_source_button.connect_toggled(clone!(
+        #[strong]
+        apt_update_filter,
+        #[strong]
+        apply_filter_action,
+        #[weak]
+        packages_boxedlist,
+        move |group_by_source_button| {
+            let group_by_source = group_by_source_button.is_active();
+            {
+                let mut apt_update_filter = apt_update_filter.borrow_mut();
+                apt_update_filter.group_by_source = group_by_source;
+                apt_update_filter.collapsed_sources.clear();
+            }
+            remove_source_rows(&packages_boxedlist);
+            if group_by_source {
+                add_source_rows(
+                    &packages_boxedlist,
+                    &apt_update_filter,
+                    &apply_filter_action,
+                );
+            }
+            apply_filter_action.activate(None);
+        }
+    ));
+
+    filter_reload_action.connect_activate(clone!(
+        #[weak]
+        packages_boxedlist,
+        #[weak]
+        filter_box,
+        #[weak]
+        origin_string_list,
+        #[weak]
+        arch_string_list,
+        move |_, _| {
+            let apt_rows = apt_package_rows(&packages_boxedlist);
+            for (string_list, values) in [
+                (
+                    &origin_string_list,
+                    apt_rows
+                        .iter()
+                        .map(|t| t.package_origin())
+                        .collect::<Vec<String>>(),
+                ),
+                (
+                    &arch_string_list,
+                    apt_rows
+                        .iter()
+                        .map(|t| t.package_arch())
+                        .collect::<Vec<String>>(),
+                ),
+            ] {
+                let mut values = values;
+                values.sort();
+                values.dedup();
+                let values: Vec<&str> = values.iter().map(|t| t.as_str()).collect();
+                string_list.splice(1, string_list.n_items() - 1, &values);
+            }
+            filter_box.set_sensitive(true);
+        }
+    ));
+
+    filter_box.append(&origin_dropdown);
+    filter_box.append(&arch_dropdown);
+    filter_box.append(&update_class_dropdown);
+    filter_box.append(&change_kind_dropdown);
+    filter_box.append(&sort_dropdown);
+    filter_box.append(&group_by_source_button);
+
+    filter_box
+}
+
+fn apt_package_rows(packages_boxedlist: &ListBox) -> Vec<AptPackageRow> {
+    let mut apt_rows = Vec::new();
+    let mut child_counter = packages_boxedlist.first_child();
+    while let Some(child) = child_counter {
+        child_counter = child.next_sibling();
+        if let Ok(apt_row) = child.downcast::<AptPackageRow>() {
+            apt_rows.push(apt_row);
+        }
+    }
+    apt_rows
+}
+
+fn remove_source_rows(packages_boxedlist: &ListBox) {
+    let mut child_counter = packages_boxedlist.first_child();
+    while let Some(child) = child_counter {
+        child_counter = child.next_sibling();
+        if let Some(source_row) = child.downcast_ref::<adw::ActionRow>() {
+            packages_boxedlist.remove(source_row);
+        }
+    }
+}
+
+/// One collapsible header row per source package, activating it hides or shows its packages.
+fn add_source_rows(
+    packages_boxedlist: &ListBox,
+    apt_update_filter: &Rc<RefCell<AptUpdateFilter>>,
+    apply_filter_action: &SimpleAction,
+) {
+    let mut source_package_counts: HashMap<String, u32> = HashMap::new();
+    for apt_row in apt_package_rows(packages_boxedlist) {
+        *source_package_counts
+            .entry(apt_row.package_source_name())
+            .or_default() += 1;
+    }
+    for (source_name, package_count) in source_package_counts {
+        let source_row_icon = Image::from_icon_name("pan-down-symbolic");
+        let source_row = adw::ActionRow::builder()
+            .title(&source_name)
+            .subtitle(
+                strfmt::strfmt(
+                    &t!("apt_source_group_subtitle").to_string(),
+                    &HashMap::from([("NUM".to_string(), package_count.to_string())]),
+                )
+                .unwrap(),
+            )
+            .activatable(true)
+            .build();
+        source_row.add_css_class("heading");
+        source_row.add_suffix(&source_row_icon);
+        source_row.connect_activated(clone!(
+            #[weak]
+            source_row_icon,
+            #[strong]
+            apt_update_filter,
+            #[strong]
+            apply_filter_action,
+            move |_| {
+                let collapsed = {
+                    let collapsed_sources = &mut apt_update_filter.borrow_mut().collapsed_sources;
+                    if !collapsed_sources.remove(&source_name) {
+                        collapsed_sources.insert(source_name.clone());
+                        true
+                    } else {
+                        false
+                    }
+                };
+                source_row_icon.set_icon_name(Some(if collapsed {
+                    "pan-end-symbolic"
+                } else {
+                    "pan-down-symbolic"
+                }));
+                apply_filter_action.activate(None);
+            }
+        ));
+        packages_boxedlist.append(&source_row);
+    }
+}
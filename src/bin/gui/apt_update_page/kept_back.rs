@@ -0,0 +1,125 @@
+use super::process::kept_back_reason_text;
+use adw::prelude::*;
+use gtk::glib::*;
+use gtk::*;
+use pika_unixsocket_tools::update_discovery::KeptBackPackage;
+use pika_unixsocket_tools::update_holds::ForcedUpgrade;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Lists what APT keeps back, packages that resolve anyway get a switch to include them.
+pub fn apt_kept_back_box(
+    get_kept_back_receiver: async_channel::Receiver<Vec<KeptBackPackage>>,
+    forced_upgrades_vec: &Rc<RefCell<Vec<ForcedUpgrade>>>,
+    update_button: &Button,
+) -> gtk::Box {
+    let kept_back_main_box = Box::builder()
+        .orientation(Orientation::Vertical)
+        .visible(false)
+        .build();
+
+    let kept_back_boxedlist = ListBox::builder()
+        .selection_mode(SelectionMode::None)
+        .build();
+    kept_back_boxedlist.add_css_class("boxed-list");
+
+    let kept_back_viewport = ScrolledWindow::builder()
+        .hexpand(true)
+        .has_frame(true)
+        .margin_top(5)
+        .hscrollbar_policy(PolicyType::Never)
+        .propagate_natural_height(true)
+        .max_content_height(200)
+        .child(&kept_back_boxedlist)
+        .overflow(Overflow::Hidden)
+        .build();
+    kept_back_viewport.add_css_class("round-all-scroll-no-padding");
+
+    let kept_back_expander = Expander::builder()
+        .margin_start(15)
+        .margin_end(15)
+        .margin_bottom(15)
+        .child(&kept_back_viewport)
+        .build();
+
+    let get_kept_back_server_context = MainContext::default();
+    // The main loop executes the asynchronous block
+    get_kept_back_server_context.spawn_local(clone!(
+        #[strong]
+        kept_back_main_box,
+        #[strong]
+        kept_back_boxedlist,
+        #[strong]
+        kept_back_expander,
+        #[strong]
+        forced_upgrades_vec,
+        #[strong]
+        update_button,
+        async move {
+            while let Ok(kept_back_packages) = get_kept_back_receiver.recv().await {
+                kept_back_boxedlist.remove_all();
+                kept_back_main_box.set_visible(!kept_back_packages.is_empty());
+                kept_back_expander.set_label(Some(
+                    &strfmt::strfmt(
+                        &t!("apt_kept_back_expander_label").to_string(),
+                        &HashMap::from([("NUM".to_string(), kept_back_packages.len().to_string())]),
+                    )
+                    .unwrap(),
+                ));
+                for kept_back in kept_back_packages {
+                    let kept_back_row = adw::ActionRow::builder()
+                        .title(format!(
+                            "{}:{}",
+                            kept_back.change.name, kept_back.change.arch
+                        ))
+                        .subtitle(format!(
+                            "{} → {}\n{}",
+                            kept_back
+                                .change
+                                .installed_version
+                                .as_deref()
+                                .unwrap_or_default(),
+                            kept_back
+                                .change
+                                .candidate_version
+                                .as_deref()
+                                .unwrap_or_default(),
+                            kept_back_reason_text(&kept_back)
+                        ))
+                        // Resolver errors are full of angle brackets
+                        .use_markup(false)
+                        .build();
+                    if kept_back.can_force {
+                        let forced_upgrade =
+                            ForcedUpgrade::new(&kept_back.change.name, &kept_back.change.arch);
+                        let kept_back_force_switch = Switch::builder()
+                            .valign(Align::Center)
+                            .tooltip_text(t!("kept_back_force_switch_tooltip_text"))
+                            .build();
+                        kept_back_force_switch.connect_active_notify(clone!(
+                            #[strong]
+                            forced_upgrades_vec,
+                            #[strong]
+                            update_button,
+                            move |kept_back_force_switch| {
+                                let mut forced_upgrades_vec = forced_upgrades_vec.borrow_mut();
+                                forced_upgrades_vec.retain(|t| *t != forced_upgrade);
+                                if kept_back_force_switch.is_active() {
+                                    forced_upgrades_vec.push(forced_upgrade.clone());
+                                    update_button.set_sensitive(true);
+                                }
+                            }
+                        ));
+                        kept_back_row.add_suffix(&kept_back_force_switch);
+                    }
+                    kept_back_boxedlist.append(&kept_back_row);
+                }
+            }
+        }
+    ));
+
+    kept_back_main_box.append(&kept_back_expander);
+
+    kept_back_main_box
+}
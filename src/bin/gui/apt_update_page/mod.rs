@@ -1,14 +1,23 @@
-mod process;
+mod debconf_dialog;
+mod filter;
+mod kept_back;
+pub mod process;
 
 use crate::apt_package_row::AptPackageRow;
+use crate::config::APP_ID;
 use adw::gio::SimpleAction;
 use adw::prelude::*;
 use gtk::glib::*;
 use gtk::*;
 //use pika_unixsocket_tools::pika_unixsocket_tools::*;
-use rust_apt::cache::*;
-use rust_apt::new_cache;
-use rust_apt::records::RecordField;
+use pika_unixsocket_tools::apt_helper_error::{AptHelperError, AptHelperErrorKind};
+use pika_unixsocket_tools::phased_updates::PhasingPolicy;
+use pika_unixsocket_tools::system_health::SystemHealth;
+use pika_unixsocket_tools::update_discovery::{
+    kept_back_packages, security_only_exclusions, upgradable_packages, AptBackend, AptChange,
+    KeptBackPackage, RustAptBackend, UpdateClass,
+};
+use pika_unixsocket_tools::update_holds::{Exclusion, ForcedUpgrade, UpdateHolds};
 use std::cell::RefCell;
 //use std::process::Command;
 use std::io::BufRead;
@@ -33,15 +42,30 @@ fn run_addon_command(
     )
     .stderr_to_stdout()
     .stdout_file(pipe_writer)
+    .unchecked()
     .start()?;
+    let mut output = String::new();
     for line in BufReader::new(pipe_reader).lines() {
         let line_clone = line?;
+        output.push_str(&line_clone);
+        output.push('\n');
         log_loop_sender
             .send_blocking(AddonChannelMsg::LogLoopLine(line_clone.clone()))
             .expect("Channel needs to be opened.");
         println!("{}", line_clone);
     }
-    child.wait()?;
+    let status = child.wait()?.status;
+    if !status.success() {
+        // apt-get only tells us what went wrong through its output
+        let kind = match status.code().and_then(AptHelperErrorKind::from_exit_code) {
+            Some(kind) => kind,
+            None => AptHelperErrorKind::classify(&output, AptHelperErrorKind::Unknown),
+        };
+        return Err(std::boxed::Box::new(AptHelperError::new(
+            kind,
+            String::new(),
+        )));
+    }
 
     Ok(())
 }
@@ -50,13 +74,22 @@ fn run_addon_command(
 pub struct AptPackageSocket {
     pub name: String,
     pub arch: String,
+    pub source_name: String,
+    pub new_install: bool,
     pub installed_version: String,
     pub candidate_version: String,
     pub description: String,
     pub source_uri: String,
+    pub origin: String,
+    pub archive: String,
+    pub label: String,
+    pub update_class: UpdateClass,
     pub maintainer: String,
     pub size: u64,
     pub installed_size: u64,
+    pub installed_size_delta: i64,
+    /// 100 unless the update is phased.
+    pub phased_update_percentage: u32,
     pub is_last: bool,
 }
 pub fn apt_update_page(
@@ -70,7 +103,9 @@ pub fn apt_update_page(
     initiated_by_main: Rc<RefCell<bool>>,
     update_sys_tray: &SimpleAction,
     apt_update_count: &Rc<RefCell<i32>>,
+    apt_security_update_count: &Rc<RefCell<i32>>,
     flatpak_update_count: &Rc<RefCell<i32>>,
+    apt_prefetch_action: &SimpleAction,
 ) -> gtk::Box {
     /*let (update_percent_sender, update_percent_receiver) = async_channel::unbounded::<String>();
     //let update_percent_sender = update_percent_sender.clone();
@@ -81,10 +116,14 @@ pub fn apt_update_page(
     //let update_status_sender_clone0 = update_status_sender.clone();*/
     let (get_upgradable_sender, get_upgradable_receiver) = async_channel::unbounded();
     let get_upgradable_sender = get_upgradable_sender.clone();
+    let (get_kept_back_sender, get_kept_back_receiver) = async_channel::unbounded();
+    let (system_health_sender, system_health_receiver) = async_channel::unbounded();
 
     (*apt_update_count.borrow_mut() = 0);
+    (*apt_security_update_count.borrow_mut() = 0);
 
-    let excluded_updates_vec: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(Vec::new()));
+    let excluded_updates_vec: Rc<RefCell<Vec<Exclusion>>> = Rc::new(RefCell::new(Vec::new()));
+    let forced_upgrades_vec: Rc<RefCell<Vec<ForcedUpgrade>>> = Rc::new(RefCell::new(Vec::new()));
 
     /*thread::spawn(move || {
         Runtime::new().unwrap().block_on(start_socket_server_no_log(
@@ -114,15 +153,7 @@ pub fn apt_update_page(
         )
         .unwrap();
         if instance.is_single() {
-            let current_locale = match std::env::var_os("LANG") {
-                Some(v) => v
-                    .into_string()
-                    .unwrap()
-                    .chars()
-                    .take_while(|&ch| ch != '.')
-                    .collect::<String>(),
-                None => panic!("$LANG is not set"),
-            };
+            let current_locale = rust_i18n::locale().to_string();
             let apt_update_command = Command::new("pkexec")
                 .args([
                     "/usr/lib/pika/pikman-update-manager/scripts/apt_update",
@@ -163,8 +194,15 @@ pub fn apt_update_page(
                     .send_blocking(AddonChannelMsg::LogLoopStatus(true))
                     .expect("The channel needs to be open.");
             }
-            Err(_) => {
+            Err(e) => {
                 println!("Status: Addon Command Failed");
+                if let Some(error) = e.downcast_ref::<AptHelperError>() {
+                    log_loop_sender_clone1
+                        .send_blocking(AddonChannelMsg::LogLoopLine(
+                            process::apt_helper_error_text(error.kind, &error.message),
+                        ))
+                        .expect("The channel needs to be open.");
+                }
                 log_loop_sender_clone1
                     .send_blocking(AddonChannelMsg::LogLoopStatus(false))
                     .expect("The channel needs to be open.");
@@ -209,6 +247,10 @@ pub fn apt_update_page(
         .build();
     packages_viewport.add_css_class("round-all-scroll-no-padding");
 
+    let apt_filter_reload_action = SimpleAction::new("apt_filter_reload", None);
+    let apt_update_filter_box =
+        filter::apt_update_filter_box(&packages_boxedlist, &searchbar, &apt_filter_reload_action);
+
     let packages_no_viewport_page = adw::StatusPage::builder()
         .icon_name("emblem-default-symbolic")
         .title(t!("apt_packages_no_viewport_page_title"))
@@ -372,6 +414,8 @@ pub fn apt_update_page(
         #[strong]
         excluded_updates_vec,
         #[strong]
+        forced_upgrades_vec,
+        #[strong]
         theme_changed_action,
         #[strong]
         flatpak_update_button,
@@ -380,6 +424,7 @@ pub fn apt_update_page(
         move |_| {
             process::apt_process_update(
                 &excluded_updates_vec.borrow(),
+                &forced_upgrades_vec.borrow(),
                 window,
                 &retry_signal_action,
                 &flatpak_update_button.borrow(),
@@ -389,7 +434,164 @@ pub fn apt_update_page(
         }
     ));
 
+    let apt_kept_back_box =
+        kept_back::apt_kept_back_box(get_kept_back_receiver, &forced_upgrades_vec, &update_button);
+
+    let system_health: Rc<RefCell<SystemHealth>> = Rc::new(RefCell::new(SystemHealth::default()));
+
+    let system_health_banner = adw::Banner::builder()
+        .title(t!("system_health_banner_title"))
+        .button_label(t!("system_health_banner_button_label"))
+        .revealed(false)
+        .build();
+
+    system_health_banner.connect_button_clicked(clone!(
+        #[weak]
+        window,
+        #[weak]
+        retry_signal_action,
+        #[strong]
+        system_health,
+        #[strong]
+        theme_changed_action,
+        #[strong]
+        flatpak_update_button,
+        #[strong]
+        initiated_by_main,
+        move |_| {
+            let system_repair_dialog = adw::MessageDialog::builder()
+                .transient_for(&window)
+                .heading(t!("system_repair_dialog_heading"))
+                .body(system_health_text(&system_health.borrow()))
+                .build();
+            system_repair_dialog.add_response(
+                "system_repair_dialog_cancel",
+                &t!("system_repair_dialog_cancel_label").to_string(),
+            );
+            system_repair_dialog.add_response(
+                "system_repair_dialog_repair",
+                &t!("system_repair_dialog_repair_label").to_string(),
+            );
+            system_repair_dialog.set_response_appearance(
+                "system_repair_dialog_repair",
+                adw::ResponseAppearance::Suggested,
+            );
+            system_repair_dialog.set_default_response(Some("system_repair_dialog_repair"));
+            system_repair_dialog.set_close_response("system_repair_dialog_cancel");
+            system_repair_dialog.choose(
+                None::<&gio::Cancellable>,
+                clone!(
+                    #[strong]
+                    retry_signal_action,
+                    #[strong]
+                    theme_changed_action,
+                    #[strong]
+                    flatpak_update_button,
+                    #[strong]
+                    initiated_by_main,
+                    move |choice| {
+                        if choice == "system_repair_dialog_repair" {
+                            process::apt_process_repair(
+                                window,
+                                &retry_signal_action,
+                                &flatpak_update_button.borrow(),
+                                initiated_by_main.clone(),
+                                &theme_changed_action,
+                            );
+                        }
+                    }
+                ),
+            );
+        }
+    ));
+
+    let system_health_server_context = MainContext::default();
+    // The main loop executes the asynchronous block
+    system_health_server_context.spawn_local(clone!(
+        #[strong]
+        system_health,
+        #[strong]
+        system_health_banner,
+        async move {
+            while let Ok(state) = system_health_receiver.recv().await {
+                system_health_banner.set_revealed(!state.is_healthy());
+                *system_health.borrow_mut() = state;
+            }
+        }
+    ));
+
+    let security_update_button = Button::builder()
+        .halign(Align::End)
+        .valign(Align::Center)
+        .hexpand(false)
+        .sensitive(false)
+        .margin_start(10)
+        .margin_end(10)
+        .margin_bottom(15)
+        .label(t!("security_update_button_label"))
+        .tooltip_text(t!("security_update_button_tooltip_text"))
+        .build();
+
+    let (security_exclusions_sender, security_exclusions_receiver) =
+        async_channel::unbounded::<Result<Vec<Exclusion>, AptHelperError>>();
+
+    security_update_button.connect_clicked(clone!(
+        #[weak]
+        packages_boxedlist,
+        move |security_update_button| {
+            set_apt_row_marks_by_update_class(&packages_boxedlist, UpdateClass::Security);
+            security_update_button.set_sensitive(false);
+            let security_exclusions_sender = security_exclusions_sender.clone();
+            // Built from the full upgrade, so removals it asks for are left out too
+            thread::spawn(move || {
+                let _ = security_exclusions_sender.send_blocking(
+                    RustAptBackend
+                        .full_upgrade_changes()
+                        .map(|t| security_only_exclusions(&t)),
+                );
+            });
+        }
+    ));
+
+    let security_exclusions_context = MainContext::default();
+    // The main loop executes the asynchronous block
+    security_exclusions_context.spawn_local(clone!(
+        #[weak]
+        window,
+        #[weak]
+        retry_signal_action,
+        #[strong]
+        security_update_button,
+        #[strong]
+        theme_changed_action,
+        #[strong]
+        flatpak_update_button,
+        #[strong]
+        initiated_by_main,
+        async move {
+            while let Ok(state) = security_exclusions_receiver.recv().await {
+                security_update_button.set_sensitive(true);
+                match state {
+                    Ok(excluded_updates_vec) => process::apt_process_security_update(
+                        &excluded_updates_vec,
+                        &Vec::new(),
+                        window.clone(),
+                        &retry_signal_action,
+                        &flatpak_update_button.borrow(),
+                        initiated_by_main.clone(),
+                        &theme_changed_action,
+                    ),
+                    Err(e) => process::apt_error_dialog(
+                        &window,
+                        &process::apt_helper_error_text(e.kind, &e.message),
+                    ),
+                }
+            }
+        }
+    ));
+
     bottom_bar.append(&select_button);
+    bottom_bar.append(&security_update_button);
     bottom_bar.append(&update_button);
 
     /*let update_percent_server_context = MainContext::default();
@@ -502,7 +704,11 @@ pub fn apt_update_page(
                     AddonChannelMsg::LogLoopStatus(state) => {
                         match state {
                             true => {
-                                get_apt_upgrades(&get_upgradable_sender);
+                                get_apt_upgrades(
+                                    &get_upgradable_sender,
+                                    &get_kept_back_sender,
+                                    &system_health_sender,
+                                );
                                 log_terminal_buffer.delete(
                                     &mut log_terminal_buffer.start_iter(),
                                     &mut log_terminal_buffer.end_iter(),
@@ -521,7 +727,11 @@ pub fn apt_update_page(
                                 ));
                             }
                             false => {
-                                get_apt_upgrades(&get_upgradable_sender);
+                                get_apt_upgrades(
+                                    &get_upgradable_sender,
+                                    &get_kept_back_sender,
+                                    &system_health_sender,
+                                );
                                 //apt_update_dialog_child_box.set_visible(false);
                                 apt_update_dialog.set_extra_child(Some(
                                     &Image::builder()
@@ -568,6 +778,10 @@ pub fn apt_update_page(
         #[strong]
         update_button,
         #[strong]
+        security_update_button,
+        #[strong]
+        apt_filter_reload_action,
+        #[strong]
         packages_boxedlist,
         #[strong]
         packages_viewport,
@@ -580,9 +794,13 @@ pub fn apt_update_page(
         #[strong]
         apt_update_count,
         #[strong]
+        apt_security_update_count,
+        #[strong]
         flatpak_update_count,
         #[strong]
         theme_changed_action,
+        #[strong]
+        apt_prefetch_action,
         async move {
             while let Ok(state) = get_upgradable_receiver.recv().await {
                 viewport_bin.set_child(Some(&packages_viewport));
@@ -611,9 +829,9 @@ pub fn apt_update_page(
                             }
                             update_button
                                 .set_sensitive(!is_all_children_unmarked(&packages_boxedlist));
-                            excluded_updates_vec
-                                .borrow_mut()
-                                .retain(|x| x != &apt_row.package_name());
+                            excluded_updates_vec.borrow_mut().retain(|x| {
+                                !x.matches(&apt_row.package_name(), &apt_row.package_arch())
+                            });
                         }
                     ),
                 );
@@ -633,56 +851,38 @@ pub fn apt_update_page(
                             select_button.set_label(&t!("select_button_select_all").to_string());
                             update_button
                                 .set_sensitive(!is_all_children_unmarked(&packages_boxedlist));
-                            excluded_updates_vec
-                                .borrow_mut()
-                                .push(apt_row.package_name())
+                            excluded_updates_vec.borrow_mut().push(Exclusion::exact(
+                                &apt_row.package_name(),
+                                Some(&apt_row.package_arch()),
+                            ))
                         }
                     ),
                 );
                 packages_boxedlist.append(&apt_row);
                 (*apt_update_count.borrow_mut() += 1);
+                if state.update_class == UpdateClass::Security {
+                    (*apt_security_update_count.borrow_mut() += 1);
+                    security_update_button.set_sensitive(true);
+                }
                 if state.is_last {
                     packages_boxedlist.set_sensitive(true);
+                    apt_filter_reload_action.activate(None);
                     update_sys_tray.activate(Some(&glib::Variant::array_from_fixed_array(&[
                         *apt_update_count.borrow(),
                         *flatpak_update_count.borrow(),
                     ])));
+                    // Only does something when the background refresh asked for it
+                    apt_prefetch_action.activate(None);
                 }
             }
         }
     ));
 
-    searchbar.connect_search_changed(clone!(
-        #[weak]
-        searchbar,
-        #[weak]
-        packages_boxedlist,
-        move |_| {
-            let mut counter = packages_boxedlist.first_child();
-            while let Some(row) = counter {
-                if row.widget_name() == "AptPackageRow" {
-                    if !searchbar.text().is_empty() {
-                        if row
-                            .property::<String>("package-name")
-                            .to_lowercase()
-                            .contains(&searchbar.text().to_string().to_lowercase())
-                        {
-                            row.set_property("visible", true);
-                            searchbar.grab_focus();
-                        } else {
-                            row.set_property("visible", false);
-                        }
-                    } else {
-                        row.set_property("visible", true);
-                    }
-                }
-                counter = row.next_sibling();
-            }
-        }
-    ));
-
+    main_box.append(&system_health_banner);
     main_box.append(&searchbar);
+    main_box.append(&apt_update_filter_box);
     main_box.append(&viewport_bin);
+    main_box.append(&apt_kept_back_box);
     main_box.append(&bottom_bar);
 
     main_box
@@ -693,10 +893,11 @@ fn is_widget_select_all_ready(parent_listbox: &impl IsA<ListBox>) -> bool {
     let mut child_counter = parent_listbox.borrow().first_child();
     while let Some(child) = child_counter {
         let next_child = child.next_sibling();
-        let downcast = child.downcast::<AptPackageRow>().unwrap();
-        if !downcast.package_marked() {
-            is_ready = true;
-            break;
+        if let Ok(downcast) = child.downcast::<AptPackageRow>() {
+            if !downcast.package_marked() {
+                is_ready = true;
+                break;
+            }
         }
         child_counter = next_child
     }
@@ -708,10 +909,11 @@ fn is_all_children_unmarked(parent_listbox: &impl IsA<ListBox>) -> bool {
     let mut child_counter = parent_listbox.borrow().first_child();
     while let Some(child) = child_counter {
         let next_child = child.next_sibling();
-        let downcast = child.downcast::<AptPackageRow>().unwrap();
-        if downcast.package_marked() {
-            is_all_unmarked = false;
-            break;
+        if let Ok(downcast) = child.downcast::<AptPackageRow>() {
+            if downcast.package_marked() {
+                is_all_unmarked = false;
+                break;
+            }
         }
         child_counter = next_child
     }
@@ -722,52 +924,139 @@ fn set_all_apt_row_marks_to(parent_listbox: &impl IsA<ListBox>, value: bool) {
     let mut child_counter = parent_listbox.borrow().first_child();
     while let Some(child) = child_counter {
         let next_child = child.next_sibling();
-        let downcast = child.downcast::<AptPackageRow>().unwrap();
-        downcast.set_package_marked(value);
+        if let Ok(downcast) = child.downcast::<AptPackageRow>() {
+            downcast.set_package_marked(value);
+        }
         child_counter = next_child
     }
 }
 
-fn get_apt_upgrades(get_upgradable_sender: &async_channel::Sender<AptPackageSocket>) {
+fn set_apt_row_marks_by_update_class(
+    parent_listbox: &impl IsA<ListBox>,
+    update_class: UpdateClass,
+) {
+    let mut child_counter = parent_listbox.borrow().first_child();
+    while let Some(child) = child_counter {
+        let next_child = child.next_sibling();
+        if let Ok(downcast) = child.downcast::<AptPackageRow>() {
+            downcast.set_package_marked(downcast.package_update_class() == update_class.as_str());
+        }
+        child_counter = next_child
+    }
+}
+
+fn get_apt_upgrades(
+    get_upgradable_sender: &async_channel::Sender<AptPackageSocket>,
+    get_kept_back_sender: &async_channel::Sender<Vec<KeptBackPackage>>,
+    system_health_sender: &async_channel::Sender<SystemHealth>,
+) {
     let get_upgradable_sender = get_upgradable_sender.clone();
+    let get_kept_back_sender = get_kept_back_sender.clone();
+    let system_health_sender = system_health_sender.clone();
+    // libapt keeps back the phased updates this machine is not due for yet
+    PhasingPolicy::new(gio::Settings::new(APP_ID).boolean("include-phased-updates")).apply();
     thread::spawn(move || {
-        // Create upgradable list cache
-        let upgradable_cache = new_cache!().unwrap();
-        //
-        upgradable_cache.upgrade(Upgrade::FullUpgrade).unwrap();
-
-        upgradable_cache.resolve(true).unwrap();
-
-        let mut upgradeable_iter = upgradable_cache.get_changes(false).peekable();
-        while let Some(pkg) = upgradeable_iter.next() {
-            if !pkg.marked_delete() {
-                let candidate_version_pkg = pkg.candidate().unwrap();
-                let package_struct = AptPackageSocket {
-                    name: pkg.name().to_string(),
-                    arch: pkg.arch().to_string(),
-                    installed_version: match pkg.installed() {
-                        Some(t) => t.version().to_string(),
-                        _ => t!("installed_version_to_be_installed").to_string(),
-                    },
-                    candidate_version: candidate_version_pkg.version().to_string(),
-                    description: match candidate_version_pkg.description() {
-                        Some(s) => s,
-                        _ => t!("apt_pkg_property_unknown").to_string(),
-                    },
-                    source_uri: candidate_version_pkg
-                        .uris()
-                        .collect::<Vec<String>>()
-                        .join("\n"),
-                    maintainer: match candidate_version_pkg.get_record(RecordField::Maintainer) {
-                        Some(s) => s,
-                        _ => t!("apt_pkg_property_unknown").to_string(),
-                    },
-                    size: candidate_version_pkg.size(),
-                    installed_size: candidate_version_pkg.installed_size(),
-                    is_last: upgradeable_iter.peek().is_none(),
-                };
-                get_upgradable_sender.send_blocking(package_struct).unwrap()
-            }
+        // An interrupted upgrade makes the listing below fail, say why first
+        system_health_sender
+            .send_blocking(SystemHealth::check())
+            .unwrap();
+        for package_struct in get_apt_upgradable_packages() {
+            get_upgradable_sender.send_blocking(package_struct).unwrap()
+        }
+        // Every kept-back package needs a trial resolve, so this comes after the list
+        match kept_back_packages(&RustAptBackend, &UpdateHolds::load()) {
+            Ok(t) => get_kept_back_sender.send_blocking(t).unwrap(),
+            Err(e) => eprintln!("{}", process::apt_helper_error_text(e.kind, &e.message)),
         }
     });
 }
+
+pub fn get_apt_upgradable_packages() -> Vec<AptPackageSocket> {
+    let upgradable_packages = match upgradable_packages(&RustAptBackend, &UpdateHolds::load()) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("{}", process::apt_helper_error_text(e.kind, &e.message));
+            return Vec::new();
+        }
+    };
+    let mut upgradeable_iter = upgradable_packages.iter().peekable();
+    let mut package_structs = Vec::new();
+    while let Some(change) = upgradeable_iter.next() {
+        package_structs.push(apt_package_socket(
+            change,
+            upgradeable_iter.peek().is_none(),
+        ));
+    }
+    package_structs
+}
+
+/// Everything the health check found, one paragraph per problem.
+fn system_health_text(system_health: &SystemHealth) -> String {
+    let mut paragraphs = Vec::new();
+    if system_health.dpkg_interrupted {
+        paragraphs.push(t!("system_health_dpkg_interrupted").to_string());
+    }
+    if !system_health.unfinished_packages.is_empty() {
+        paragraphs.push(
+            strfmt::strfmt(
+                &t!("system_health_unfinished_packages").to_string(),
+                &std::collections::HashMap::from([(
+                    "PACKAGES".to_string(),
+                    system_health.unfinished_packages.join(", "),
+                )]),
+            )
+            .unwrap(),
+        );
+    }
+    if !system_health.broken_packages.is_empty() {
+        paragraphs.push(
+            strfmt::strfmt(
+                &t!("system_health_broken_packages").to_string(),
+                &std::collections::HashMap::from([(
+                    "PACKAGES".to_string(),
+                    system_health.broken_packages.join(", "),
+                )]),
+            )
+            .unwrap(),
+        );
+    }
+    if let Some(cache_error) = &system_health.cache_error {
+        paragraphs.push(cache_error.to_owned());
+    }
+    paragraphs.push(t!("system_repair_dialog_body").to_string());
+    paragraphs.join("\n\n")
+}
+
+/// Row data for an upgradable package, with translated fallbacks for the missing bits.
+pub fn apt_package_socket(change: &AptChange, is_last: bool) -> AptPackageSocket {
+    let unknown = |value: &Option<String>| -> String {
+        match value {
+            Some(s) => s.to_owned(),
+            _ => t!("apt_pkg_property_unknown").to_string(),
+        }
+    };
+    AptPackageSocket {
+        name: change.name.to_owned(),
+        arch: change.arch.to_owned(),
+        source_name: change.source_name.to_owned(),
+        new_install: change.installed_version.is_none(),
+        installed_version: match &change.installed_version {
+            Some(t) => t.to_owned(),
+            _ => t!("installed_version_to_be_installed").to_string(),
+        },
+        candidate_version: unknown(&change.candidate_version),
+        description: unknown(&change.description),
+        source_uri: change.source_uri.to_owned(),
+        origin: unknown(&change.origin),
+        archive: unknown(&change.archive),
+        label: unknown(&change.label),
+        update_class: change.update_class,
+        maintainer: unknown(&change.maintainer),
+        size: change.download_size,
+        installed_size: change.installed_size_after,
+        installed_size_delta: change.installed_size_after as i64
+            - change.installed_size_before as i64,
+        phased_update_percentage: change.phased_update_percentage.unwrap_or(100) as u32,
+        is_last,
+    }
+}
@@ -2,65 +2,54 @@ use adw::gio::SimpleAction;
 use adw::prelude::*;
 use gtk::glib::*;
 use gtk::*;
-use pika_unixsocket_tools::pika_unixsocket_tools::{
-    start_socket_server, start_socket_server_no_log,
+use pika_unixsocket_tools::apt_helper_args::{
+    apt_full_upgrade_path, APT_FULL_UPGRADE_ACTION_ID, APT_FULL_UPGRADE_PATH,
 };
+use pika_unixsocket_tools::apt_helper_error::AptHelperErrorKind;
+use pika_unixsocket_tools::change_explanation::{ChangeExplanation, ExplainedChange, RelationKind};
+use pika_unixsocket_tools::offline_upgrade::OfflineUpgradeResult;
+use pika_unixsocket_tools::phased_updates::PhasingPolicy;
+use pika_unixsocket_tools::pika_unixsocket_tools::start_interactive_socket_server;
+use pika_unixsocket_tools::progress_protocol::{
+    ChangeAction, ConflictAnswer, ProgressMessage, ProgressReply,
+};
+use pika_unixsocket_tools::session_socket::{
+    bind_progress_socket, create_session_socket_dir, remove_session_socket_dir, PeerCredPolicy,
+};
+use pika_unixsocket_tools::update_discovery::{
+    simulate_full_upgrade, KeptBackPackage, KeptBackReason, RustAptBackend,
+};
+use pika_unixsocket_tools::update_holds::{Exclusion, ExclusionsFile, ForcedUpgrade, UpdateHolds};
 use pretty_bytes::converter::convert;
-use rust_apt::cache::Upgrade;
-use rust_apt::new_cache;
-use serde::Serialize;
-use serde_json::Value;
 use std::cell::RefCell;
-use std::path::Path;
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::rc::Rc;
 use std::thread;
 use tokio::runtime::Runtime;
 
+use super::debconf_dialog::apt_debconf_prompt;
 use crate::build_ui::{create_color_badge, get_current_font};
-
-struct AptChangesInfo {
-    package_count_upgrade: u64,
-    package_count_install: u64,
-    package_count_downgrade: u64,
-    package_count_remove: u64,
-    total_download_size: u64,
-    total_installed_size: i64,
-}
-#[derive(Serialize)]
-struct Exclusions {
-    exclusions: Vec<Value>,
-}
-
-impl AptChangesInfo {
-    fn add_upgrade(&mut self) {
-        self.package_count_upgrade += 1;
-    }
-    fn add_install(&mut self) {
-        self.package_count_install += 1;
-    }
-    fn add_downgrade(&mut self) {
-        self.package_count_downgrade += 1;
-    }
-    fn add_remove(&mut self) {
-        self.package_count_remove += 1;
-    }
-
-    fn increase_total_download_size_by(&mut self, value: u64) {
-        self.total_download_size += value;
-    }
-
-    fn increase_total_installed_size_by(&mut self, value: u64) {
-        self.total_installed_size += value as i64;
-    }
-
-    fn decrease_total_installed_size_by(&mut self, value: u64) {
-        self.total_installed_size -= value as i64;
-    }
+use crate::config::{kernels_to_keep_setting, APP_ID};
+
+/// What the helper is started for once the user agreed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AptUpgradeMode {
+    Install,
+    /// Only download now, systemd installs it on the next boot.
+    StageOffline,
+    /// Finish an interrupted run and fix broken dependencies, nothing gets upgraded.
+    Repair,
+    /// Only remove what the cleanup page selected, nothing gets upgraded.
+    Cleanup {
+        autoremove: bool,
+        kernels_to_keep: Option<u32>,
+        clean_cache: bool,
+    },
 }
 
 pub fn apt_process_update(
-    excluded_updates_vec: &Vec<String>,
+    excluded_updates_vec: &Vec<Exclusion>,
+    forced_upgrades_vec: &Vec<ForcedUpgrade>,
     window: adw::ApplicationWindow,
     retry_signal_action: &SimpleAction,
     flatpak_update_button: &Button,
@@ -101,6 +90,8 @@ pub fn apt_process_update(
         #[strong]
         excluded_updates_vec,
         #[strong]
+        forced_upgrades_vec,
+        #[strong]
         theme_changed_action,
         #[strong]
         initiated_by_main,
@@ -108,6 +99,7 @@ pub fn apt_process_update(
         flatpak_update_button,
         move |_, _| apt_confirm_window(
             &excluded_updates_vec,
+            &forced_upgrades_vec,
             window,
             &retry_signal_action,
             &flatpak_update_button,
@@ -127,86 +119,64 @@ pub fn apt_process_update(
     }
 }
 
+/// Goes straight to the confirmation, leaving everything but security updates out is the point.
+pub fn apt_process_security_update(
+    excluded_updates_vec: &Vec<Exclusion>,
+    forced_upgrades_vec: &Vec<ForcedUpgrade>,
+    window: adw::ApplicationWindow,
+    retry_signal_action: &SimpleAction,
+    flatpak_update_button: &Button,
+    initiated_by_main: Rc<RefCell<bool>>,
+    theme_changed_action: &SimpleAction,
+) {
+    apt_confirm_window(
+        excluded_updates_vec,
+        forced_upgrades_vec,
+        window,
+        retry_signal_action,
+        flatpak_update_button,
+        initiated_by_main,
+        theme_changed_action,
+    )
+}
+
 fn apt_confirm_window(
-    excluded_updates_vec: &Vec<String>,
+    excluded_updates_vec: &Vec<Exclusion>,
+    forced_upgrades_vec: &Vec<ForcedUpgrade>,
     window: adw::ApplicationWindow,
     retry_signal_action: &SimpleAction,
     flatpak_update_button: &Button,
     initiated_by_main: Rc<RefCell<bool>>,
     theme_changed_action: &SimpleAction,
 ) {
-    let to_be_removed_packages_vec: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(Vec::new()));
     // Emulate Apt Full Upgrade to get transaction info
-    let mut apt_changes_struct = AptChangesInfo {
-        package_count_upgrade: 0,
-        package_count_install: 0,
-        package_count_downgrade: 0,
-        package_count_remove: 0,
-        total_download_size: 0,
-        total_installed_size: 0,
-    };
-
-    let apt_cache = new_cache!().unwrap();
-    let apt_upgrade_cache = new_cache!().unwrap();
-
-    apt_cache.upgrade(Upgrade::FullUpgrade).unwrap();
-
-    for change in apt_cache.get_changes(false) {
-        if !excluded_updates_vec
-            .iter()
-            .any(|e| change.name().contains(e))
-        {
-            let pkg = apt_upgrade_cache.get(change.name()).unwrap();
-            if change.marked_upgrade() || change.marked_install() || change.marked_downgrade() {
-                pkg.mark_install(true, false);
-            } else if change.marked_delete() {
-                pkg.mark_delete(false);
-                to_be_removed_packages_vec
-                    .borrow_mut()
-                    .push(pkg.name().to_owned());
-            }
-            pkg.protect();
+    PhasingPolicy::new(gio::Settings::new(APP_ID).boolean("include-phased-updates")).apply();
+    let apt_changes_struct = match simulate_full_upgrade(
+        &RustAptBackend,
+        excluded_updates_vec,
+        forced_upgrades_vec,
+        &UpdateHolds::load(),
+        kernels_to_keep_setting(),
+    ) {
+        Ok(t) => t,
+        Err(e) => {
+            apt_error_dialog(&window, &apt_helper_error_text(e.kind, &e.message));
+            return;
         }
-    }
-
-    apt_upgrade_cache.resolve(true).unwrap();
+    };
+    let to_be_removed_packages_vec: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(
+        apt_changes_struct.to_be_removed_packages.clone(),
+    ));
 
     println!("{}", t!("gui_changes_emu_msg_0"));
-    for change in apt_upgrade_cache.get_changes(false) {
-        if change.is_installed() {
-            apt_changes_struct
-                .decrease_total_installed_size_by(change.installed().unwrap().installed_size());
-        }
-        if change.marked_upgrade() && change.is_installed() {
-            println!("{}: {}", t!("gui_changes_emu_msg_upgrading"), change.name());
-            apt_changes_struct.add_upgrade();
-            apt_changes_struct.increase_total_download_size_by(change.candidate().unwrap().size());
-            apt_changes_struct
-                .increase_total_installed_size_by(change.candidate().unwrap().installed_size());
-        } else if change.marked_install() || change.marked_upgrade() && !change.is_installed() {
-            println!(
-                "{}: {}",
-                t!("gui_changes_emu_msg_installing"),
-                change.name()
-            );
-            apt_changes_struct.add_install();
-            apt_changes_struct.increase_total_download_size_by(change.candidate().unwrap().size());
-            apt_changes_struct
-                .increase_total_installed_size_by(change.candidate().unwrap().installed_size());
-        } else if change.marked_downgrade() {
-            println!(
-                "{}: {}",
-                t!("gui_changes_emu_msg_downgrading"),
-                change.name()
-            );
-            apt_changes_struct.add_downgrade();
-            apt_changes_struct.increase_total_download_size_by(change.candidate().unwrap().size());
-            apt_changes_struct
-                .increase_total_installed_size_by(change.candidate().unwrap().installed_size());
-        } else if change.marked_delete() {
-            println!("{}: {}", t!("gui_changes_emu_msg_removing"), change.name());
-            apt_changes_struct.add_remove();
-        }
+    for (action, name) in &apt_changes_struct.changes {
+        let action_label = match action {
+            ChangeAction::Upgrade => t!("gui_changes_emu_msg_upgrading"),
+            ChangeAction::Install => t!("gui_changes_emu_msg_installing"),
+            ChangeAction::Downgrade => t!("gui_changes_emu_msg_downgrading"),
+            ChangeAction::Remove => t!("gui_changes_emu_msg_removing"),
+        };
+        println!("{}: {}", action_label, name);
     }
 
     let apt_confirm_dialog_child_box = Box::builder().orientation(Orientation::Vertical).build();
@@ -255,6 +225,16 @@ fn apt_confirm_window(
         &apt_update_dialog_badges_size_group1,
     ));
 
+    apt_confirm_dialog_child_box.append(&create_color_badge(
+        &t!("package_count_security_badge_label"),
+        &apt_changes_struct.package_count_security.to_string(),
+        "background-accent-bg",
+        &theme_changed_action,
+        &apt_update_dialog_badges_size_group,
+        &apt_update_dialog_badges_size_group0,
+        &apt_update_dialog_badges_size_group1,
+    ));
+
     apt_confirm_dialog_child_box.append(&create_color_badge(
         &t!("total_download_size_badge_label"),
         &convert(apt_changes_struct.total_download_size as f64),
@@ -265,6 +245,26 @@ fn apt_confirm_window(
         &apt_update_dialog_badges_size_group1,
     ));
 
+    // A background download already fetched everything
+    if apt_changes_struct.is_ready_to_install() {
+        apt_confirm_dialog_child_box.append(&create_color_badge(
+            &t!("ready_to_install_badge_label"),
+            &strfmt::strfmt(
+                &t!("ready_to_install_badge_text").to_string(),
+                &std::collections::HashMap::from([(
+                    "SIZE".to_string(),
+                    convert(apt_changes_struct.total_download_size as f64),
+                )]),
+            )
+            .unwrap(),
+            "background-accent-bg",
+            &theme_changed_action,
+            &apt_update_dialog_badges_size_group,
+            &apt_update_dialog_badges_size_group0,
+            &apt_update_dialog_badges_size_group1,
+        ));
+    }
+
     apt_confirm_dialog_child_box.append(&create_color_badge(
         &t!("total_installed_size_badge_label"),
         &convert(apt_changes_struct.total_installed_size as f64),
@@ -275,6 +275,67 @@ fn apt_confirm_window(
         &apt_update_dialog_badges_size_group1,
     ));
 
+    if !apt_changes_struct.held_back_packages.is_empty() {
+        let apt_held_back_label = Label::builder()
+            .label(t!("apt_held_back_label"))
+            .wrap(true)
+            .margin_top(10)
+            .build();
+        let apt_held_back_text_view = TextView::builder()
+            .buffer(
+                &TextBuffer::builder()
+                    .text(apt_changes_struct.held_back_packages.join("\n"))
+                    .build(),
+            )
+            .hexpand(true)
+            .editable(false)
+            .build();
+        let apt_held_back_text_viewport = gtk::ScrolledWindow::builder()
+            .hexpand(true)
+            .has_frame(true)
+            .height_request(100)
+            .hscrollbar_policy(PolicyType::Never)
+            .child(&apt_held_back_text_view)
+            .build();
+        apt_held_back_text_viewport.add_css_class("round-all-scroll");
+        apt_confirm_dialog_child_box.append(&apt_held_back_label);
+        apt_confirm_dialog_child_box.append(&apt_held_back_text_viewport);
+    }
+
+    if !apt_changes_struct.explanations.is_empty() {
+        let apt_why_text_view = TextView::builder()
+            .buffer(
+                &TextBuffer::builder()
+                    .text(
+                        apt_changes_struct
+                            .explanations
+                            .iter()
+                            .map(change_explanation_text)
+                            .collect::<Vec<String>>()
+                            .join("\n"),
+                    )
+                    .build(),
+            )
+            .hexpand(true)
+            .editable(false)
+            .wrap_mode(WrapMode::WordChar)
+            .build();
+        let apt_why_text_viewport = gtk::ScrolledWindow::builder()
+            .hexpand(true)
+            .has_frame(true)
+            .height_request(150)
+            .hscrollbar_policy(PolicyType::Never)
+            .child(&apt_why_text_view)
+            .build();
+        apt_why_text_viewport.add_css_class("round-all-scroll");
+        let apt_why_expander = Expander::builder()
+            .label(t!("apt_why_expander_label"))
+            .margin_top(10)
+            .child(&apt_why_text_viewport)
+            .build();
+        apt_confirm_dialog_child_box.append(&apt_why_expander);
+    }
+
     let apt_confirm_dialog = adw::MessageDialog::builder()
         .transient_for(&window)
         .heading(t!("apt_confirm_dialog_heading"))
@@ -287,6 +348,11 @@ fn apt_confirm_window(
         &t!("apt_confirm_dialog_cancel_label").to_string(),
     );
 
+    apt_confirm_dialog.add_response(
+        "apt_confirm_dialog_offline",
+        &t!("apt_confirm_dialog_offline_label").to_string(),
+    );
+
     apt_confirm_dialog.add_response(
         "apt_confirm_dialog_confirm",
         &t!("apt_confirm_dialog_confirm_label").to_string(),
@@ -300,28 +366,6 @@ fn apt_confirm_window(
     apt_confirm_dialog.set_default_response(Some("apt_confirm_dialog_confirm"));
     apt_confirm_dialog.set_close_response("apt_confirm_dialog_cancel");
 
-    let json_file_path = "/tmp/pika-apt-exclusions.json";
-
-    if Path::new(json_file_path).exists() {
-        std::fs::remove_file(json_file_path).expect("Failed to remove old json file");
-    }
-
-    if !excluded_updates_vec.is_empty() {
-        let exclusions_array = Exclusions {
-            exclusions: excluded_updates_vec
-                .into_iter()
-                .map(|i| serde_json::from_str(format!("{{\"package\":\"{}\"}}", i).as_str()))
-                .collect::<Result<Vec<Value>, _>>()
-                .unwrap(),
-        };
-
-        std::fs::write(
-            json_file_path,
-            serde_json::to_string_pretty(&exclusions_array).unwrap(),
-        )
-        .expect("Failed to write to json file");
-    }
-
     let apt_confirm_start_signal_action = SimpleAction::new("apt_confirm_start", None);
 
     apt_confirm_start_signal_action.connect_activate(clone!(
@@ -337,7 +381,13 @@ fn apt_confirm_window(
         flatpak_update_button,
         #[strong]
         initiated_by_main,
+        #[strong]
+        excluded_updates_vec,
+        #[strong]
+        forced_upgrades_vec,
         move |_, _| {
+            let excluded_updates_vec0 = excluded_updates_vec.clone();
+            let forced_upgrades_vec0 = forced_upgrades_vec.clone();
             let retry_signal_action0 = retry_signal_action.clone();
             let theme_changed_action0 = theme_changed_action.clone();
             let flatpak_update_button0 = flatpak_update_button.clone();
@@ -345,8 +395,17 @@ fn apt_confirm_window(
             apt_confirm_dialog
                 .clone()
                 .choose(None::<&gio::Cancellable>, move |choice| {
-                    if choice == "apt_confirm_dialog_confirm" {
+                    if choice == "apt_confirm_dialog_confirm"
+                        || choice == "apt_confirm_dialog_offline"
+                    {
                         apt_full_upgrade_from_socket(
+                            &excluded_updates_vec0,
+                            &forced_upgrades_vec0,
+                            if choice == "apt_confirm_dialog_offline" {
+                                AptUpgradeMode::StageOffline
+                            } else {
+                                AptUpgradeMode::Install
+                            },
                             window,
                             &retry_signal_action0,
                             &flatpak_update_button0,
@@ -366,7 +425,16 @@ fn apt_confirm_window(
             .text(
                 to_be_removed_packages_borrow
                     .iter()
-                    .map(|x| x.to_string() + "\n")
+                    .map(|x| {
+                        match apt_changes_struct
+                            .explanations
+                            .iter()
+                            .find(|t| t.package == *x && t.change == ExplainedChange::Remove)
+                        {
+                            Some(explanation) => change_explanation_text(explanation) + "\n",
+                            None => x.to_string() + "\n",
+                        }
+                    })
                     .collect::<String>()
                     + "\n",
             )
@@ -421,20 +489,69 @@ fn apt_confirm_window(
     }
 }
 
+/// Runs the repair straight away, the broken state is what the user confirmed.
+pub fn apt_process_repair(
+    window: adw::ApplicationWindow,
+    retry_signal_action: &SimpleAction,
+    flatpak_update_button: &Button,
+    initiated_by_main: Rc<RefCell<bool>>,
+    theme_changed_action: &SimpleAction,
+) {
+    apt_full_upgrade_from_socket(
+        &Vec::new(),
+        &Vec::new(),
+        AptUpgradeMode::Repair,
+        window,
+        retry_signal_action,
+        flatpak_update_button,
+        initiated_by_main,
+        theme_changed_action,
+    )
+}
+
+/// Removes what the user picked on the cleanup page, they already confirmed it there.
+pub fn apt_process_cleanup(
+    autoremove: bool,
+    kernels_to_keep: Option<u32>,
+    clean_cache: bool,
+    window: adw::ApplicationWindow,
+    retry_signal_action: &SimpleAction,
+    flatpak_update_button: &Button,
+    initiated_by_main: Rc<RefCell<bool>>,
+    theme_changed_action: &SimpleAction,
+) {
+    apt_full_upgrade_from_socket(
+        &Vec::new(),
+        &Vec::new(),
+        AptUpgradeMode::Cleanup {
+            autoremove,
+            kernels_to_keep,
+            clean_cache,
+        },
+        window,
+        retry_signal_action,
+        flatpak_update_button,
+        initiated_by_main,
+        theme_changed_action,
+    )
+}
+
 fn apt_full_upgrade_from_socket(
+    excluded_updates_vec: &Vec<Exclusion>,
+    forced_upgrades_vec: &Vec<ForcedUpgrade>,
+    mode: AptUpgradeMode,
     window: adw::ApplicationWindow,
     retry_signal_action: &SimpleAction,
     flatpak_update_button: &Button,
     initiated_by_main: Rc<RefCell<bool>>,
     theme_changed_action: &SimpleAction,
 ) {
-    let (upgrade_percent_sender, upgrade_percent_receiver) = async_channel::unbounded::<String>();
-    let upgrade_percent_sender = upgrade_percent_sender.clone();
-    let (upgrade_speed_sender, upgrade_speed_receiver) = async_channel::unbounded::<String>();
-    let upgrade_speed_sender = upgrade_speed_sender.clone();
-    let (upgrade_status_sender, upgrade_status_receiver) = async_channel::unbounded::<String>();
+    let (upgrade_status_sender, upgrade_status_receiver) =
+        async_channel::unbounded::<ProgressMessage>();
     let upgrade_status_sender = upgrade_status_sender.clone();
     let upgrade_status_sender_clone0 = upgrade_status_sender.clone();
+    let (upgrade_reply_sender, upgrade_reply_receiver) =
+        async_channel::unbounded::<ProgressReply>();
 
     let log_file_path = format!(
         "/tmp/pika-apt-upgrade_{}.log",
@@ -442,56 +559,141 @@ fn apt_full_upgrade_from_socket(
     );
     let log_file_path_clone0 = log_file_path.clone();
 
-    thread::spawn(move || {
-        Runtime::new().unwrap().block_on(start_socket_server_no_log(
-            upgrade_percent_sender,
-            "/tmp/pika_apt_upgrade_percent.sock",
-        ));
-    });
-
-    thread::spawn(move || {
-        Runtime::new().unwrap().block_on(start_socket_server_no_log(
-            upgrade_speed_sender,
-            "/tmp/pika_apt_upgrade_speed.sock",
-        ));
-    });
+    let exclusions_file = ExclusionsFile::new(excluded_updates_vec, forced_upgrades_vec);
+    // A repair and a cleanup upgrade nothing the exclusions, phasing or a snapshot could
+    // apply to
+    let upgrades = matches!(mode, AptUpgradeMode::Install | AptUpgradeMode::StageOffline);
+    let has_exclusions = upgrades && !exclusions_file.is_empty();
+    let include_phased_updates =
+        upgrades && gio::Settings::new(APP_ID).boolean("include-phased-updates");
+    let pre_upgrade_snapshot = Some(gio::Settings::new(APP_ID).string("pre-upgrade-snapshot"))
+        .filter(|t| upgrades && t.as_str() != "none");
+    // Upgrades apply the kernel policy on their own, a cleanup only if the user picked it
+    let kernels_to_keep = match mode {
+        AptUpgradeMode::Install | AptUpgradeMode::StageOffline => kernels_to_keep_setting(),
+        AptUpgradeMode::Repair => None,
+        AptUpgradeMode::Cleanup {
+            kernels_to_keep, ..
+        } => kernels_to_keep,
+    };
 
     thread::spawn(move || {
-        Runtime::new().unwrap().block_on(start_socket_server(
-            upgrade_status_sender,
-            "/tmp/pika_apt_upgrade_status.sock",
-            &log_file_path,
-        ));
-    });
+        // Private per-run socket directory, the root helper only trusts sockets in here
+        let (socket_dir, socket_listener) =
+            match create_session_socket_dir().and_then(|t| match bind_progress_socket(&t) {
+                Ok(socket_listener) => Ok((t, socket_listener)),
+                Err(e) => {
+                    remove_session_socket_dir(&t);
+                    Err(e)
+                }
+            }) {
+                Ok(t) => t,
+                Err(e) => {
+                    send_setup_error(&upgrade_status_sender_clone0, e.to_string());
+                    return;
+                }
+            };
+        // Only we can write in the session directory, so the helper can trust this file
+        let exclusions_file_path = socket_dir.join("exclusions.json");
+        if has_exclusions {
+            if let Err(e) = exclusions_file.write_to(&exclusions_file_path) {
+                remove_session_socket_dir(&socket_dir);
+                send_setup_error(&upgrade_status_sender_clone0, e.to_string());
+                return;
+            }
+        }
 
-    thread::spawn(move || {
-        let current_locale = match std::env::var_os("LANG") {
-            Some(v) => v
-                .into_string()
-                .unwrap()
-                .chars()
-                .take_while(|&ch| ch != '.')
-                .collect::<String>(),
-            None => panic!("$LANG is not set"),
+        let mut helper_args: Vec<String> = vec![
+            "--locale".to_owned(),
+            rust_i18n::locale().to_string(),
+            "--socket-dir".to_owned(),
+            socket_dir.to_string_lossy().to_string(),
+            // The user already confirmed the transaction in our own dialog
+            "--assume-yes".to_owned(),
+        ];
+        if has_exclusions {
+            helper_args.push("--exclusions-file".to_owned());
+            helper_args.push(exclusions_file_path.to_string_lossy().to_string());
+        }
+        if include_phased_updates {
+            helper_args.push("--include-phased-updates".to_owned());
+        }
+        if let Some(pre_upgrade_snapshot) = pre_upgrade_snapshot {
+            helper_args.push("--snapshot".to_owned());
+            helper_args.push(pre_upgrade_snapshot.to_string());
+        }
+        if let Some(kernels_to_keep) = kernels_to_keep {
+            helper_args.push("--remove-old-kernels".to_owned());
+            helper_args.push(kernels_to_keep.to_string());
+        }
+        match mode {
+            AptUpgradeMode::Install => {}
+            AptUpgradeMode::StageOffline => {
+                helper_args.push("--stage-offline".to_owned());
+            }
+            AptUpgradeMode::Repair => {
+                helper_args.push("--repair".to_owned());
+            }
+            AptUpgradeMode::Cleanup {
+                autoremove,
+                clean_cache,
+                ..
+            } => {
+                helper_args.push("--cleanup".to_owned());
+                if autoremove {
+                    helper_args.push("--autoremove".to_owned());
+                }
+                if clean_cache {
+                    helper_args.push("--clean-cache".to_owned());
+                }
+            }
+        }
+        let mut apt_upgrade_command = Command::new("pkexec");
+        apt_upgrade_command
+            .arg(apt_full_upgrade_path(&helper_args))
+            .args(&helper_args);
+        let mut apt_upgrade_child = match apt_upgrade_command.spawn() {
+            Ok(t) => t,
+            Err(e) => {
+                remove_session_socket_dir(&socket_dir);
+                send_setup_error(&upgrade_status_sender_clone0, e.to_string());
+                return;
+            }
         };
-        let apt_upgrade_command = Command::new("pkexec")
-            .args([
-                "/usr/lib/pika/pikman-update-manager/scripts/apt_full_upgrade",
-                &current_locale,
-            ])
-            .status()
-            .unwrap();
-        match apt_upgrade_command.code().unwrap() {
-            0 => upgrade_status_sender_clone0
-                .send_blocking("FN_OVERRIDE_SUCCESSFUL".to_owned())
+        // Only the helper we just started (running as root) may report progress
+        let peer_policy = PeerCredPolicy::new(vec![0]).with_ancestor_pid(apt_upgrade_child.id());
+        thread::spawn(move || {
+            Runtime::new()
+                .unwrap()
+                .block_on(start_interactive_socket_server(
+                    upgrade_status_sender,
+                    upgrade_reply_receiver,
+                    socket_listener,
+                    &log_file_path,
+                    peer_policy,
+                ));
+        });
+        let apt_upgrade_command = apt_upgrade_child.wait().unwrap();
+        remove_session_socket_dir(&socket_dir);
+        // The helper reports its own errors, only failures it could not see are sent here
+        match apt_upgrade_command.code() {
+            Some(0) => upgrade_status_sender_clone0
+                .send_blocking(ProgressMessage::Finished { exit_code: 0 })
                 .unwrap(),
-            53 => {}
-            _ => {
+            Some(code)
+                if AptHelperErrorKind::from_exit_code(code)
+                    .is_some_and(|kind| kind.is_reported_by_helper()) => {}
+            code => {
+                let code = code.unwrap_or(1);
                 upgrade_status_sender_clone0
-                    .send_blocking(t!("upgrade_status_error_perms").to_string())
+                    .send_blocking(ProgressMessage::Error {
+                        error_kind: AptHelperErrorKind::from_exit_code(code)
+                            .unwrap_or(AptHelperErrorKind::Unknown),
+                        message: String::new(),
+                    })
                     .unwrap();
                 upgrade_status_sender_clone0
-                    .send_blocking("FN_OVERRIDE_FAILED".to_owned())
+                    .send_blocking(ProgressMessage::Finished { exit_code: code })
                     .unwrap()
             }
         }
@@ -562,7 +764,11 @@ fn apt_full_upgrade_from_socket(
     let apt_upgrade_dialog = adw::MessageDialog::builder()
         .transient_for(&window)
         .extra_child(&apt_upgrade_dialog_child_box)
-        .heading(t!("apt_upgrade_dialog_heading"))
+        .heading(match mode {
+            AptUpgradeMode::Repair => t!("apt_repair_dialog_heading"),
+            AptUpgradeMode::Cleanup { .. } => t!("apt_cleanup_dialog_heading"),
+            _ => t!("apt_upgrade_dialog_heading"),
+        })
         .width_request(500)
         .build();
 
@@ -571,6 +777,18 @@ fn apt_full_upgrade_from_socket(
         &t!("apt_upgrade_dialog_ok_label").to_string(),
     );
 
+    if mode == AptUpgradeMode::StageOffline {
+        apt_upgrade_dialog.add_response(
+            "apt_upgrade_dialog_reboot",
+            &t!("apt_upgrade_dialog_reboot_label").to_string(),
+        );
+        apt_upgrade_dialog.set_response_appearance(
+            "apt_upgrade_dialog_reboot",
+            adw::ResponseAppearance::Suggested,
+        );
+        apt_upgrade_dialog.set_response_enabled("apt_upgrade_dialog_reboot", false);
+    }
+
     let apt_upgrade_dialog_child_box_done =
         Box::builder().orientation(Orientation::Vertical).build();
 
@@ -594,61 +812,72 @@ fn apt_full_upgrade_from_socket(
     apt_upgrade_dialog.set_response_enabled("apt_upgrade_dialog_ok", false);
     apt_upgrade_dialog.set_close_response("apt_upgrade_dialog_ok");
 
-    let upgrade_percent_server_context = MainContext::default();
-    // The main loop executes the asynchronous block
-    upgrade_percent_server_context.spawn_local(clone!(
-        #[weak]
-        apt_upgrade_dialog_progress_bar,
-        async move {
-            while let Ok(state) = upgrade_percent_receiver.recv().await {
-                match state.as_ref() {
-                    "FN_OVERRIDE_SUCCESSFUL" => {}
-                    _ => match state.parse::<f64>() {
-                        Ok(p) => apt_upgrade_dialog_progress_bar.set_fraction(p / 100.0),
-                        Err(_) => {}
-                    },
-                }
-            }
-        }
-    ));
-
-    let upgrade_speed_server_context = MainContext::default();
-    // The main loop executes the asynchronous block
-    upgrade_speed_server_context.spawn_local(clone!(
-        #[weak]
-        apt_speed_label,
-        async move {
-            while let Ok(state) = upgrade_speed_receiver.recv().await {
-                apt_speed_label.set_label(&state);
-            }
-        }
-    ));
-
     let upgrade_status_server_context = MainContext::default();
     // The main loop executes the asynchronous block
     upgrade_status_server_context.spawn_local(clone!(
+        #[strong]
+        window,
         #[weak]
         apt_upgrade_dialog,
         #[weak]
         apt_upgrade_dialog_child_box,
+        #[weak]
+        apt_upgrade_dialog_progress_bar,
+        #[weak]
+        apt_speed_label,
         #[strong]
         apt_upgrade_dialog_child_box_done,
         #[strong]
         apt_upgrade_log_image,
         async move {
             while let Ok(state) = upgrade_status_receiver.recv().await {
-                match state.as_ref() {
-                    "FN_OVERRIDE_SUCCESSFUL" => {
+                match state {
+                    ProgressMessage::Percent { percent } => {
+                        apt_upgrade_dialog_progress_bar.set_fraction(percent as f64 / 100.0)
+                    }
+                    ProgressMessage::Speed { speed } => apt_speed_label.set_label(&speed),
+                    ProgressMessage::Status { message }
+                    | ProgressMessage::ItemHit { message }
+                    | ProgressMessage::ItemFetch { message }
+                    | ProgressMessage::ItemDone { message }
+                    | ProgressMessage::ItemFail { message } => {
+                        apt_upgrade_dialog.set_body(&message)
+                    }
+                    ProgressMessage::PackageError { package, error } => apt_upgrade_dialog
+                        .set_body(
+                            &strfmt::strfmt(
+                                &t!("apt_install_str_error").to_string(),
+                                &std::collections::HashMap::from([
+                                    ("PKGNAME".to_string(), package),
+                                    ("ERROR".to_string(), error),
+                                ]),
+                            )
+                            .unwrap(),
+                        ),
+                    ProgressMessage::Error {
+                        error_kind,
+                        message,
+                    } => apt_upgrade_dialog.set_body(&apt_helper_error_text(error_kind, &message)),
+                    ProgressMessage::Finished { exit_code: 0 } => {
                         apt_upgrade_dialog_child_box.set_visible(false);
                         apt_upgrade_log_image.set_icon_name(Some("face-cool-symbolic"));
                         apt_upgrade_dialog
                             .set_extra_child(Some(&apt_upgrade_dialog_child_box_done));
-                        apt_upgrade_dialog.set_title(Some(
-                            &t!("apt_upgrade_dialog_status_successful").to_string(),
-                        ));
+                        if mode == AptUpgradeMode::StageOffline {
+                            apt_upgrade_dialog.set_title(Some(
+                                &t!("apt_upgrade_dialog_status_staged").to_string(),
+                            ));
+                            apt_upgrade_dialog
+                                .set_response_enabled("apt_upgrade_dialog_reboot", true);
+                        } else {
+                            apt_upgrade_dialog.set_title(Some(
+                                &t!("apt_upgrade_dialog_status_successful").to_string(),
+                            ));
+                        }
                         apt_upgrade_dialog.set_response_enabled("apt_upgrade_dialog_ok", true);
+                        break;
                     }
-                    "FN_OVERRIDE_FAILED" => {
+                    ProgressMessage::Finished { .. } => {
                         apt_upgrade_dialog_child_box.set_visible(false);
                         apt_upgrade_log_image.set_icon_name(Some("dialog-error-symbolic"));
                         apt_upgrade_dialog
@@ -658,8 +887,20 @@ fn apt_full_upgrade_from_socket(
                         apt_upgrade_dialog.set_response_enabled("apt_upgrade_dialog_ok", true);
                         apt_upgrade_dialog
                             .set_response_enabled("apt_upgrade_dialog_open_log_file", true);
+                        break;
                     }
-                    _ => apt_upgrade_dialog.set_body(&state),
+                    // Already shown in the confirmation dialog
+                    ProgressMessage::TransactionPlan { .. } => {}
+                    ProgressMessage::ConflictPrompt {
+                        package,
+                        path,
+                        diff,
+                    } => apt_conffile_prompt(&window, &upgrade_reply_sender, package, path, diff),
+                    ProgressMessage::DebconfPrompt {
+                        id,
+                        title,
+                        questions,
+                    } => apt_debconf_prompt(&window, &upgrade_reply_sender, id, title, questions),
                 }
             }
         }
@@ -685,7 +926,279 @@ fn apt_full_upgrade_from_socket(
                     *initiated_by_main_borrow = false;
                 }
             }
+            "apt_upgrade_dialog_reboot" => {
+                let _ = Command::new("systemctl").arg("reboot").spawn();
+            }
             _ => {}
         }
     });
 }
+
+/// Fails the progress dialog when the helper could not even be started.
+fn send_setup_error(
+    upgrade_status_sender: &async_channel::Sender<ProgressMessage>,
+    message: String,
+) {
+    let _ = upgrade_status_sender.send_blocking(ProgressMessage::Error {
+        error_kind: AptHelperErrorKind::Unknown,
+        message,
+    });
+    let _ = upgrade_status_sender.send_blocking(ProgressMessage::Finished { exit_code: 1 });
+}
+
+/// Shows an error that stopped the upgrade before there was anything to confirm.
+pub fn apt_error_dialog(window: &adw::ApplicationWindow, body: &str) {
+    let apt_error_dialog = adw::MessageDialog::builder()
+        .transient_for(window)
+        .heading(t!("apt_upgrade_dialog_status_failed"))
+        .body(body)
+        .build();
+    apt_error_dialog.add_response(
+        "apt_error_dialog_ok",
+        &t!("apt_upgrade_dialog_ok_label").to_string(),
+    );
+    apt_error_dialog.present();
+}
+
+/// Asks what to do with a configuration file the user changed and the upgrade ships anew,
+/// closing the dialog leaves it to merge later.
+fn apt_conffile_prompt(
+    window: &adw::ApplicationWindow,
+    upgrade_reply_sender: &async_channel::Sender<ProgressReply>,
+    package: String,
+    path: String,
+    diff: String,
+) {
+    let apt_conffile_diff_buffer = gtk::TextBuffer::builder().text(&diff).build();
+    let apt_conffile_diff_view = gtk::TextView::builder()
+        .buffer(&apt_conffile_diff_buffer)
+        .editable(false)
+        .monospace(true)
+        .build();
+    let apt_conffile_diff_viewport = ScrolledWindow::builder()
+        .hexpand(true)
+        .height_request(300)
+        .child(&apt_conffile_diff_view)
+        .build();
+    let apt_conffile_diff_expander = Expander::builder()
+        .label(t!("apt_conffile_dialog_show_diff_label"))
+        .child(&apt_conffile_diff_viewport)
+        .build();
+
+    let apt_conffile_dialog = adw::MessageDialog::builder()
+        .transient_for(window)
+        .heading(t!("apt_conffile_dialog_heading"))
+        .body(
+            strfmt::strfmt(
+                &t!("apt_conffile_dialog_body").to_string(),
+                &std::collections::HashMap::from([
+                    ("PACKAGE".to_string(), package),
+                    ("PATH".to_string(), path.to_owned()),
+                ]),
+            )
+            .unwrap(),
+        )
+        .extra_child(&apt_conffile_diff_expander)
+        .width_request(600)
+        .build();
+
+    apt_conffile_dialog.add_response(
+        ConflictAnswer::MergeLater.as_str(),
+        &t!("apt_conffile_dialog_merge_later_label").to_string(),
+    );
+    apt_conffile_dialog.add_response(
+        ConflictAnswer::Keep.as_str(),
+        &t!("apt_conffile_dialog_keep_label").to_string(),
+    );
+    apt_conffile_dialog.add_response(
+        ConflictAnswer::Replace.as_str(),
+        &t!("apt_conffile_dialog_replace_label").to_string(),
+    );
+    apt_conffile_dialog.set_response_appearance(
+        ConflictAnswer::Replace.as_str(),
+        adw::ResponseAppearance::Destructive,
+    );
+    apt_conffile_dialog.set_default_response(Some(ConflictAnswer::Keep.as_str()));
+    apt_conffile_dialog.set_close_response(ConflictAnswer::MergeLater.as_str());
+
+    let upgrade_reply_sender = upgrade_reply_sender.clone();
+    apt_conffile_dialog.choose(None::<&gio::Cancellable>, move |choice| {
+        let answer = match choice.as_str() {
+            "keep" => ConflictAnswer::Keep,
+            "replace" => ConflictAnswer::Replace,
+            _ => ConflictAnswer::MergeLater,
+        };
+        // The helper stops waiting on its own if the connection is gone
+        let _ = upgrade_reply_sender.send_blocking(ProgressReply::ConflictAnswer { path, answer });
+    });
+}
+
+/// Tells the user once how the upgrade installed at boot went.
+pub fn notify_offline_upgrade_result(app: &adw::Application, glib_settings: &gio::Settings) {
+    let offline_upgrade_result = match OfflineUpgradeResult::load() {
+        Some(t) => t,
+        None => return,
+    };
+    if glib_settings.string("offline-upgrade-result-seen") == offline_upgrade_result.finished_at {
+        return;
+    }
+    let _ = glib_settings.set_string(
+        "offline-upgrade-result-seen",
+        &offline_upgrade_result.finished_at,
+    );
+    let notification = if offline_upgrade_result.is_success() {
+        let notification =
+            gio::Notification::new(&t!("offline_upgrade_result_successful_title").to_string());
+        notification.set_body(Some(
+            &strfmt::strfmt(
+                &t!("offline_upgrade_result_successful_body").to_string(),
+                &std::collections::HashMap::from([(
+                    "NUM".to_string(),
+                    offline_upgrade_result.changes.len().to_string(),
+                )]),
+            )
+            .unwrap(),
+        ));
+        notification
+    } else {
+        let notification =
+            gio::Notification::new(&t!("offline_upgrade_result_failed_title").to_string());
+        notification.set_body(Some(&apt_helper_error_text(
+            offline_upgrade_result
+                .error_kind
+                .unwrap_or(AptHelperErrorKind::Unknown),
+            &offline_upgrade_result.message,
+        )));
+        notification.set_priority(gio::NotificationPriority::High);
+        notification
+    };
+    app.send_notification(Some("offline-upgrade-result"), &notification);
+}
+
+/// Downloads the archives of a full upgrade without installing them, so a later upgrade
+/// can start right away. Blocks until the helper exits.
+///
+/// Nobody asked for it, so it is skipped when polkit would have to prompt for a password.
+pub fn apt_prefetch_upgrade(include_phased_updates: bool) -> Result<(), AptHelperErrorKind> {
+    if !is_authorized_without_prompt(APT_FULL_UPGRADE_ACTION_ID) {
+        return Err(AptHelperErrorKind::NotAuthorized);
+    }
+    // Only the holds apply, the helper reads them itself, what to skip this time is decided
+    // when the user clicks Update
+    let mut apt_prefetch_command = Command::new("pkexec");
+    apt_prefetch_command
+        .args([
+            APT_FULL_UPGRADE_PATH,
+            "--download-only",
+            "--assume-yes",
+            "--no-progress-socket",
+        ])
+        .stdout(Stdio::null());
+    if include_phased_updates {
+        apt_prefetch_command.arg("--include-phased-updates");
+    }
+    let status = apt_prefetch_command.status();
+    match status.ok().and_then(|t| t.code()) {
+        Some(0) => Ok(()),
+        Some(code) => {
+            Err(AptHelperErrorKind::from_exit_code(code).unwrap_or(AptHelperErrorKind::Unknown))
+        }
+        None => Err(AptHelperErrorKind::Unknown),
+    }
+}
+
+/// Whether polkit lets this process run `action_id` without any interaction.
+fn is_authorized_without_prompt(action_id: &str) -> bool {
+    Command::new("pkcheck")
+        .args([
+            "--action-id",
+            action_id,
+            "--process",
+            &std::process::id().to_string(),
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|t| t.success())
+}
+
+/// Why APT keeps a package back, in words.
+pub fn kept_back_reason_text(kept_back: &KeptBackPackage) -> String {
+    let detail = kept_back.detail.clone().unwrap_or_default();
+    let (template, key) = match kept_back.reason {
+        KeptBackReason::DpkgHold => (t!("kept_back_reason_dpkg_hold"), "PACKAGE"),
+        KeptBackReason::Phased => (t!("kept_back_reason_phased"), "PERCENT"),
+        KeptBackReason::NeedsRemovals => (t!("kept_back_reason_needs_removals"), "PACKAGES"),
+        KeptBackReason::BrokenDependencies => (t!("kept_back_reason_broken_dependencies"), "ERROR"),
+        KeptBackReason::Unknown => (t!("kept_back_reason_unknown"), "PACKAGE"),
+    };
+    let value = match kept_back.reason {
+        KeptBackReason::DpkgHold | KeptBackReason::Unknown => kept_back.change.name.to_owned(),
+        _ => detail,
+    };
+    strfmt::strfmt(
+        &template.to_string(),
+        &std::collections::HashMap::from([(key.to_string(), value)]),
+    )
+    .unwrap()
+}
+
+/// One line like `libfoo (New Install): bar depends on libfoo`.
+pub fn change_explanation_text(explanation: &ChangeExplanation) -> String {
+    let change_label = match explanation.change {
+        ExplainedChange::Install => t!("apt_why_change_install"),
+        ExplainedChange::Remove => t!("apt_why_change_remove"),
+        ExplainedChange::HeldBack => t!("apt_why_change_held_back"),
+    };
+    let chain = if explanation.chain.is_empty() {
+        t!("apt_why_no_reason").to_string()
+    } else {
+        explanation
+            .chain
+            .iter()
+            .map(|relation| {
+                let relation_label = match relation.kind {
+                    RelationKind::Depends => t!("apt_why_relation_depends"),
+                    RelationKind::Recommends => t!("apt_why_relation_recommends"),
+                    RelationKind::Conflicts => t!("apt_why_relation_conflicts"),
+                };
+                match &relation.constraint {
+                    Some(constraint) => format!(
+                        "{} {} {} ({})",
+                        relation.package, relation_label, relation.target, constraint
+                    ),
+                    None => format!(
+                        "{} {} {}",
+                        relation.package, relation_label, relation.target
+                    ),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" → ")
+    };
+    format!("{} ({}): {}", explanation.package, change_label, chain)
+}
+
+/// Turns an error reported by one of the apt helpers into something the user can act on.
+pub fn apt_helper_error_text(kind: AptHelperErrorKind, message: &str) -> String {
+    let hint = match kind {
+        AptHelperErrorKind::LockHeld => t!("apt_helper_error_lock_held"),
+        AptHelperErrorKind::AuthCancelled => t!("apt_helper_error_auth_cancelled"),
+        AptHelperErrorKind::NetworkFailure => t!("apt_helper_error_network_failure"),
+        AptHelperErrorKind::UnresolvableDependencies => {
+            t!("apt_helper_error_unresolvable_dependencies")
+        }
+        AptHelperErrorKind::DpkgInterrupted => t!("apt_helper_error_dpkg_interrupted"),
+        AptHelperErrorKind::DiskFull => t!("apt_helper_error_disk_full"),
+        AptHelperErrorKind::BadExclusionsFile => t!("apt_helper_error_bad_exclusions_file"),
+        AptHelperErrorKind::SnapshotFailed => t!("apt_helper_error_snapshot_failed"),
+        AptHelperErrorKind::NotAuthorized => t!("apt_helper_error_not_authorized"),
+        AptHelperErrorKind::Aborted => t!("apt_helper_cli_aborted"),
+        AptHelperErrorKind::Unknown => t!("upgrade_status_error_perms"),
+    };
+    if message.is_empty() {
+        hint.to_string()
+    } else {
+        format!("{}\n\n{}", hint, message)
+    }
+}
@@ -1,8 +1,11 @@
 use crate::apt_manage_page::apt_manage_page;
 use crate::apt_update_page;
+use crate::cleanup_page::cleanup_page;
 use crate::config::{APP_GITHUB, APP_ICON, APP_ID, VERSION};
 use crate::flatpak_manage_page::flatpak_manage_page;
 use crate::flatpak_update_page;
+use crate::history_page::history_page;
+use crate::kernels_page::kernels_page;
 use crate::main_update_page::main_update_page;
 use adw::prelude::*;
 use adw::*;
@@ -125,6 +128,7 @@ pub fn build_ui(app: &Application) {
     // Systray
 
     let apt_update_count = Rc::new(RefCell::new(0));
+    let apt_security_update_count = Rc::new(RefCell::new(0));
     let flatpak_update_count = Rc::new(RefCell::new(0));
 
     let update_sys_tray = gio::SimpleAction::new("sys_tray", Some(glib::VariantTy::ARRAY));
@@ -409,6 +413,8 @@ pub fn build_ui(app: &Application) {
 
     //window.present();
 
+    apt_update_page::process::notify_offline_upgrade_result(app, &glib_settings);
+
     // Theme update actions
     {
         let setting = gtk::Settings::default().unwrap();
@@ -520,6 +526,39 @@ pub fn build_ui(app: &Application) {
     let flatpak_ran_once = Rc::new(RefCell::new(false));
     let initiated_by_main = Rc::new(RefCell::new(false));
 
+    // Set by the background refresh, the page asks for the download once the list is loaded
+    let apt_prefetch_action = gio::SimpleAction::new("prefetch", None);
+    let apt_prefetch_requested = Rc::new(RefCell::new(false));
+    let apt_prefetch_running = Arc::new(AtomicBool::new(false));
+
+    apt_prefetch_action.connect_activate(clone!(
+        #[strong]
+        glib_settings,
+        #[strong]
+        apt_prefetch_requested,
+        move |_, _| {
+            if !apt_prefetch_requested.replace(false)
+                || !glib_settings.boolean("prefetch-updates")
+                || gio::NetworkMonitor::default().is_network_metered()
+                || apt_prefetch_running.swap(true, std::sync::atomic::Ordering::SeqCst)
+            {
+                return;
+            }
+            let include_phased_updates = glib_settings.boolean("include-phased-updates");
+            let apt_prefetch_running = apt_prefetch_running.clone();
+            thread::spawn(move || {
+                match apt_update_page::process::apt_prefetch_upgrade(include_phased_updates) {
+                    Ok(_) => println!("{}", t!("apt_prefetch_done")),
+                    Err(kind) => eprintln!(
+                        "{}",
+                        apt_update_page::process::apt_helper_error_text(kind, "")
+                    ),
+                }
+                apt_prefetch_running.store(false, std::sync::atomic::Ordering::SeqCst);
+            });
+        }
+    ));
+
     let apt_update_view_stack_bin = Bin::builder().build();
 
     apt_retry_signal_action.connect_activate(clone!(
@@ -542,9 +581,13 @@ pub fn build_ui(app: &Application) {
         #[strong]
         apt_update_count,
         #[strong]
+        apt_security_update_count,
+        #[strong]
         flatpak_update_count,
         #[strong]
         theme_changed_action,
+        #[strong]
+        apt_prefetch_action,
         move |action, _| {
             (*apt_update_button.borrow_mut() = gtk::Button::new());
             apt_update_view_stack_bin.set_child(Some(&apt_update_page::apt_update_page(
@@ -558,7 +601,9 @@ pub fn build_ui(app: &Application) {
                 initiated_by_main.clone(),
                 &update_sys_tray,
                 &apt_update_count,
+                &apt_security_update_count,
                 &flatpak_update_count,
+                &apt_prefetch_action,
             )));
         }
     ));
@@ -574,7 +619,9 @@ pub fn build_ui(app: &Application) {
         initiated_by_main.clone(),
         &update_sys_tray,
         &apt_update_count,
+        &apt_security_update_count,
         &flatpak_update_count,
+        &apt_prefetch_action,
     )));
 
     // Add to stack switcher
@@ -585,6 +632,7 @@ pub fn build_ui(app: &Application) {
             &initiated_by_main,
             &theme_changed_action,
             &update_sys_tray,
+            &apt_security_update_count,
         ),
         Some("main_update_page"),
         &t!("main_update_page_title"),
@@ -681,6 +729,55 @@ pub fn build_ui(app: &Application) {
     );
     window_adw_view_switcher_sidebar_box.append(&flatpak_manage_page_toggle_button);
 
+    window_adw_stack.add_titled(
+        &history_page(
+            window.clone(),
+            &apt_retry_signal_action,
+            &flatpak_retry_signal_action,
+        ),
+        Some("history_page"),
+        &t!("history_page_title"),
+    );
+
+    let history_page_toggle_button = add_content_button(
+        &window_adw_stack,
+        false,
+        "history_page".to_string(),
+        t!("history_page_title").to_string(),
+        &null_toggle_button,
+    );
+    window_adw_view_switcher_sidebar_box.append(&history_page_toggle_button);
+
+    window_adw_stack.add_titled(
+        &kernels_page(&apt_retry_signal_action),
+        Some("kernels_page"),
+        &t!("kernels_page_title"),
+    );
+
+    let kernels_page_toggle_button = add_content_button(
+        &window_adw_stack,
+        false,
+        "kernels_page".to_string(),
+        t!("kernels_page_title").to_string(),
+        &null_toggle_button,
+    );
+    window_adw_view_switcher_sidebar_box.append(&kernels_page_toggle_button);
+
+    window_adw_stack.add_titled(
+        &cleanup_page(window.clone(), &theme_changed_action),
+        Some("cleanup_page"),
+        &t!("cleanup_page_title"),
+    );
+
+    let cleanup_page_toggle_button = add_content_button(
+        &window_adw_stack,
+        false,
+        "cleanup_page".to_string(),
+        t!("cleanup_page_title").to_string(),
+        &null_toggle_button,
+    );
+    window_adw_view_switcher_sidebar_box.append(&cleanup_page_toggle_button);
+
     app.connect_command_line(clone!(
         #[strong]
         apt_manage_page_toggle_button,
@@ -793,6 +890,8 @@ pub fn build_ui(app: &Application) {
         apt_retry_signal_action,
         #[strong]
         flatpak_retry_signal_action,
+        #[strong]
+        apt_prefetch_requested,
         async move {
             while let Ok(message) = constant_loop_receiver.recv().await {
                 let banner_text = t!("banner_text_no_internet").to_string();
@@ -812,6 +911,7 @@ pub fn build_ui(app: &Application) {
                     ConstantLoopMessage::RefreshRequest => {
                         update_sys_tray
                             .activate(Some(&glib::Variant::array_from_fixed_array(&[-1, -1])));
+                        *apt_prefetch_requested.borrow_mut() = true;
                         apt_retry_signal_action.activate(None);
                         flatpak_retry_signal_action.activate(None);
                     }
@@ -0,0 +1,431 @@
+use crate::apt_update_page::process::apt_process_cleanup;
+use crate::config::APP_ID;
+use crate::flatpak_update_page::process::flatpak_run_transactions;
+use adw::prelude::*;
+use gtk::glib::*;
+use gtk::*;
+use pika_unixsocket_tools::kernels::{old_kernels, InstalledKernel};
+use pika_unixsocket_tools::progress_protocol::ChangeAction;
+use pika_unixsocket_tools::system_cleanup::{
+    autoremovable_packages, cached_archives, total_size, unused_flatpak_refs, CleanupItem,
+    UnusedFlatpakRef,
+};
+use pika_unixsocket_tools::update_discovery::{FlatpakInstallationKind, APT_ARCHIVES_DIR};
+use pretty_bytes::converter::convert;
+use rust_apt::new_cache;
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::thread;
+
+/// Everything the cleanup could remove right now.
+#[derive(Debug, Clone, Default)]
+struct CleanupCandidates {
+    autoremovable: Vec<CleanupItem>,
+    old_kernels: Vec<InstalledKernel>,
+    cached_archives: Vec<CleanupItem>,
+    unused_flatpak_refs: Vec<UnusedFlatpakRef>,
+}
+
+impl CleanupCandidates {
+    fn find(kernels_to_keep: u32) -> Self {
+        let (autoremovable, old_kernels) = match new_cache!() {
+            Ok(cache) => (
+                autoremovable_packages(&cache),
+                old_kernels(&cache, kernels_to_keep as usize),
+            ),
+            Err(e) => {
+                eprintln!("{}", e);
+                (Vec::new(), Vec::new())
+            }
+        };
+        Self {
+            autoremovable,
+            old_kernels,
+            cached_archives: cached_archives(Path::new(APT_ARCHIVES_DIR)),
+            unused_flatpak_refs: unused_flatpak_refs(),
+        }
+    }
+
+    /// Items of each category, in the order the page lists them.
+    fn category_items(&self) -> [Vec<CleanupItem>; 4] {
+        [
+            self.autoremovable.clone(),
+            self.old_kernel_items(),
+            self.cached_archives.clone(),
+            self.unused_flatpak_items(),
+        ]
+    }
+
+    fn old_kernel_items(&self) -> Vec<CleanupItem> {
+        self.old_kernels
+            .iter()
+            .map(|t| CleanupItem {
+                name: t.release.to_owned(),
+                size: t.installed_size,
+            })
+            .collect()
+    }
+
+    fn unused_flatpak_items(&self) -> Vec<CleanupItem> {
+        self.unused_flatpak_refs
+            .iter()
+            .map(|t| CleanupItem {
+                name: match t.installation {
+                    FlatpakInstallationKind::System => {
+                        format!("{} ({})", t.ref_format, t!("cleanup_flatpak_system_label"))
+                    }
+                    FlatpakInstallationKind::User => {
+                        format!("{} ({})", t.ref_format, t!("cleanup_flatpak_user_label"))
+                    }
+                },
+                size: t.installed_size,
+            })
+            .collect()
+    }
+}
+
+/// A category row with the item rows currently shown in it.
+struct CleanupCategory {
+    expander_row: adw::ExpanderRow,
+    check_button: CheckButton,
+    item_rows: RefCell<Vec<adw::ActionRow>>,
+}
+
+fn selected_size(categories: &[CleanupCategory], cleanup_candidates: &CleanupCandidates) -> u64 {
+    cleanup_candidates
+        .category_items()
+        .iter()
+        .zip(categories.iter())
+        .filter(|(_, category)| category.check_button.is_active())
+        .map(|(items, _)| total_size(items))
+        .sum()
+}
+
+/// Orphaned packages, old kernels, cached archives and unused Flatpak runtimes with the space
+/// they take, the selected ones are removed through the usual progress dialogs.
+pub fn cleanup_page(
+    window: adw::ApplicationWindow,
+    theme_changed_action: &gio::SimpleAction,
+) -> gtk::Box {
+    let glib_settings = gio::Settings::new(APP_ID);
+
+    let main_box = Box::builder()
+        .hexpand(true)
+        .vexpand(true)
+        .orientation(Orientation::Vertical)
+        .build();
+
+    let cleanup_label = Label::builder()
+        .label(t!("cleanup_page_label"))
+        .halign(Align::Start)
+        .wrap(true)
+        .margin_top(15)
+        .margin_bottom(15)
+        .margin_start(15)
+        .margin_end(15)
+        .build();
+    cleanup_label.add_css_class("heading");
+
+    let cleanup_boxedlist = ListBox::builder()
+        .selection_mode(SelectionMode::None)
+        .build();
+    cleanup_boxedlist.add_css_class("boxed-list");
+    cleanup_boxedlist.add_css_class("no-round-borders");
+
+    let cleanup_viewport = ScrolledWindow::builder()
+        .vexpand(true)
+        .hexpand(true)
+        .has_frame(true)
+        .margin_bottom(15)
+        .margin_end(15)
+        .margin_start(15)
+        .child(&cleanup_boxedlist)
+        .overflow(Overflow::Hidden)
+        .build();
+    cleanup_viewport.add_css_class("round-all-scroll-no-padding");
+
+    let categories: Rc<Vec<CleanupCategory>> = Rc::new(
+        [
+            t!("cleanup_autoremove_title"),
+            t!("cleanup_old_kernels_title"),
+            t!("cleanup_cached_archives_title"),
+            t!("cleanup_unused_flatpaks_title"),
+        ]
+        .iter()
+        .map(|title| {
+            let check_button = CheckButton::builder()
+                .valign(Align::Center)
+                .active(true)
+                .build();
+            let expander_row = adw::ExpanderRow::builder().title(title.to_string()).build();
+            expander_row.add_prefix(&check_button);
+            cleanup_boxedlist.append(&expander_row);
+            CleanupCategory {
+                expander_row,
+                check_button,
+                item_rows: RefCell::new(Vec::new()),
+            }
+        })
+        .collect(),
+    );
+
+    let bottom_bar = Box::builder().valign(Align::End).build();
+
+    let cleanup_button = Button::builder()
+        .halign(Align::End)
+        .valign(Align::Center)
+        .hexpand(true)
+        .margin_start(10)
+        .margin_end(30)
+        .margin_bottom(15)
+        .label(t!("cleanup_button_label"))
+        .sensitive(false)
+        .build();
+    cleanup_button.add_css_class("destructive-action");
+
+    let cleanup_candidates: Rc<RefCell<CleanupCandidates>> =
+        Rc::new(RefCell::new(CleanupCandidates::default()));
+
+    let (cleanup_candidates_sender, cleanup_candidates_receiver) =
+        async_channel::unbounded::<CleanupCandidates>();
+
+    let cleanup_selection_changed_action =
+        gio::SimpleAction::new("cleanup_selection_changed", None);
+    cleanup_selection_changed_action.connect_activate(clone!(
+        #[strong]
+        categories,
+        #[strong]
+        cleanup_candidates,
+        #[weak]
+        cleanup_button,
+        move |_, _| {
+            let selected_size = selected_size(&categories, &cleanup_candidates.borrow());
+            cleanup_button.set_sensitive(selected_size > 0);
+            cleanup_button.set_label(&if selected_size > 0 {
+                strfmt::strfmt(
+                    &t!("cleanup_button_size_label").to_string(),
+                    &std::collections::HashMap::from([(
+                        "SIZE".to_string(),
+                        convert(selected_size as f64),
+                    )]),
+                )
+                .unwrap()
+            } else {
+                t!("cleanup_button_label").to_string()
+            });
+        }
+    ));
+
+    for category in categories.iter() {
+        category.check_button.connect_toggled(clone!(
+            #[strong]
+            cleanup_selection_changed_action,
+            move |_| {
+                cleanup_selection_changed_action.activate(None);
+            }
+        ));
+    }
+
+    let cleanup_reload_action = gio::SimpleAction::new("cleanup_reload", None);
+    cleanup_reload_action.connect_activate(clone!(
+        #[strong]
+        categories,
+        #[strong]
+        glib_settings,
+        #[strong]
+        cleanup_candidates_sender,
+        #[weak]
+        cleanup_button,
+        move |_, _| {
+            cleanup_button.set_sensitive(false);
+            for category in categories.iter() {
+                category
+                    .expander_row
+                    .set_subtitle(&t!("cleanup_calculating_label"));
+                category.expander_row.set_sensitive(false);
+                category.check_button.set_sensitive(false);
+            }
+            let kernels_to_keep = glib_settings.uint("kernels-to-keep");
+            let cleanup_candidates_sender = cleanup_candidates_sender.clone();
+            thread::spawn(move || {
+                cleanup_candidates_sender
+                    .send_blocking(CleanupCandidates::find(kernels_to_keep))
+                    .expect("cleanup_candidates_receiver closed");
+            });
+        }
+    ));
+
+    let cleanup_candidates_context = MainContext::default();
+    // The main loop executes the asynchronous block
+    cleanup_candidates_context.spawn_local(clone!(
+        #[strong]
+        categories,
+        #[strong]
+        cleanup_candidates,
+        #[strong]
+        cleanup_selection_changed_action,
+        async move {
+            while let Ok(state) = cleanup_candidates_receiver.recv().await {
+                for (category, items) in categories.iter().zip(state.category_items().iter()) {
+                    let mut item_rows = category.item_rows.borrow_mut();
+                    for item_row in item_rows.drain(..) {
+                        category.expander_row.remove(&item_row);
+                    }
+                    category.expander_row.set_subtitle(
+                        &strfmt::strfmt(
+                            &t!("cleanup_category_subtitle").to_string(),
+                            &std::collections::HashMap::from([
+                                ("NUM".to_string(), items.len().to_string()),
+                                ("SIZE".to_string(), convert(total_size(items) as f64)),
+                            ]),
+                        )
+                        .unwrap(),
+                    );
+                    for item in items {
+                        let item_row = adw::ActionRow::builder()
+                            .title(&item.name)
+                            .use_markup(false)
+                            .subtitle(convert(item.size as f64))
+                            .build();
+                        category.expander_row.add_row(&item_row);
+                        item_rows.push(item_row);
+                    }
+                    category.expander_row.set_sensitive(true);
+                    category.check_button.set_sensitive(!items.is_empty());
+                }
+                *cleanup_candidates.borrow_mut() = state;
+                cleanup_selection_changed_action.activate(None);
+            }
+        }
+    ));
+
+    // Never shown, the APT cleanup clicks it when it is done to go on with the Flatpaks
+    let flatpak_cleanup_button = Button::builder().visible(false).build();
+    flatpak_cleanup_button.connect_clicked(clone!(
+        #[strong]
+        window,
+        #[strong]
+        cleanup_candidates,
+        #[strong]
+        cleanup_reload_action,
+        #[strong]
+        theme_changed_action,
+        move |_| {
+            let unused_flatpak_refs = cleanup_candidates.borrow().unused_flatpak_refs.clone();
+            let refs_of = |installation: FlatpakInstallationKind| -> Vec<String> {
+                unused_flatpak_refs
+                    .iter()
+                    .filter(|t| t.installation == installation)
+                    .map(|t| t.ref_format.to_owned())
+                    .collect()
+            };
+            flatpak_run_transactions(
+                refs_of(FlatpakInstallationKind::System),
+                refs_of(FlatpakInstallationKind::User),
+                ChangeAction::Remove,
+                window.clone(),
+                &cleanup_reload_action,
+                &theme_changed_action,
+            );
+        }
+    ));
+
+    cleanup_button.connect_clicked(clone!(
+        #[strong]
+        window,
+        #[strong]
+        categories,
+        #[strong]
+        cleanup_candidates,
+        #[strong]
+        glib_settings,
+        #[strong]
+        cleanup_reload_action,
+        #[strong]
+        flatpak_cleanup_button,
+        #[strong]
+        theme_changed_action,
+        move |_| {
+            let is_selected = |index: usize| categories[index].check_button.is_active();
+            let cleanup_candidates = cleanup_candidates.borrow();
+            let (autoremove, remove_old_kernels, clean_cache, remove_unused_flatpaks) = (
+                is_selected(0) && !cleanup_candidates.autoremovable.is_empty(),
+                is_selected(1) && !cleanup_candidates.old_kernels.is_empty(),
+                is_selected(2) && !cleanup_candidates.cached_archives.is_empty(),
+                is_selected(3) && !cleanup_candidates.unused_flatpak_refs.is_empty(),
+            );
+
+            let cleanup_confirm_dialog = adw::MessageDialog::builder()
+                .transient_for(&window)
+                .heading(t!("cleanup_confirm_dialog_heading"))
+                .body(
+                    strfmt::strfmt(
+                        &t!("cleanup_confirm_dialog_body").to_string(),
+                        &std::collections::HashMap::from([(
+                            "SIZE".to_string(),
+                            convert(selected_size(&categories, &cleanup_candidates) as f64),
+                        )]),
+                    )
+                    .unwrap(),
+                )
+                .build();
+            cleanup_confirm_dialog.add_response(
+                "cleanup_confirm_dialog_cancel",
+                &t!("cleanup_confirm_dialog_cancel_label").to_string(),
+            );
+            cleanup_confirm_dialog.add_response(
+                "cleanup_confirm_dialog_confirm",
+                &t!("cleanup_confirm_dialog_confirm_label").to_string(),
+            );
+            cleanup_confirm_dialog.set_response_appearance(
+                "cleanup_confirm_dialog_confirm",
+                adw::ResponseAppearance::Destructive,
+            );
+            cleanup_confirm_dialog.set_default_response(Some("cleanup_confirm_dialog_cancel"));
+            cleanup_confirm_dialog.set_close_response("cleanup_confirm_dialog_cancel");
+
+            let window = window.clone();
+            let kernels_to_keep = glib_settings.uint("kernels-to-keep");
+            let cleanup_reload_action = cleanup_reload_action.clone();
+            let flatpak_cleanup_button = flatpak_cleanup_button.clone();
+            let theme_changed_action = theme_changed_action.clone();
+            cleanup_confirm_dialog.choose(None::<&gio::Cancellable>, move |choice| {
+                if choice != "cleanup_confirm_dialog_confirm" {
+                    return;
+                }
+                if autoremove || remove_old_kernels || clean_cache {
+                    apt_process_cleanup(
+                        autoremove,
+                        remove_old_kernels.then_some(kernels_to_keep),
+                        clean_cache,
+                        window,
+                        &cleanup_reload_action,
+                        &flatpak_cleanup_button,
+                        Rc::new(RefCell::new(remove_unused_flatpaks)),
+                        &theme_changed_action,
+                    );
+                } else if remove_unused_flatpaks {
+                    flatpak_cleanup_button.emit_clicked();
+                }
+            });
+        }
+    ));
+
+    main_box.connect_map(clone!(
+        #[strong]
+        cleanup_reload_action,
+        move |_| {
+            cleanup_reload_action.activate(None);
+        }
+    ));
+
+    bottom_bar.append(&cleanup_button);
+    bottom_bar.append(&flatpak_cleanup_button);
+
+    main_box.append(&cleanup_label);
+    main_box.append(&cleanup_viewport);
+    main_box.append(&bottom_bar);
+
+    main_box
+}
@@ -0,0 +1,715 @@
+use crate::apt_update_page::process::{
+    apt_helper_error_text, change_explanation_text, kept_back_reason_text,
+};
+use crate::config::{kernels_to_keep_setting, APP_ID};
+use gtk::gio;
+use libflatpak::prelude::*;
+use pika_unixsocket_tools::apt_helper_args::apt_full_upgrade_path;
+use pika_unixsocket_tools::apt_helper_error::{AptHelperError, AptHelperErrorKind};
+use pika_unixsocket_tools::phased_updates::PhasingPolicy;
+use pika_unixsocket_tools::progress_protocol::{decode_message, ProgressMessage};
+use pika_unixsocket_tools::session_socket::{create_session_socket_dir, remove_session_socket_dir};
+use pika_unixsocket_tools::update_discovery::{
+    kept_back_packages, list_flatpak_updates, security_only_exclusions, simulate_full_upgrade,
+    upgradable_packages, AptBackend, AptChange, FlatpakInstallationKind, FlatpakUpdate,
+    LibflatpakBackend, RustAptBackend, UpdateClass,
+};
+use pika_unixsocket_tools::update_holds::{Exclusion, ExclusionsFile, ForcedUpgrade, UpdateHolds};
+use pretty_bytes::converter::convert;
+use serde::Serialize;
+use std::io::{BufRead, BufReader, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+const CLI_USAGE: &str = "Usage: pikman-update-manager --cli [OPTIONS]
+
+Options:
+  --list                 Only list pending updates
+  --exclude <PACKAGE>    Leave a package out of the APT upgrade, can be repeated
+                         Takes a name, name:arch or a glob like 'linux-image-*'
+  --exclude-regex <RE>   Leave every package matching a regex out of the APT upgrade
+  --security-only        Only install APT updates from security pockets
+  --force-upgrade <PACKAGE>
+                         Upgrade a package APT keeps back, deferred phased
+                         updates included, can be repeated
+  --include-phased-updates
+                         Do not defer phased updates
+  -y, --assume-yes       Do not ask for confirmation
+  --no-apt               Skip APT updates
+  --no-flatpak           Skip Flatpak updates
+  -h, --help             Show this help";
+
+#[derive(Default)]
+struct CliArgs {
+    list_only: bool,
+    excluded_updates_vec: Vec<Exclusion>,
+    security_only: bool,
+    forced_upgrade_names: Vec<String>,
+    forced_upgrades_vec: Vec<ForcedUpgrade>,
+    include_phased_updates: bool,
+    assume_yes: bool,
+    no_apt: bool,
+    no_flatpak: bool,
+    help: bool,
+}
+
+impl CliArgs {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--list" => parsed.list_only = true,
+                "--exclude" => match args.next() {
+                    Some(t) => {
+                        let exclusion = Exclusion::parse(t);
+                        exclusion
+                            .validate()
+                            .map_err(|e| format!("--exclude {}: {}", t, e))?;
+                        parsed.excluded_updates_vec.push(exclusion)
+                    }
+                    None => return Err("--exclude needs a package name".to_owned()),
+                },
+                "--exclude-regex" => match args.next() {
+                    Some(t) => {
+                        let exclusion = Exclusion::regex(t);
+                        exclusion
+                            .validate()
+                            .map_err(|e| format!("--exclude-regex {}: {}", t, e))?;
+                        parsed.excluded_updates_vec.push(exclusion)
+                    }
+                    None => return Err("--exclude-regex needs an expression".to_owned()),
+                },
+                "--security-only" => parsed.security_only = true,
+                "--force-upgrade" => match args.next() {
+                    Some(t) => parsed.forced_upgrade_names.push(t.to_owned()),
+                    None => return Err("--force-upgrade needs a package name".to_owned()),
+                },
+                "--include-phased-updates" => parsed.include_phased_updates = true,
+                "-y" | "--assume-yes" => parsed.assume_yes = true,
+                "--no-apt" => parsed.no_apt = true,
+                "--no-flatpak" => parsed.no_flatpak = true,
+                "-h" | "--help" => parsed.help = true,
+                t => return Err(format!("unknown option {}", t)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+const LIST_UPDATES_USAGE: &str = "Usage: pikman-update-manager --list-updates [OPTIONS]
+
+Options:
+  --json                 Print a single JSON document (default)
+  --ndjson               Print one JSON object per update
+  --no-apt               Skip APT updates
+  --no-flatpak           Skip Flatpak updates
+  -h, --help             Show this help";
+
+#[derive(Serialize)]
+struct PendingUpdates {
+    apt: Vec<AptChange>,
+    flatpak: Vec<FlatpakUpdate>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum PendingUpdate<'a> {
+    Apt(&'a AptChange),
+    Flatpak(&'a FlatpakUpdate),
+}
+
+/// Terminal frontend, runs without a display and returns the process exit code.
+pub fn run_cli(args: &[String]) -> i32 {
+    let mut cli_args = match CliArgs::parse(args) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, CLI_USAGE);
+            return 2;
+        }
+    };
+    if cli_args.help {
+        println!("{}", CLI_USAGE);
+        return 0;
+    }
+
+    cli_args.include_phased_updates |= include_phased_updates_setting();
+    PhasingPolicy::new(cli_args.include_phased_updates).apply();
+
+    let mut apt_update_count = 0;
+    if !cli_args.no_apt {
+        println!("{}", t!("cli_apt_updates_heading"));
+        let apt_updates = match upgradable_packages(&RustAptBackend, &UpdateHolds::load()) {
+            Ok(t) => t,
+            Err(e) => return print_apt_error(e),
+        };
+        for package in apt_updates {
+            if cli_args.security_only && package.update_class != UpdateClass::Security {
+                continue;
+            }
+            println!(
+                "  {}:{}  {} -> {}{}{}",
+                package.name,
+                package.arch,
+                package
+                    .installed_version
+                    .clone()
+                    .unwrap_or(t!("installed_version_to_be_installed").to_string()),
+                package.candidate_version.clone().unwrap_or_default(),
+                match package.update_class {
+                    UpdateClass::Security => format!("  [{}]", t!("update_class_security_label")),
+                    UpdateClass::ThirdParty => {
+                        format!("  [{}]", t!("update_class_third_party_label"))
+                    }
+                    UpdateClass::Regular => String::new(),
+                },
+                match package.phased_update_percentage {
+                    Some(percentage) if percentage < 100 => format!(
+                        "  [{}]",
+                        strfmt::strfmt(
+                            &t!("phased_update_badge_label").to_string(),
+                            &std::collections::HashMap::from([(
+                                "PERCENT".to_string(),
+                                percentage.to_string()
+                            )]),
+                        )
+                        .unwrap()
+                    ),
+                    _ => String::new(),
+                }
+            );
+            apt_update_count += 1;
+        }
+        if apt_update_count == 0 {
+            println!("  {}", t!("apt_packages_no_viewport_page_title"));
+        }
+        println!();
+
+        let kept_back = match kept_back_packages(&RustAptBackend, &UpdateHolds::load()) {
+            Ok(t) => t,
+            Err(e) => return print_apt_error(e),
+        };
+        if !kept_back.is_empty() {
+            println!("{}", t!("apt_kept_back_label"));
+            for package in &kept_back {
+                println!(
+                    "  {}:{}  {} -> {}  ({})",
+                    package.change.name,
+                    package.change.arch,
+                    package
+                        .change
+                        .installed_version
+                        .as_deref()
+                        .unwrap_or_default(),
+                    package
+                        .change
+                        .candidate_version
+                        .as_deref()
+                        .unwrap_or_default(),
+                    kept_back_reason_text(package)
+                );
+            }
+            println!();
+        }
+        // `name` or `name:arch`, only packages that resolve when asked for explicitly
+        for spec in &cli_args.forced_upgrade_names {
+            let (package, arch) = match spec.split_once(':') {
+                Some((package, arch)) => (package, Some(arch)),
+                None => (spec.as_str(), None),
+            };
+            let matching: Vec<&AptChange> = kept_back
+                .iter()
+                .filter(|t| t.can_force)
+                .map(|t| &t.change)
+                .filter(|t| t.name == package && (arch.is_none() || arch == Some(t.arch.as_str())))
+                .collect();
+            if matching.is_empty() {
+                eprintln!(
+                    "{}",
+                    strfmt::strfmt(
+                        &t!("cli_force_upgrade_not_kept_back").to_string(),
+                        &std::collections::HashMap::from([(
+                            "PACKAGE".to_string(),
+                            spec.to_owned()
+                        )]),
+                    )
+                    .unwrap()
+                );
+                return 2;
+            }
+            for change in matching {
+                cli_args
+                    .forced_upgrades_vec
+                    .push(ForcedUpgrade::new(&change.name, &change.arch));
+                apt_update_count += 1;
+            }
+        }
+    }
+
+    let flatpak_updates = if cli_args.no_flatpak {
+        Vec::new()
+    } else {
+        let flatpak_updates = list_flatpak_updates(&LibflatpakBackend);
+        println!("{}", t!("cli_flatpak_updates_heading"));
+        for flatpak_update in &flatpak_updates {
+            println!(
+                "  {}  ({}, {})",
+                flatpak_update.ref_format,
+                flatpak_update.remote_name.as_deref().unwrap_or("Unknown"),
+                match flatpak_update.installation {
+                    FlatpakInstallationKind::System => "system",
+                    FlatpakInstallationKind::User => "user",
+                }
+            );
+        }
+        if flatpak_updates.is_empty() {
+            println!("  {}", t!("flatpak_packages_no_viewport_page_title"));
+        }
+        println!();
+        flatpak_updates
+    };
+
+    if cli_args.list_only || (apt_update_count == 0 && flatpak_updates.is_empty()) {
+        return 0;
+    }
+
+    if apt_update_count != 0 && !cli_args.assume_yes && std::io::stdin().is_terminal() {
+        eprint!("{} ", t!("cli_exclude_prompt"));
+        let _ = std::io::stderr().flush();
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer).is_ok() {
+            for spec in answer.split_whitespace() {
+                let exclusion = Exclusion::parse(spec);
+                match exclusion.validate() {
+                    Ok(_) => cli_args.excluded_updates_vec.push(exclusion),
+                    Err(e) => eprintln!("{}: {}", spec, e),
+                }
+            }
+        }
+    }
+
+    if apt_update_count != 0 && cli_args.security_only {
+        match RustAptBackend.full_upgrade_changes() {
+            Ok(t) => cli_args
+                .excluded_updates_vec
+                .extend(security_only_exclusions(&t)),
+            Err(e) => return print_apt_error(e),
+        }
+    }
+
+    if apt_update_count != 0 {
+        if let Err(e) = print_apt_changes_summary(
+            &cli_args.excluded_updates_vec,
+            &cli_args.forced_upgrades_vec,
+        ) {
+            return print_apt_error(e);
+        }
+    }
+    if !flatpak_updates.is_empty() {
+        println!(
+            "{}: {}",
+            t!("system_flatref_count_badge_label"),
+            flatpak_updates
+                .iter()
+                .filter(|t| t.installation == FlatpakInstallationKind::System)
+                .count()
+        );
+        println!(
+            "{}: {}",
+            t!("user_flatref_count_badge_label"),
+            flatpak_updates
+                .iter()
+                .filter(|t| t.installation == FlatpakInstallationKind::User)
+                .count()
+        );
+        println!();
+    }
+
+    if !cli_args.assume_yes && !confirm(&t!("apt_helper_cli_confirm")) {
+        eprintln!("{}", t!("apt_helper_cli_aborted"));
+        return AptHelperErrorKind::Aborted.exit_code();
+    }
+
+    if apt_update_count != 0 {
+        let exit_code = run_apt_full_upgrade(
+            &cli_args.excluded_updates_vec,
+            &cli_args.forced_upgrades_vec,
+            cli_args.include_phased_updates,
+            kernels_to_keep_setting(),
+        );
+        if exit_code != 0 {
+            return exit_code;
+        }
+    }
+    if !flatpak_updates.is_empty() {
+        if let Err(e) = run_flatpak_updates(&flatpak_updates) {
+            eprintln!("{}", e);
+            return 1;
+        }
+    }
+    0
+}
+
+/// The GUI setting, if the schema is installed at all.
+fn include_phased_updates_setting() -> bool {
+    match gio::SettingsSchemaSource::default().and_then(|t| t.lookup(APP_ID, true)) {
+        Some(_) => gio::Settings::new(APP_ID).boolean("include-phased-updates"),
+        None => false,
+    }
+}
+
+fn print_apt_error(error: AptHelperError) -> i32 {
+    eprintln!("{}", apt_helper_error_text(error.kind, &error.message));
+    error.kind.exit_code()
+}
+
+fn print_apt_changes_summary(
+    excluded_updates_vec: &Vec<Exclusion>,
+    forced_upgrades_vec: &Vec<ForcedUpgrade>,
+) -> Result<(), AptHelperError> {
+    let apt_changes_struct = simulate_full_upgrade(
+        &RustAptBackend,
+        excluded_updates_vec,
+        forced_upgrades_vec,
+        &UpdateHolds::load(),
+        kernels_to_keep_setting(),
+    )?;
+    println!("{}", t!("apt_confirm_dialog_body"));
+    for (label, value) in [
+        (
+            t!("package_count_upgrade_badge_label"),
+            apt_changes_struct.package_count_upgrade.to_string(),
+        ),
+        (
+            t!("package_count_install_badge_label"),
+            apt_changes_struct.package_count_install.to_string(),
+        ),
+        (
+            t!("package_count_downgrade_badge_label"),
+            apt_changes_struct.package_count_downgrade.to_string(),
+        ),
+        (
+            t!("package_count_remove_badge_label"),
+            apt_changes_struct.package_count_remove.to_string(),
+        ),
+        (
+            t!("package_count_security_badge_label"),
+            apt_changes_struct.package_count_security.to_string(),
+        ),
+        (
+            t!("total_download_size_badge_label"),
+            convert(apt_changes_struct.total_download_size as f64),
+        ),
+        (
+            t!("total_installed_size_badge_label"),
+            convert(apt_changes_struct.total_installed_size as f64),
+        ),
+    ] {
+        println!("  {}: {}", label, value);
+    }
+    if apt_changes_struct.is_ready_to_install() {
+        println!(
+            "  {}: {}",
+            t!("ready_to_install_badge_label"),
+            strfmt::strfmt(
+                &t!("ready_to_install_badge_text").to_string(),
+                &std::collections::HashMap::from([(
+                    "SIZE".to_string(),
+                    convert(apt_changes_struct.total_download_size as f64),
+                )]),
+            )
+            .unwrap()
+        );
+    }
+    if !apt_changes_struct.to_be_removed_packages.is_empty() {
+        println!("{}", t!("apt_remove_confirm_dialog_body"));
+        for package in &apt_changes_struct.to_be_removed_packages {
+            println!("  {}", package);
+        }
+    }
+    if !apt_changes_struct.held_back_packages.is_empty() {
+        println!("{}", t!("apt_held_back_label"));
+        for package in &apt_changes_struct.held_back_packages {
+            println!("  {}", package);
+        }
+    }
+    if !apt_changes_struct.explanations.is_empty() {
+        println!("{}", t!("apt_why_expander_label"));
+        for explanation in &apt_changes_struct.explanations {
+            println!("  {}", change_explanation_text(explanation));
+        }
+    }
+    println!();
+    Ok(())
+}
+
+fn confirm(question: &str) -> bool {
+    eprint!("{} [y/N] ", question);
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    match std::io::stdin().read_line(&mut answer) {
+        Ok(_) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+        Err(_) => false,
+    }
+}
+
+fn draw_progress_bar(percent: f32, label: &str) {
+    let percent = percent.clamp(0.0, 100.0);
+    let filled = (percent / 100.0 * PROGRESS_BAR_WIDTH as f32) as usize;
+    let mut stderr = std::io::stderr();
+    if stderr.is_terminal() {
+        let _ = write!(
+            stderr,
+            "\r\x1b[2K[{}{}] {:>3.0}% {}",
+            "#".repeat(filled),
+            "-".repeat(PROGRESS_BAR_WIDTH - filled),
+            percent,
+            label
+        );
+        let _ = stderr.flush();
+    }
+}
+
+fn print_progress_line(message: &str) {
+    let mut stderr = std::io::stderr();
+    if stderr.is_terminal() {
+        let _ = write!(stderr, "\r\x1b[2K");
+    }
+    println!("{}", message);
+}
+
+/// Passes on a line the helper printed that is not one of its JSON messages.
+fn print_helper_output(line: &[u8]) {
+    if line.is_empty() {
+        return;
+    }
+    let mut stderr = std::io::stderr();
+    if stderr.is_terminal() {
+        let _ = write!(stderr, "\r\x1b[2K");
+    }
+    let _ = writeln!(stderr, "{}", String::from_utf8_lossy(line));
+}
+
+/// Runs the root helper like the GUI does, but reads its progress from stdout.
+fn run_apt_full_upgrade(
+    excluded_updates_vec: &Vec<Exclusion>,
+    forced_upgrades_vec: &Vec<ForcedUpgrade>,
+    include_phased_updates: bool,
+    kernels_to_keep: Option<u32>,
+) -> i32 {
+    let locale = rust_i18n::locale().to_string();
+    let session_dir = match create_session_socket_dir() {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Could not create a private session directory: {}", e);
+            return 1;
+        }
+    };
+    let exclusions_file_path = session_dir.join("exclusions.json");
+
+    let mut helper_args: Vec<String> = vec![
+        "--locale".to_owned(),
+        locale,
+        "--json".to_owned(),
+        "--no-progress-socket".to_owned(),
+        "--assume-yes".to_owned(),
+    ];
+    let exclusions_file = ExclusionsFile::new(excluded_updates_vec, forced_upgrades_vec);
+    if !exclusions_file.is_empty() {
+        if let Err(e) = exclusions_file.write_to(&exclusions_file_path) {
+            eprintln!("Failed to write to json file: {}", e);
+            remove_session_socket_dir(&session_dir);
+            return 1;
+        }
+        helper_args.push("--exclusions-file".to_owned());
+        helper_args.push(exclusions_file_path.to_string_lossy().to_string());
+    }
+    if include_phased_updates {
+        helper_args.push("--include-phased-updates".to_owned());
+    }
+    if let Some(kernels_to_keep) = kernels_to_keep {
+        helper_args.push("--remove-old-kernels".to_owned());
+        helper_args.push(kernels_to_keep.to_string());
+    }
+
+    let mut helper_command = Command::new("pkexec");
+    helper_command
+        .arg(apt_full_upgrade_path(&helper_args))
+        .args(&helper_args);
+
+    let mut helper_child = match helper_command.stdout(Stdio::piped()).spawn() {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("{}", e);
+            remove_session_socket_dir(&session_dir);
+            return 1;
+        }
+    };
+
+    let mut percent: f32 = 0.0;
+    let mut reported_error = false;
+    if let Some(stdout) = helper_child.stdout.take() {
+        for line in BufReader::new(stdout).split(b'\n') {
+            let line = match line {
+                Ok(t) => t,
+                Err(_) => break,
+            };
+            // Whatever the helper prints besides its JSON lines, dpkg or a maintainer
+            // script for example, still belongs in front of the user
+            let message = match decode_message(&line) {
+                Ok(t) => t,
+                Err(_) => {
+                    print_helper_output(&line);
+                    continue;
+                }
+            };
+            match message {
+                ProgressMessage::Percent { percent: t } => {
+                    percent = t;
+                    draw_progress_bar(percent, "");
+                }
+                ProgressMessage::Speed { speed } => draw_progress_bar(percent, &speed),
+                ProgressMessage::Status { message } => draw_progress_bar(percent, &message),
+                ProgressMessage::Error {
+                    error_kind,
+                    message,
+                } => {
+                    reported_error = true;
+                    print_progress_line(&apt_helper_error_text(error_kind, &message));
+                }
+                ProgressMessage::TransactionPlan { .. } => {}
+                message => {
+                    if let Some(log_line) = message.log_line() {
+                        print_progress_line(&log_line);
+                    }
+                }
+            }
+        }
+    }
+
+    let exit_code = match helper_child.wait() {
+        Ok(t) => t.code().unwrap_or(1),
+        Err(_) => 1,
+    };
+    remove_session_socket_dir(&session_dir);
+    print_progress_line("");
+    if exit_code == 0 {
+        println!("{}", t!("apt_upgrade_dialog_status_successful"));
+    } else {
+        if !reported_error {
+            let error_kind = AptHelperErrorKind::from_exit_code(exit_code)
+                .unwrap_or(AptHelperErrorKind::Unknown);
+            eprintln!("{}", apt_helper_error_text(error_kind, ""));
+        }
+        eprintln!("{}", t!("apt_upgrade_dialog_status_failed"));
+    }
+    exit_code
+}
+
+fn run_flatpak_updates(flatpak_updates: &[FlatpakUpdate]) -> Result<(), String> {
+    let cancellable_no = libflatpak::gio::Cancellable::NONE;
+    for installation_kind in [
+        FlatpakInstallationKind::System,
+        FlatpakInstallationKind::User,
+    ] {
+        let refs: Vec<&FlatpakUpdate> = flatpak_updates
+            .iter()
+            .filter(|t| t.installation == installation_kind)
+            .collect();
+        if refs.is_empty() {
+            continue;
+        }
+        let installation = match installation_kind {
+            FlatpakInstallationKind::System => libflatpak::Installation::new_system(cancellable_no),
+            FlatpakInstallationKind::User => libflatpak::Installation::new_user(cancellable_no),
+        }
+        .map_err(|e| e.to_string())?;
+        let transaction = libflatpak::Transaction::for_installation(&installation, cancellable_no)
+            .map_err(|e| e.to_string())?;
+        for flatpak_update in refs {
+            transaction
+                .add_update(&flatpak_update.ref_format, &[], None)
+                .map_err(|e| e.to_string())?;
+        }
+
+        transaction.connect_new_operation(move |_, transaction_operation, transaction_progress| {
+            let ref_name = transaction_operation
+                .get_ref()
+                .map(|t| t.to_string())
+                .unwrap_or_default();
+            print_progress_line(&format!("{}: {}", t!("flatpak_ref"), ref_name));
+            transaction_progress.connect_changed(move |transaction_progress| {
+                draw_progress_bar(
+                    transaction_progress.progress() as f32,
+                    &transaction_progress
+                        .status()
+                        .map(|t| t.to_string())
+                        .unwrap_or_default(),
+                );
+            });
+        });
+
+        transaction.run(cancellable_no).map_err(|e| e.to_string())?;
+        print_progress_line("");
+    }
+    println!("{}", t!("flatpak_transaction_dialog_status_successful"));
+    Ok(())
+}
+
+/// Prints every pending update as JSON, for monitoring and inventory scripts.
+pub fn run_list_updates(args: &[String]) -> i32 {
+    let mut ndjson = false;
+    let mut no_apt = false;
+    let mut no_flatpak = false;
+    for arg in args {
+        match arg.as_str() {
+            "--json" => ndjson = false,
+            "--ndjson" => ndjson = true,
+            "--no-apt" => no_apt = true,
+            "--no-flatpak" => no_flatpak = true,
+            "-h" | "--help" => {
+                println!("{}", LIST_UPDATES_USAGE);
+                return 0;
+            }
+            t => {
+                eprintln!("unknown option {}\n\n{}", t, LIST_UPDATES_USAGE);
+                return 2;
+            }
+        }
+    }
+
+    PhasingPolicy::new(include_phased_updates_setting()).apply();
+    let pending_updates = PendingUpdates {
+        apt: if no_apt {
+            Vec::new()
+        } else {
+            match upgradable_packages(&RustAptBackend, &UpdateHolds::load()) {
+                Ok(t) => t,
+                Err(e) => return print_apt_error(e),
+            }
+        },
+        flatpak: if no_flatpak {
+            Vec::new()
+        } else {
+            list_flatpak_updates(&LibflatpakBackend)
+        },
+    };
+
+    if ndjson {
+        let records = pending_updates
+            .apt
+            .iter()
+            .map(PendingUpdate::Apt)
+            .chain(pending_updates.flatpak.iter().map(PendingUpdate::Flatpak));
+        for record in records {
+            println!("{}", serde_json::to_string(&record).unwrap());
+        }
+    } else {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&pending_updates).unwrap()
+        );
+    }
+    0
+}
@@ -1,4 +1,20 @@
+use gtk::gio;
+use gtk::prelude::*;
+
 pub const APP_ID: &str = "com.github.pikaos-linux.pikmanupdatemanager";
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const APP_ICON: &str = "com.github.pikaos-linux.pikmanupdatemanager";
 pub const APP_GITHUB: &str = "https://github.com/PikaOS-Linux/pkg-pikman-update-manager";
+
+/// How many kernels an upgrade keeps, if the settings have old ones removed at all. The
+/// schema may be missing when the CLI runs from a build tree.
+pub fn kernels_to_keep_setting() -> Option<u32> {
+    gio::SettingsSchemaSource::default()
+        .and_then(|t| t.lookup(APP_ID, true))
+        .and_then(|_| {
+            let glib_settings = gio::Settings::new(APP_ID);
+            glib_settings
+                .boolean("remove-old-kernels")
+                .then(|| glib_settings.uint("kernels-to-keep"))
+        })
+}
@@ -41,8 +41,13 @@ impl Default for FlatpakRefRow {
             ref_name: "??".to_owned(),
             name: "??".to_owned(),
             arch: "??".to_owned(),
+            branch: "??".to_owned(),
             summary: "??".to_owned(),
+            origin: "??".to_owned(),
             remote_name: "??".to_owned(),
+            installed_version: "??".to_owned(),
+            installed_commit: "??".to_owned(),
+            remote_commit: "??".to_owned(),
             installed_size_installed: 0,
             installed_size_remote: 0,
             download_size: 0,
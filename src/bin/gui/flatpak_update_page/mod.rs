@@ -1,4 +1,4 @@
-mod process;
+pub mod process;
 
 use crate::build_ui::get_current_font;
 use crate::flatpak_ref_row::FlatpakRefRow;
@@ -7,6 +7,9 @@ use adw::prelude::*;
 use gtk::glib::*;
 use gtk::*;
 use libflatpak::prelude::*;
+use pika_unixsocket_tools::update_discovery::{
+    FlatpakBackend, FlatpakInstallationKind, FlatpakUpdate, LibflatpakBackend,
+};
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::thread;
@@ -16,8 +19,13 @@ pub struct FlatpakRefStruct {
     pub ref_name: String,
     pub name: String,
     pub arch: String,
+    pub branch: String,
     pub summary: String,
+    pub origin: String,
     pub remote_name: String,
+    pub installed_version: String,
+    pub installed_commit: String,
+    pub remote_commit: String,
     pub installed_size_installed: u64,
     pub installed_size_remote: u64,
     pub download_size: u64,
@@ -55,8 +63,6 @@ pub fn flatpak_update_page(
     let user_refs_for_upgrade_vec_all: Rc<RefCell<Vec<FlatpakRefRow>>> =
         Rc::new(RefCell::new(Vec::new()));
 
-    let cancellable_no = libflatpak::gio::Cancellable::NONE;
-
     thread::spawn(move || {
         let cancellable_no = libflatpak::gio::Cancellable::NONE;
         let flatpak_system_installation =
@@ -405,7 +411,6 @@ pub fn flatpak_update_page(
                 match state.as_ref() {
                     "FN_OVERRIDE_SUCCESSFUL" => {
                         get_flatpak_updates(
-                            cancellable_no,
                             &viewport_bin,
                             &update_button,
                             &select_button,
@@ -424,7 +429,6 @@ pub fn flatpak_update_page(
                     }
                     "FN_OVERRIDE_FAILED" => {
                         get_flatpak_updates(
-                            cancellable_no,
                             &viewport_bin,
                             &update_button,
                             &select_button,
@@ -543,8 +547,32 @@ fn set_all_flatpak_row_marks_to(parent_listbox: &impl adw::prelude::IsA<ListBox>
         child_counter = next_child
     }
 }
+/// Row data for a discovered update, with readable fallbacks for the missing bits.
+pub fn flatpak_ref_struct(flatpak_update: &FlatpakUpdate, is_last: bool) -> FlatpakRefStruct {
+    let unknown = |value: &Option<String>, fallback: &str| -> String {
+        value.clone().unwrap_or(fallback.to_owned())
+    };
+    FlatpakRefStruct {
+        ref_name: unknown(&flatpak_update.ref_name, "Unknown"),
+        name: unknown(&flatpak_update.name, "Unknown"),
+        arch: unknown(&flatpak_update.arch, "Unknown Arch"),
+        branch: unknown(&flatpak_update.branch, "Unknown"),
+        summary: unknown(&flatpak_update.summary, "No Summary"),
+        origin: unknown(&flatpak_update.origin, "Unknown"),
+        remote_name: unknown(&flatpak_update.remote_name, "Unknown"),
+        installed_version: unknown(&flatpak_update.installed_version, "Unknown"),
+        installed_commit: unknown(&flatpak_update.installed_commit, "Unknown"),
+        remote_commit: unknown(&flatpak_update.remote_commit, "Unknown"),
+        installed_size_installed: flatpak_update.installed_size_installed,
+        installed_size_remote: flatpak_update.installed_size_remote,
+        download_size: flatpak_update.download_size,
+        ref_format: flatpak_update.ref_format.to_owned(),
+        is_system: flatpak_update.installation == FlatpakInstallationKind::System,
+        is_last,
+    }
+}
+
 fn get_flatpak_updates(
-    cancellable_no: Option<&libflatpak::gio::Cancellable>,
     viewport_bin: &adw::Bin,
     update_button: &gtk::Button,
     select_button: &gtk::Button,
@@ -559,14 +587,12 @@ fn get_flatpak_updates(
     apt_update_count: &Rc<RefCell<i32>>,
     flatpak_update_count: &Rc<RefCell<i32>>,
 ) {
-    let flatpak_system_installation = libflatpak::Installation::new_system(cancellable_no).unwrap();
-    let flatpak_system_updates = flatpak_system_installation
-        .list_installed_refs_for_update(cancellable_no)
+    let flatpak_system_updates = LibflatpakBackend
+        .list_updates(FlatpakInstallationKind::System)
         .unwrap();
     //
-    let flatpak_user_installation = libflatpak::Installation::new_user(cancellable_no).unwrap();
-    let flatpak_user_updates = flatpak_user_installation
-        .list_installed_refs_for_update(cancellable_no)
+    let flatpak_user_updates = LibflatpakBackend
+        .list_updates(FlatpakInstallationKind::User)
         .unwrap();
     //
     let mut system_last_triggered = false;
@@ -580,72 +606,9 @@ fn get_flatpak_updates(
     if !flatpak_system_updates.is_empty() {
         let flatpak_system_updates_iter = &mut flatpak_system_updates.iter().peekable();
         //
-        while let Some(flatpak_ref) = flatpak_system_updates_iter.next() {
-            let mut remote_flatpak_ref: Option<libflatpak::RemoteRef> = None;
-            while let Ok(remotes) =
-                libflatpak::Installation::list_remotes(&flatpak_system_installation, cancellable_no)
-            {
-                for remote in remotes {
-                    if remote.is_disabled() {
-                        continue;
-                    };
-                    match libflatpak::Installation::fetch_remote_ref_sync(
-                        &flatpak_system_installation,
-                        &match remote.name() {
-                            Some(t) => t,
-                            None => continue,
-                        },
-                        flatpak_ref.kind(),
-                        &match flatpak_ref.name() {
-                            Some(t) => t,
-                            None => continue,
-                        },
-                        flatpak_ref.arch().as_deref(),
-                        flatpak_ref.branch().as_deref(),
-                        cancellable_no,
-                    ) {
-                        Ok(t) => {
-                            remote_flatpak_ref = Some(t);
-                            break;
-                        }
-                        Err(_) => continue,
-                    }
-                }
-                if remote_flatpak_ref.is_some() {
-                    break;
-                }
-            }
-            let flatref_struct = FlatpakRefStruct {
-                ref_name: flatpak_ref.name().unwrap_or("Unknown".into()).to_string(),
-                name: flatpak_ref
-                    .appdata_name()
-                    .unwrap_or(flatpak_ref.name().unwrap_or("Unknown".into()))
-                    .to_string(),
-                arch: flatpak_ref
-                    .arch()
-                    .unwrap_or("Unknown Arch".into())
-                    .to_string(),
-                summary: flatpak_ref
-                    .appdata_summary()
-                    .unwrap_or("No Summary".into())
-                    .to_string(),
-                remote_name: match remote_flatpak_ref {
-                    Some(ref t) => t.remote_name().unwrap_or("Unknown".into()).to_string(),
-                    None => "Unknown".into(),
-                },
-                installed_size_installed: flatpak_ref.installed_size(),
-                installed_size_remote: match remote_flatpak_ref {
-                    Some(ref t) => t.installed_size(),
-                    None => 0,
-                },
-                download_size: match remote_flatpak_ref {
-                    Some(t) => t.download_size(),
-                    None => 0,
-                },
-                ref_format: flatpak_ref.format_ref().unwrap().into(),
-                is_system: true,
-                is_last: flatpak_system_updates_iter.peek().is_none(),
-            };
+        while let Some(flatpak_update) = flatpak_system_updates_iter.next() {
+            let flatref_struct =
+                flatpak_ref_struct(flatpak_update, flatpak_system_updates_iter.peek().is_none());
 
             let flatpak_row = FlatpakRefRow::new(&flatref_struct);
 
@@ -716,72 +679,9 @@ fn get_flatpak_updates(
     if !flatpak_user_updates.is_empty() {
         let flatpak_user_updates_iter = &mut flatpak_user_updates.iter().peekable();
         //
-        while let Some(flatpak_ref) = flatpak_user_updates_iter.next() {
-            let mut remote_flatpak_ref: Option<libflatpak::RemoteRef> = None;
-            while let Ok(remotes) =
-                libflatpak::Installation::list_remotes(&flatpak_user_installation, cancellable_no)
-            {
-                for remote in remotes {
-                    if remote.is_disabled() {
-                        continue;
-                    };
-                    match libflatpak::Installation::fetch_remote_ref_sync(
-                        &flatpak_user_installation,
-                        &match remote.name() {
-                            Some(t) => t,
-                            None => continue,
-                        },
-                        flatpak_ref.kind(),
-                        &match flatpak_ref.name() {
-                            Some(t) => t,
-                            None => continue,
-                        },
-                        flatpak_ref.arch().as_deref(),
-                        flatpak_ref.branch().as_deref(),
-                        cancellable_no,
-                    ) {
-                        Ok(t) => {
-                            remote_flatpak_ref = Some(t);
-                            break;
-                        }
-                        Err(_) => continue,
-                    }
-                }
-                if remote_flatpak_ref.is_some() {
-                    break;
-                }
-            }
-            let flatref_struct = FlatpakRefStruct {
-                ref_name: flatpak_ref.name().unwrap_or("Unknown".into()).to_string(),
-                name: flatpak_ref
-                    .appdata_name()
-                    .unwrap_or(flatpak_ref.name().unwrap_or("Unknown".into()))
-                    .to_string(),
-                arch: flatpak_ref
-                    .arch()
-                    .unwrap_or("Unknown Arch".into())
-                    .to_string(),
-                summary: flatpak_ref
-                    .appdata_summary()
-                    .unwrap_or("No Summary".into())
-                    .to_string(),
-                remote_name: match remote_flatpak_ref {
-                    Some(ref t) => t.remote_name().unwrap_or("Unknown".into()).to_string(),
-                    None => "Unknown".into(),
-                },
-                installed_size_installed: flatpak_ref.installed_size(),
-                installed_size_remote: match remote_flatpak_ref {
-                    Some(ref t) => t.installed_size(),
-                    None => 0,
-                },
-                download_size: match remote_flatpak_ref {
-                    Some(t) => t.download_size(),
-                    None => 0,
-                },
-                ref_format: flatpak_ref.format_ref().unwrap().into(),
-                is_system: false,
-                is_last: flatpak_user_updates_iter.peek().is_none(),
-            };
+        while let Some(flatpak_update) = flatpak_user_updates_iter.next() {
+            let flatref_struct =
+                flatpak_ref_struct(flatpak_update, flatpak_user_updates_iter.peek().is_none());
 
             let flatpak_row = FlatpakRefRow::new(&flatref_struct);
 
@@ -5,6 +5,11 @@ use adw::prelude::*;
 use gtk::glib::*;
 use gtk::*;
 use libflatpak::prelude::*;
+use pika_unixsocket_tools::progress_protocol::ChangeAction;
+use pika_unixsocket_tools::session_socket::current_uid;
+use pika_unixsocket_tools::transaction_history::{
+    user_history_path, user_name, HistoryEntry, HistoryPackage, TransactionKind,
+};
 use pretty_bytes::converter::convert;
 use std::fs::OpenOptions;
 use std::io::Write;
@@ -216,6 +221,7 @@ pub fn flatpak_process_update(
                 flatpak_run_transactions(
                     system_refs_for_upgrade_vec,
                     user_refs_for_upgrade_vec,
+                    ChangeAction::Upgrade,
                     window,
                     &retry_signal_action0,
                     &theme_changed_action0,
@@ -224,9 +230,12 @@ pub fn flatpak_process_update(
         });
 }
 
-fn flatpak_run_transactions(
+/// Updates the given refs, or uninstalls them with `ChangeAction::Remove`, behind a progress
+/// dialog.
+pub fn flatpak_run_transactions(
     system_refs_for_upgrade_vec: Vec<String>,
     user_refs_for_upgrade_vec: Vec<String>,
+    action: ChangeAction,
     window: adw::ApplicationWindow,
     retry_signal_action: &SimpleAction,
     theme_changed_action: &SimpleAction,
@@ -240,6 +249,11 @@ fn flatpak_run_transactions(
 
     thread::spawn(move || {
         let cancellable_no = libflatpak::gio::Cancellable::NONE;
+        let history_entry = HistoryEntry::new(
+            TransactionKind::Flatpak,
+            user_name(current_uid()),
+            Vec::new(),
+        );
 
         let transaction_status_sender0 = transaction_status_sender.clone();
         let transaction_percent_sender0 = transaction_percent_sender.clone();
@@ -290,14 +304,30 @@ fn flatpak_run_transactions(
 
         let flatpak_system_installation =
             libflatpak::Installation::new_system(cancellable_no).unwrap();
+        let flatpak_user_installation = libflatpak::Installation::new_user(cancellable_no).unwrap();
+
+        let mut refs_before = Vec::new();
+        for ref_format in &system_refs_for_upgrade_vec {
+            refs_before.push((
+                flatpak_system_installation.clone(),
+                ref_format.to_owned(),
+                installed_flatpak_version(&flatpak_system_installation, ref_format),
+            ));
+        }
+        for ref_format in &user_refs_for_upgrade_vec {
+            refs_before.push((
+                flatpak_user_installation.clone(),
+                ref_format.to_owned(),
+                installed_flatpak_version(&flatpak_user_installation, ref_format),
+            ));
+        }
+
         let flatpak_system_transaction =
             libflatpak::Transaction::for_installation(&flatpak_system_installation, cancellable_no)
                 .unwrap();
 
         for ref_format in system_refs_for_upgrade_vec {
-            flatpak_system_transaction
-                .add_update(&ref_format, &[], None)
-                .unwrap();
+            add_flatpak_operation(&flatpak_system_transaction, &ref_format, action);
         }
 
         flatpak_system_transaction.connect_new_operation(transaction_run_closure.clone());
@@ -305,6 +335,7 @@ fn flatpak_run_transactions(
         match flatpak_system_transaction.run(cancellable_no) {
             Ok(_) => {}
             Err(e) => {
+                save_flatpak_history(history_entry, &refs_before, action, 1, e.to_string());
                 transaction_status_sender
                     .send_blocking(e.to_string())
                     .expect("transaction_sync_status_receiver closed");
@@ -317,7 +348,6 @@ fn flatpak_run_transactions(
 
         //
 
-        let flatpak_user_installation = libflatpak::Installation::new_user(cancellable_no).unwrap();
         let flatpak_user_transaction =
             libflatpak::Transaction::for_installation(&flatpak_user_installation, cancellable_no)
                 .unwrap();
@@ -325,18 +355,18 @@ fn flatpak_run_transactions(
         flatpak_user_transaction.connect_new_operation(transaction_run_closure);
 
         for ref_format in user_refs_for_upgrade_vec {
-            flatpak_user_transaction
-                .add_update(&ref_format, &[], None)
-                .unwrap();
+            add_flatpak_operation(&flatpak_user_transaction, &ref_format, action);
         }
 
         match flatpak_user_transaction.run(cancellable_no) {
             Ok(_) => {
+                save_flatpak_history(history_entry, &refs_before, action, 0, String::new());
                 transaction_status_sender
                     .send_blocking("FN_OVERRIDE_SUCCESSFUL".to_owned())
                     .expect("transaction_sync_status_receiver closed");
             }
             Err(e) => {
+                save_flatpak_history(history_entry, &refs_before, action, 1, e.to_string());
                 transaction_status_sender
                     .send_blocking(e.to_string())
                     .expect("transaction_sync_status_receiver closed");
@@ -604,3 +634,66 @@ fn create_color_badge(
     group_size.add_widget(&boxedlist);
     boxedlist
 }
+
+fn add_flatpak_operation(
+    transaction: &libflatpak::Transaction,
+    ref_format: &str,
+    action: ChangeAction,
+) {
+    match action {
+        ChangeAction::Remove => transaction.add_uninstall(ref_format).unwrap(),
+        _ => transaction.add_update(ref_format, &[], None).unwrap(),
+    }
+}
+
+/// The installed version of a ref and its short commit, the version alone often stays
+/// the same across runtime updates.
+fn installed_flatpak_version(
+    installation: &libflatpak::Installation,
+    ref_format: &str,
+) -> Option<String> {
+    let flatpak_ref = libflatpak::Ref::parse(ref_format).ok()?;
+    let installed_ref = installation
+        .installed_ref(
+            flatpak_ref.kind(),
+            &flatpak_ref.name()?,
+            flatpak_ref.arch().as_deref(),
+            flatpak_ref.branch().as_deref(),
+            libflatpak::gio::Cancellable::NONE,
+        )
+        .ok()?;
+    let commit: String = installed_ref.commit()?.chars().take(12).collect();
+    match installed_ref.appdata_version() {
+        Some(version) => Some(format!("{} ({})", version, commit)),
+        None => Some(commit),
+    }
+}
+
+/// Appends the finished transaction to the user's history, with where each ref ended up.
+fn save_flatpak_history(
+    mut history_entry: HistoryEntry,
+    refs_before: &[(libflatpak::Installation, String, Option<String>)],
+    action: ChangeAction,
+    exit_code: i32,
+    message: String,
+) {
+    history_entry.packages = refs_before
+        .iter()
+        .map(
+            |(installation, ref_format, version_before)| HistoryPackage {
+                name: ref_format.to_owned(),
+                action,
+                version_before: version_before.clone(),
+                version_after: installed_flatpak_version(installation, ref_format),
+            },
+        )
+        .collect();
+    if !message.is_empty() {
+        history_entry.log.push(message.clone());
+    }
+    history_entry.message = message;
+    history_entry.finish(exit_code);
+    if let Err(e) = history_entry.append_to(&user_history_path()) {
+        eprintln!("Couldn't save the transaction history: {}", e);
+    }
+}
@@ -0,0 +1,357 @@
+use adw::prelude::*;
+use gtk::glib::*;
+use gtk::*;
+use pika_unixsocket_tools::transaction_history::{
+    export_history, load_history, HistoryEntry, TransactionKind,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Every recorded APT and Flatpak transaction, newest first, searchable and exportable.
+pub fn history_page(
+    window: adw::ApplicationWindow,
+    apt_retry_signal_action: &gio::SimpleAction,
+    flatpak_retry_signal_action: &gio::SimpleAction,
+) -> gtk::Box {
+    let main_box = Box::builder()
+        .hexpand(true)
+        .vexpand(true)
+        .orientation(Orientation::Vertical)
+        .build();
+
+    let searchbar = SearchEntry::builder()
+        .search_delay(500)
+        .placeholder_text(t!("history_searchbar_placeholder"))
+        .margin_top(15)
+        .margin_bottom(15)
+        .margin_end(15)
+        .margin_start(15)
+        .build();
+    searchbar.add_css_class("rounded-all-25");
+
+    let history_boxedlist = ListBox::builder()
+        .selection_mode(SelectionMode::None)
+        .build();
+    history_boxedlist.add_css_class("boxed-list");
+    history_boxedlist.add_css_class("no-round-borders");
+
+    let history_viewport = ScrolledWindow::builder()
+        .vexpand(true)
+        .hexpand(true)
+        .has_frame(true)
+        .margin_bottom(15)
+        .margin_end(15)
+        .margin_start(15)
+        .child(&history_boxedlist)
+        .overflow(Overflow::Hidden)
+        .build();
+    history_viewport.add_css_class("round-all-scroll-no-padding");
+
+    let bottom_bar = Box::builder().valign(Align::End).build();
+
+    let export_button = Button::builder()
+        .halign(Align::End)
+        .valign(Align::Center)
+        .hexpand(true)
+        .margin_start(10)
+        .margin_end(30)
+        .margin_bottom(15)
+        .label(t!("history_export_button_label"))
+        .build();
+    export_button.add_css_class("suggested-action");
+
+    let history_entries: Rc<RefCell<Vec<HistoryEntry>>> = Rc::new(RefCell::new(Vec::new()));
+    let shown_history_entries: Rc<RefCell<Vec<HistoryEntry>>> = Rc::new(RefCell::new(Vec::new()));
+
+    let history_filter_action = gio::SimpleAction::new("history_filter", None);
+    history_filter_action.connect_activate(clone!(
+        #[strong]
+        history_entries,
+        #[strong]
+        shown_history_entries,
+        #[weak]
+        searchbar,
+        #[weak]
+        history_boxedlist,
+        #[weak]
+        export_button,
+        move |_, _| {
+            history_boxedlist.remove_all();
+            let query = searchbar.text().to_string();
+            let mut shown_history_entries = shown_history_entries.borrow_mut();
+            *shown_history_entries = history_entries
+                .borrow()
+                .iter()
+                .filter(|t| t.matches(&query))
+                .cloned()
+                .collect();
+            export_button.set_sensitive(!shown_history_entries.is_empty());
+            if shown_history_entries.is_empty() {
+                history_boxedlist.append(
+                    &adw::ActionRow::builder()
+                        .title(t!("history_empty_label"))
+                        .build(),
+                );
+            }
+            for history_entry in shown_history_entries.iter() {
+                history_boxedlist.append(&history_entry_row(history_entry));
+            }
+        }
+    ));
+
+    let history_reload_action = gio::SimpleAction::new("history_reload", None);
+    history_reload_action.connect_activate(clone!(
+        #[strong]
+        history_entries,
+        #[strong]
+        history_filter_action,
+        move |_, _| {
+            *history_entries.borrow_mut() = load_history();
+            history_filter_action.activate(None);
+        }
+    ));
+    history_reload_action.activate(None);
+
+    // A finished transaction always ends in a reload of its update page
+    for retry_signal_action in [apt_retry_signal_action, flatpak_retry_signal_action] {
+        retry_signal_action.connect_activate(clone!(
+            #[strong]
+            history_reload_action,
+            move |_, _| {
+                history_reload_action.activate(None);
+            }
+        ));
+    }
+
+    main_box.connect_map(clone!(
+        #[strong]
+        history_reload_action,
+        move |_| {
+            history_reload_action.activate(None);
+        }
+    ));
+
+    searchbar.connect_search_changed(clone!(
+        #[strong]
+        history_filter_action,
+        move |_| {
+            history_filter_action.activate(None);
+        }
+    ));
+
+    #[allow(deprecated)]
+    let history_export_file_dialog = gtk::FileChooserNative::builder()
+        .title(t!("history_export_file_dialog_title"))
+        .accept_label(t!("history_export_file_dialog_accept_label"))
+        .cancel_label(t!("history_export_file_dialog_cancel_label"))
+        .action(gtk::FileChooserAction::Save)
+        .transient_for(&window)
+        .modal(true)
+        .build();
+
+    history_export_file_dialog.connect_response(clone!(
+        #[strong]
+        window,
+        #[strong]
+        shown_history_entries,
+        move |dialog, response| {
+            if response != gtk::ResponseType::Accept {
+                return;
+            }
+            #[allow(deprecated)]
+            let path = match dialog.file().and_then(|t| t.path()) {
+                Some(t) => t,
+                None => return,
+            };
+            if let Err(e) = export_history(&shown_history_entries.borrow(), &path) {
+                let history_export_failed_dialog = adw::MessageDialog::builder()
+                    .heading(t!("history_export_failed_heading"))
+                    .body(e.to_string())
+                    .transient_for(&window)
+                    .build();
+                history_export_failed_dialog.add_response(
+                    "history_export_failed_dialog_ok",
+                    &t!("history_export_failed_dialog_ok_label").to_string(),
+                );
+                history_export_failed_dialog.present();
+            }
+        }
+    ));
+
+    export_button.connect_clicked(clone!(
+        #[strong]
+        history_export_file_dialog,
+        move |_| {
+            #[allow(deprecated)]
+            history_export_file_dialog.set_current_name(&format!(
+                "pikman-update-manager-history_{}.json",
+                chrono::offset::Local::now().format("%Y-%m-%d")
+            ));
+            history_export_file_dialog.set_visible(true);
+        }
+    ));
+
+    bottom_bar.append(&export_button);
+
+    main_box.append(&searchbar);
+    main_box.append(&history_viewport);
+    main_box.append(&bottom_bar);
+
+    main_box
+}
+
+fn history_entry_row(history_entry: &HistoryEntry) -> adw::ExpanderRow {
+    let started_at = chrono::DateTime::parse_from_rfc3339(&history_entry.started_at)
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|_| history_entry.started_at.to_owned());
+    let kind = match history_entry.kind {
+        TransactionKind::Apt => t!("history_kind_apt"),
+        TransactionKind::Flatpak => t!("history_kind_flatpak"),
+    }
+    .to_string();
+    let result = if history_entry.is_success() {
+        t!("history_result_successful").to_string()
+    } else {
+        strfmt::strfmt(
+            &t!("history_result_failed").to_string(),
+            &HashMap::from([("CODE".to_string(), history_entry.exit_code.to_string())]),
+        )
+        .unwrap()
+    };
+
+    let history_entry_row = adw::ExpanderRow::builder()
+        .title(
+            strfmt::strfmt(
+                &t!("history_row_title").to_string(),
+                &HashMap::from([("DATE".to_string(), started_at), ("KIND".to_string(), kind)]),
+            )
+            .unwrap(),
+        )
+        .subtitle(
+            strfmt::strfmt(
+                &t!("history_row_subtitle").to_string(),
+                &HashMap::from([
+                    ("USER".to_string(), history_entry.user.to_owned()),
+                    ("NUM".to_string(), history_entry.packages.len().to_string()),
+                    ("RESULT".to_string(), result),
+                ]),
+            )
+            .unwrap(),
+        )
+        .build();
+    history_entry_row.add_prefix(
+        &gtk::Image::builder()
+            .icon_name(if history_entry.is_success() {
+                "emblem-default-symbolic"
+            } else {
+                "dialog-error-symbolic"
+            })
+            .build(),
+    );
+
+    for package in &history_entry.packages {
+        history_entry_row.add_row(
+            &adw::ActionRow::builder()
+                .title(&package.name)
+                .subtitle(format!(
+                    "{}: {} → {}",
+                    package.action.as_str(),
+                    package.version_before.as_deref().unwrap_or("-"),
+                    package.version_after.as_deref().unwrap_or("-")
+                ))
+                .use_markup(false)
+                .build(),
+        );
+    }
+
+    let mut details = Vec::new();
+    if history_entry.offline {
+        details.push(t!("history_offline_label").to_string());
+    }
+    if history_entry.repair {
+        details.push(t!("history_repair_label").to_string());
+    }
+    if let Some(snapshot_id) = &history_entry.snapshot_id {
+        details.push(
+            strfmt::strfmt(
+                &t!("history_snapshot_label").to_string(),
+                &HashMap::from([("ID".to_string(), snapshot_id.to_owned())]),
+            )
+            .unwrap(),
+        );
+    }
+    let exclusions: Vec<String> = history_entry
+        .exclusions
+        .exclusions
+        .iter()
+        .map(|t| t.package.to_owned())
+        .chain(
+            history_entry
+                .exclusions
+                .holds
+                .iter()
+                .map(|t| t.package.to_owned()),
+        )
+        .collect();
+    if !exclusions.is_empty() {
+        details.push(
+            strfmt::strfmt(
+                &t!("history_exclusions_label").to_string(),
+                &HashMap::from([("PACKAGES".to_string(), exclusions.join(", "))]),
+            )
+            .unwrap(),
+        );
+    }
+    if !history_entry.unresolved_conffiles.is_empty() {
+        details.push(
+            strfmt::strfmt(
+                &t!("history_unresolved_conffiles_label").to_string(),
+                &HashMap::from([(
+                    "FILES".to_string(),
+                    history_entry.unresolved_conffiles.join(", "),
+                )]),
+            )
+            .unwrap(),
+        );
+    }
+    if !history_entry.message.is_empty() {
+        details.push(history_entry.message.to_owned());
+    }
+    if !details.is_empty() {
+        history_entry_row.add_row(
+            &gtk::Label::builder()
+                .label(details.join("\n"))
+                .halign(Align::Start)
+                .wrap(true)
+                .selectable(true)
+                .margin_top(10)
+                .margin_bottom(10)
+                .margin_start(10)
+                .margin_end(10)
+                .build(),
+        );
+    }
+
+    if !history_entry.log.is_empty() {
+        let history_log_label = gtk::Label::builder()
+            .label(history_entry.log.join("\n"))
+            .halign(Align::Start)
+            .wrap(true)
+            .selectable(true)
+            .build();
+        history_log_label.add_css_class("monospace");
+        history_entry_row.add_row(
+            &Expander::builder()
+                .label(t!("history_log_label"))
+                .child(&history_log_label)
+                .margin_top(10)
+                .margin_bottom(10)
+                .margin_start(10)
+                .margin_end(10)
+                .build(),
+        );
+    }
+
+    history_entry_row
+}
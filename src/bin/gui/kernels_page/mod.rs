@@ -0,0 +1,246 @@
+use crate::config::APP_ID;
+use adw::prelude::*;
+use gtk::glib::*;
+use gtk::*;
+use pika_unixsocket_tools::kernels::{
+    kernels_to_remove, kernels_with_upgrade, running_kernel_release, InstalledKernel,
+};
+use pretty_bytes::converter::convert;
+use std::thread;
+
+/// Installed kernels, the running one and the ones the pending upgrade brings in, with the
+/// policy that decides how many of them stay.
+pub fn kernels_page(apt_retry_signal_action: &gio::SimpleAction) -> gtk::Box {
+    let glib_settings = gio::Settings::new(APP_ID);
+
+    let main_box = Box::builder()
+        .hexpand(true)
+        .vexpand(true)
+        .orientation(Orientation::Vertical)
+        .build();
+
+    let running_kernel_label = Label::builder()
+        .halign(Align::Start)
+        .wrap(true)
+        .margin_top(15)
+        .margin_bottom(15)
+        .margin_start(15)
+        .margin_end(15)
+        .build();
+    running_kernel_label.add_css_class("heading");
+
+    let kernels_boxedlist = ListBox::builder()
+        .selection_mode(SelectionMode::None)
+        .build();
+    kernels_boxedlist.add_css_class("boxed-list");
+    kernels_boxedlist.add_css_class("no-round-borders");
+
+    let kernels_viewport = ScrolledWindow::builder()
+        .vexpand(true)
+        .hexpand(true)
+        .has_frame(true)
+        .margin_bottom(15)
+        .margin_end(15)
+        .margin_start(15)
+        .child(&kernels_boxedlist)
+        .overflow(Overflow::Hidden)
+        .build();
+    kernels_viewport.add_css_class("round-all-scroll-no-padding");
+
+    let kernel_policy_prefrencesgroup = adw::PreferencesGroup::builder()
+        .title(t!("kernel_policy_group_title"))
+        .margin_bottom(15)
+        .margin_end(15)
+        .margin_start(15)
+        .build();
+
+    let remove_old_kernels_switchrow = adw::SwitchRow::builder()
+        .title(t!("remove_old_kernels_switchrow_title"))
+        .subtitle(t!("remove_old_kernels_switchrow_subtitle"))
+        .build();
+    glib_settings
+        .bind(
+            "remove-old-kernels",
+            &remove_old_kernels_switchrow,
+            "active",
+        )
+        .build();
+
+    let kernels_to_keep_spinrow = adw::SpinRow::builder()
+        .title(t!("kernels_to_keep_spinrow_title"))
+        .subtitle(t!("kernels_to_keep_spinrow_subtitle"))
+        .climb_rate(1.0)
+        .adjustment(&gtk::Adjustment::new(
+            glib_settings.uint("kernels-to-keep") as f64,
+            1.0,
+            10.0,
+            1.0,
+            0.0,
+            0.0,
+        ))
+        .build();
+
+    kernel_policy_prefrencesgroup.add(&remove_old_kernels_switchrow);
+    kernel_policy_prefrencesgroup.add(&kernels_to_keep_spinrow);
+
+    let (kernels_sender, kernels_receiver) =
+        async_channel::unbounded::<Result<Vec<InstalledKernel>, String>>();
+
+    let kernels_reload_action = gio::SimpleAction::new("kernels_reload", None);
+    kernels_reload_action.connect_activate(clone!(
+        #[weak]
+        running_kernel_label,
+        #[weak]
+        kernels_boxedlist,
+        #[strong]
+        kernels_sender,
+        move |_, _| {
+            running_kernel_label.set_label(
+                &strfmt::strfmt(
+                    &t!("running_kernel_label_label").to_string(),
+                    &std::collections::HashMap::from([(
+                        "RELEASE".to_string(),
+                        running_kernel_release()
+                            .unwrap_or_else(|| t!("running_kernel_unknown").to_string()),
+                    )]),
+                )
+                .unwrap(),
+            );
+            kernels_boxedlist.remove_all();
+            kernels_boxedlist.append(
+                &adw::ActionRow::builder()
+                    .title(t!("kernels_loading_label"))
+                    .build(),
+            );
+            let kernels_sender = kernels_sender.clone();
+            thread::spawn(move || {
+                kernels_sender
+                    .send_blocking(kernels_with_upgrade())
+                    .expect("kernels_receiver closed");
+            });
+        }
+    ));
+
+    let kernels_context = MainContext::default();
+    // The main loop executes the asynchronous block
+    kernels_context.spawn_local(clone!(
+        #[weak]
+        kernels_boxedlist,
+        #[strong]
+        glib_settings,
+        async move {
+            while let Ok(state) = kernels_receiver.recv().await {
+                kernels_boxedlist.remove_all();
+                let kernels = match state {
+                    Ok(t) => t,
+                    Err(e) => {
+                        kernels_boxedlist.append(
+                            &adw::ActionRow::builder()
+                                .title(t!("kernels_error_label"))
+                                .subtitle(glib::markup_escape_text(&e))
+                                .build(),
+                        );
+                        continue;
+                    }
+                };
+                let removed_releases: Vec<String> = if glib_settings.boolean("remove-old-kernels") {
+                    kernels_to_remove(&kernels, glib_settings.uint("kernels-to-keep") as usize)
+                        .into_iter()
+                        .map(|t| t.release)
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                for kernel in &kernels {
+                    kernels_boxedlist.append(&kernel_row(
+                        kernel,
+                        removed_releases.contains(&kernel.release),
+                    ));
+                }
+            }
+        }
+    ));
+
+    kernels_to_keep_spinrow.connect_value_notify(clone!(
+        #[strong]
+        glib_settings,
+        #[strong]
+        kernels_reload_action,
+        move |spinrow| match glib_settings.set_uint("kernels-to-keep", spinrow.value() as u32) {
+            Ok(_) => kernels_reload_action.activate(None),
+            Err(_) => spinrow.set_value(glib_settings.uint("kernels-to-keep") as f64),
+        }
+    ));
+
+    remove_old_kernels_switchrow.connect_active_notify(clone!(
+        #[strong]
+        kernels_reload_action,
+        move |_| {
+            kernels_reload_action.activate(None);
+        }
+    ));
+
+    // Upgrades install and remove kernels, a hidden page catches up once it is shown
+    apt_retry_signal_action.connect_activate(clone!(
+        #[weak]
+        main_box,
+        #[strong]
+        kernels_reload_action,
+        move |_, _| {
+            if main_box.is_mapped() {
+                kernels_reload_action.activate(None);
+            }
+        }
+    ));
+
+    main_box.connect_map(clone!(
+        #[strong]
+        kernels_reload_action,
+        move |_| {
+            kernels_reload_action.activate(None);
+        }
+    ));
+
+    main_box.append(&running_kernel_label);
+    main_box.append(&kernels_viewport);
+    main_box.append(&kernel_policy_prefrencesgroup);
+
+    main_box
+}
+
+fn kernel_row(kernel: &InstalledKernel, removed_by_policy: bool) -> adw::ActionRow {
+    let kernel_row = adw::ActionRow::builder()
+        .title(&kernel.release)
+        .use_markup(false)
+        .subtitle(
+            strfmt::strfmt(
+                &t!("kernel_row_subtitle").to_string(),
+                &std::collections::HashMap::from([
+                    ("VERSION".to_string(), kernel.version.to_owned()),
+                    ("SIZE".to_string(), convert(kernel.installed_size as f64)),
+                ]),
+            )
+            .unwrap(),
+        )
+        .build();
+    let mut tags = Vec::new();
+    if kernel.running {
+        tags.push((t!("kernel_running_tag"), "success-color-text"));
+    }
+    if kernel.pending {
+        tags.push((t!("kernel_pending_tag"), "warning-color-text"));
+    }
+    if removed_by_policy {
+        tags.push((t!("kernel_removed_tag"), "destructive-color-text"));
+    }
+    for (label, css_class) in tags {
+        let tag_label = Label::builder()
+            .label(label)
+            .valign(Align::Center)
+            .margin_start(5)
+            .build();
+        tag_label.add_css_class(css_class);
+        kernel_row.add_suffix(&tag_label);
+    }
+    kernel_row
+}
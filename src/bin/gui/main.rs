@@ -2,10 +2,14 @@ mod apt_manage_page;
 mod apt_package_row;
 mod apt_update_page;
 mod build_ui;
+mod cleanup_page;
+mod cli;
 mod config;
 mod flatpak_manage_page;
 mod flatpak_ref_row;
 mod flatpak_update_page;
+mod history_page;
+mod kernels_page;
 mod main_update_page;
 
 use crate::config::APP_ID;
@@ -24,16 +28,22 @@ i18n!("locales", fallback = "en_US");
 
 /// main function
 fn main() {
-    let current_locale = match env::var_os("LANG") {
-        Some(v) => v
-            .into_string()
-            .unwrap()
-            .chars()
-            .take_while(|&ch| ch != '.')
-            .collect::<String>(),
-        None => panic!("$LANG is not set"),
+    // Cron jobs, timers and bare SSH sessions often have no $LANG, like the helpers we fall
+    // back to English then
+    let current_locale = match env::var("LANG") {
+        Ok(v) if !v.is_empty() => v.chars().take_while(|&ch| ch != '.').collect::<String>(),
+        _ => "en_US".to_owned(),
     };
     rust_i18n::set_locale(&current_locale);
+
+    // The terminal frontends must work without a display, so it never reaches GTK
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(|t| t.as_str()) {
+        Some("--cli") => std::process::exit(cli::run_cli(&args[2..])),
+        Some("--list-updates") => std::process::exit(cli::run_list_updates(&args[2..])),
+        _ => {}
+    }
+
     let application =
         adw::Application::new(Some(APP_ID), gio::ApplicationFlags::HANDLES_COMMAND_LINE);
     application.connect_startup(|app| {
@@ -11,6 +11,7 @@ pub fn main_update_page(
     initiated_by_main: &Rc<RefCell<bool>>,
     theme_changed_action: &gio::SimpleAction,
     update_sys_tray: &gio::SimpleAction,
+    apt_security_update_count: &Rc<RefCell<i32>>,
 ) -> gtk::Box {
     let main_box = Box::builder()
         .hexpand(true)
@@ -71,6 +72,16 @@ pub fn main_update_page(
         &update_badge_box_size_group1,
     ));
     //
+    update_badge_box.append(&create_color_badge(
+        &t!("update_badge_box_apt_security_label"),
+        &t!("pikman_indicator_flatpak_item_label_calculating"),
+        "background-accent-bg",
+        &theme_changed_action,
+        &update_badge_box_size_group,
+        &update_badge_box_size_group0,
+        &update_badge_box_size_group1,
+    ));
+    //
     update_badge_box.append(&create_color_badge(
         &t!("update_badge_box_flatpak_label"),
         &t!("pikman_indicator_flatpak_item_label_calculating"),
@@ -90,6 +101,8 @@ pub fn main_update_page(
         header_label,
         #[strong]
         theme_changed_action,
+        #[strong]
+        apt_security_update_count,
         move |_, param| {
             let array: &[i32] = param.unwrap().fixed_array().unwrap();
             let vec = array.to_vec();
@@ -134,6 +147,19 @@ pub fn main_update_page(
                 &update_badge_box_size_group1,
             ));
             //
+            update_badge_box.append(&create_color_badge(
+                &t!("update_badge_box_apt_security_label"),
+                &match apt_update_count {
+                    -1 => t!("pikman_indicator_flatpak_item_label_calculating").into(),
+                    _ => apt_security_update_count.borrow().to_string(),
+                },
+                "background-accent-bg",
+                &theme_changed_action,
+                &update_badge_box_size_group,
+                &update_badge_box_size_group0,
+                &update_badge_box_size_group1,
+            ));
+            //
             update_badge_box.append(&create_color_badge(
                 &t!("update_badge_box_flatpak_label"),
                 &match flatpak_update_count {
@@ -0,0 +1,399 @@
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+
+/// One `package (version) distributions; urgency=...` block of a Debian changelog.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangelogEntry {
+    pub source: String,
+    pub version: String,
+    pub distributions: String,
+    pub urgency: Option<String>,
+    pub changes: Vec<String>,
+    pub maintainer: Option<String>,
+    pub date: Option<String>,
+}
+
+impl ChangelogEntry {
+    /// Formats the entry back the way it appears in `changelog.Debian`.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{} ({}) {}", self.source, self.version, self.distributions);
+        if let Some(urgency) = &self.urgency {
+            text.push_str(&format!("; urgency={}", urgency));
+        }
+        text.push_str("\n\n");
+        for line in &self.changes {
+            text.push_str(line);
+            text.push('\n');
+        }
+        if let Some(maintainer) = &self.maintainer {
+            text.push_str(&format!("\n -- {}", maintainer));
+            if let Some(date) = &self.date {
+                text.push_str(&format!("  {}", date));
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+fn parse_header(line: &str) -> Option<ChangelogEntry> {
+    let header_regex = regex::Regex::new(r"^(\S+) \(([^()\s]+)\) ([^;]*)(?:;\s*(.*))?$").unwrap();
+    let captures = header_regex.captures(line)?;
+    let urgency = captures.get(4).and_then(|metadata| {
+        metadata
+            .as_str()
+            .split(',')
+            .filter_map(|t| t.trim().split_once('='))
+            .find(|(key, _)| key.eq_ignore_ascii_case("urgency"))
+            .map(|(_, value)| value.to_owned())
+    });
+    Some(ChangelogEntry {
+        source: captures[1].to_owned(),
+        version: captures[2].to_owned(),
+        distributions: captures[3].trim().to_owned(),
+        urgency,
+        changes: Vec::new(),
+        maintainer: None,
+        date: None,
+    })
+}
+
+/// Parses a Debian changelog, newest entry first, skipping anything it does not understand.
+pub fn parse_changelog(text: &str) -> Vec<ChangelogEntry> {
+    let mut entries: Vec<ChangelogEntry> = Vec::new();
+    let mut current: Option<ChangelogEntry> = None;
+
+    for line in text.lines() {
+        if !line.starts_with(char::is_whitespace) && !line.is_empty() {
+            if let Some(entry) = parse_header(line) {
+                entries.extend(current.take());
+                current = Some(entry);
+            }
+            continue;
+        }
+        let entry = match current.as_mut() {
+            Some(t) => t,
+            None => continue,
+        };
+        if let Some(trailer) = line.strip_prefix(" -- ") {
+            let (maintainer, date) = match trailer.split_once("  ") {
+                Some((maintainer, date)) => (maintainer, Some(date.trim().to_owned())),
+                None => (trailer, None),
+            };
+            entry.maintainer = Some(maintainer.trim().to_owned());
+            entry.date = date;
+            entries.extend(current.take());
+        } else if !line.trim().is_empty() || !entry.changes.is_empty() {
+            entry.changes.push(line.trim_end().to_owned());
+        }
+    }
+    entries.extend(current);
+
+    for entry in &mut entries {
+        while entry.changes.last().is_some_and(|t| t.is_empty()) {
+            entry.changes.pop();
+        }
+    }
+    entries
+}
+
+/// The entries an upgrade from `installed_version` brings in, only the newest one for fresh installs.
+pub fn entries_newer_than(
+    entries: &[ChangelogEntry],
+    installed_version: Option<&str>,
+    compare_versions: impl Fn(&str, &str) -> Ordering,
+) -> Vec<ChangelogEntry> {
+    match installed_version {
+        Some(installed_version) => entries
+            .iter()
+            .take_while(|t| compare_versions(&t.version, installed_version) == Ordering::Greater)
+            .cloned()
+            .collect(),
+        None => entries.iter().take(1).cloned().collect(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangelogReferenceKind {
+    Cve,
+    DebianBug,
+    LaunchpadBug,
+}
+
+/// A CVE or bug number mentioned in a changelog, `start..end` is the byte range in the text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangelogReference {
+    pub kind: ChangelogReferenceKind,
+    pub id: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl ChangelogReference {
+    pub fn url(&self) -> String {
+        match self.kind {
+            ChangelogReferenceKind::Cve => {
+                format!("https://security-tracker.debian.org/tracker/{}", self.id)
+            }
+            ChangelogReferenceKind::DebianBug => format!("https://bugs.debian.org/{}", self.id),
+            ChangelogReferenceKind::LaunchpadBug => {
+                format!("https://bugs.launchpad.net/bugs/{}", self.id)
+            }
+        }
+    }
+}
+
+/// Finds `CVE-YYYY-NNNN`, `Closes: #N, #M` and `LP: #N` references, in text order.
+pub fn find_references(text: &str) -> Vec<ChangelogReference> {
+    let cve_regex = regex::Regex::new(r"CVE-\d{4}-\d{4,}").unwrap();
+    let bug_list_regex =
+        regex::Regex::new(r"(?i)(closes|lp):\s*(?:bug)?#?\d+(?:,\s*(?:bug)?#?\d+)*").unwrap();
+    let bug_regex = regex::Regex::new(r"#?(\d+)").unwrap();
+
+    let mut references: Vec<ChangelogReference> = cve_regex
+        .find_iter(text)
+        .map(|t| ChangelogReference {
+            kind: ChangelogReferenceKind::Cve,
+            id: t.as_str().to_owned(),
+            start: t.start(),
+            end: t.end(),
+        })
+        .collect();
+
+    for bug_list in bug_list_regex.captures_iter(text) {
+        let kind = if bug_list[1].eq_ignore_ascii_case("lp") {
+            ChangelogReferenceKind::LaunchpadBug
+        } else {
+            ChangelogReferenceKind::DebianBug
+        };
+        let bug_list = bug_list.get(0).unwrap();
+        // Skip the "Closes:" keyword itself, so only the numbers are matched
+        let offset = bug_list.as_str().find(':').unwrap() + 1;
+        for bug in bug_regex.captures_iter(&bug_list.as_str()[offset..]) {
+            let bug_match = bug.get(0).unwrap();
+            references.push(ChangelogReference {
+                kind,
+                id: bug[1].to_owned(),
+                start: bug_list.start() + offset + bug_match.start(),
+                end: bug_list.start() + offset + bug_match.end(),
+            });
+        }
+    }
+
+    references.sort_by_key(|t| t.start);
+    references
+}
+
+/// Where changelogs come from: the local cache, a downloaded `.deb`, then the archive's changelog URL.
+pub struct ChangelogFetcher {
+    pub cache_dir: PathBuf,
+    pub archives_dir: PathBuf,
+}
+
+impl Default for ChangelogFetcher {
+    fn default() -> Self {
+        let cache_dir = match std::env::var("XDG_CACHE_HOME") {
+            Ok(t) if !t.is_empty() => PathBuf::from(t),
+            _ => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(".cache"),
+        };
+        Self {
+            cache_dir: cache_dir.join("pikman-update-manager").join("changelogs"),
+            archives_dir: PathBuf::from("/var/cache/apt/archives"),
+        }
+    }
+}
+
+impl ChangelogFetcher {
+    fn cache_path(&self, package: &str, version: &str) -> PathBuf {
+        self.cache_dir.join(format!(
+            "{}_{}.changelog",
+            package,
+            version.replace(':', "%3a")
+        ))
+    }
+
+    /// Fetches the full changelog of `package` at `version`, caching whatever was found.
+    pub fn fetch(&self, package: &str, arch: &str, version: &str) -> Result<String, String> {
+        let cache_path = self.cache_path(package, version);
+        if let Ok(t) = std::fs::read_to_string(&cache_path) {
+            return Ok(t);
+        }
+
+        let changelog = match self.read_downloaded_deb(package, arch, version) {
+            Some(t) => t,
+            None => download_changelog(package, version)?,
+        };
+
+        if std::fs::create_dir_all(&self.cache_dir).is_ok() {
+            if let Err(e) = std::fs::write(&cache_path, &changelog) {
+                eprintln!("Could not cache {}: {}", cache_path.display(), e);
+            }
+        }
+        Ok(changelog)
+    }
+
+    fn read_downloaded_deb(&self, package: &str, arch: &str, version: &str) -> Option<String> {
+        let deb_path = [arch, "all"]
+            .iter()
+            .map(|arch| {
+                self.archives_dir.join(format!(
+                    "{}_{}_{}.deb",
+                    package,
+                    version.replace(':', "%3a"),
+                    arch
+                ))
+            })
+            .find(|t| t.exists())?;
+        ["changelog.Debian.gz", "changelog.gz"]
+            .iter()
+            .find_map(|file| read_deb_member(&deb_path, package, file))
+    }
+}
+
+fn read_deb_member(deb_path: &Path, package: &str, file: &str) -> Option<String> {
+    let member = format!("./usr/share/doc/{}/{}", package, file);
+    duct::cmd!("dpkg-deb", "--fsys-tarfile", deb_path)
+        .pipe(duct::cmd!("tar", "-xO", member))
+        .pipe(duct::cmd!("gunzip", "-c"))
+        .stderr_null()
+        .read()
+        .ok()
+        .filter(|t| !t.trim().is_empty())
+}
+
+fn download_changelog(package: &str, version: &str) -> Result<String, String> {
+    let print_uris = duct::cmd!(
+        "apt-get",
+        "changelog",
+        "--print-uris",
+        format!("{}={}", package, version)
+    )
+    .stderr_to_stdout()
+    .read()
+    .map_err(|e| e.to_string())?;
+    // apt prints `'https://.../changelog' package.changelog`
+    let uri = print_uris
+        .lines()
+        .find_map(|t| {
+            t.strip_prefix('\'')?
+                .split_once('\'')
+                .map(|t| t.0.to_owned())
+        })
+        .ok_or(print_uris.trim().to_owned())?;
+
+    let response = reqwest::blocking::get(&uri).map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("{}: {}", uri, response.status()));
+    }
+    response.text().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANGELOG: &str = "\
+mesa (24.2.1-1pika1) pika; urgency=high
+
+  * New upstream release.
+  * Fix GPU hang on resume (Closes: #1071234, #1071235).
+  * Backport fix for CVE-2024-1234 and CVE-2024-56789.
+
+ -- Pika Maintainers <maintainers@pika-os.com>  Tue, 03 Sep 2024 10:00:00 +0000
+
+mesa (24.1.5-1pika1) pika; urgency=medium
+
+  * Fix crash on startup. LP: #2070001
+
+ -- Pika Maintainers <maintainers@pika-os.com>  Mon, 05 Aug 2024 10:00:00 +0000
+
+mesa (24.1.0-1) unstable; urgency=medium
+
+  * Initial upload.
+
+ -- Debian X Strike Force <debian-x@lists.debian.org>  Mon, 20 May 2024 10:00:00 +0000
+";
+
+    fn compare_versions(a: &str, b: &str) -> Ordering {
+        a.cmp(b)
+    }
+
+    #[test]
+    fn changelog_is_parsed_newest_first() {
+        let entries = parse_changelog(CHANGELOG);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].source, "mesa");
+        assert_eq!(entries[0].version, "24.2.1-1pika1");
+        assert_eq!(entries[0].distributions, "pika");
+        assert_eq!(entries[0].urgency.as_deref(), Some("high"));
+        assert_eq!(entries[0].changes.len(), 3);
+        assert_eq!(
+            entries[0].maintainer.as_deref(),
+            Some("Pika Maintainers <maintainers@pika-os.com>")
+        );
+        assert_eq!(
+            entries[0].date.as_deref(),
+            Some("Tue, 03 Sep 2024 10:00:00 +0000")
+        );
+        assert_eq!(
+            parse_changelog(&entries[1].to_text()),
+            vec![entries[1].clone()]
+        );
+    }
+
+    #[test]
+    fn only_entries_newer_than_installed_are_kept() {
+        let entries = parse_changelog(CHANGELOG);
+        let newer = entries_newer_than(&entries, Some("24.1.5-1pika1"), compare_versions);
+        assert_eq!(newer.len(), 1);
+        assert_eq!(newer[0].version, "24.2.1-1pika1");
+
+        let newer = entries_newer_than(&entries, Some("24.1.0-1"), compare_versions);
+        assert_eq!(newer.len(), 2);
+
+        let newer = entries_newer_than(&entries, None, compare_versions);
+        assert_eq!(newer.len(), 1);
+    }
+
+    #[test]
+    fn cve_and_bug_references_are_found() {
+        let references = find_references(CHANGELOG);
+        let found: Vec<(ChangelogReferenceKind, &str)> =
+            references.iter().map(|t| (t.kind, t.id.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (ChangelogReferenceKind::DebianBug, "1071234"),
+                (ChangelogReferenceKind::DebianBug, "1071235"),
+                (ChangelogReferenceKind::Cve, "CVE-2024-1234"),
+                (ChangelogReferenceKind::Cve, "CVE-2024-56789"),
+                (ChangelogReferenceKind::LaunchpadBug, "2070001"),
+            ]
+        );
+        assert_eq!(
+            &CHANGELOG[references[0].start..references[0].end],
+            "#1071234"
+        );
+        assert_eq!(
+            &CHANGELOG[references[2].start..references[2].end],
+            "CVE-2024-1234"
+        );
+    }
+
+    #[test]
+    fn cached_changelogs_are_used() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("pikman-changelog-test-{}", std::process::id()));
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        std::fs::write(cache_dir.join("mesa_1%3a24.2.1-1.changelog"), CHANGELOG).unwrap();
+        let fetcher = ChangelogFetcher {
+            cache_dir: cache_dir.clone(),
+            archives_dir: cache_dir.join("archives"),
+        };
+        assert_eq!(
+            fetcher.fetch("mesa", "amd64", "1:24.2.1-1").unwrap(),
+            CHANGELOG
+        );
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+}
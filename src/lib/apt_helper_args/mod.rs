@@ -0,0 +1,441 @@
+use crate::snapshots::SnapshotBackendKind;
+use std::env;
+use std::path::PathBuf;
+
+/// Exit code for a command line that could not be parsed.
+pub const EXIT_CODE_USAGE: i32 = 2;
+
+/// Wrapper behind the polkit action any local user may run without a password.
+pub const APT_FULL_UPGRADE_PATH: &str =
+    "/usr/lib/pika/pikman-update-manager/scripts/apt_full_upgrade";
+/// The polkit action of `APT_FULL_UPGRADE_PATH`.
+pub const APT_FULL_UPGRADE_ACTION_ID: &str =
+    "com.github.pikaos-linux.pikmanupdatemanager.upgrade.manager";
+/// Wrapper behind the polkit action that asks for an administrator first.
+pub const APT_FULL_UPGRADE_ADMIN_PATH: &str =
+    "/usr/lib/pika/pikman-update-manager/scripts/apt_full_upgrade_admin";
+/// Set by the wrapper that needs no password, the helper refuses what
+/// [`AptHelperArgs::needs_admin`] covers under it.
+pub const UNAUTHENTICATED_ENV: &str = "PIKMAN_UPDATE_MANAGER_UNAUTHENTICATED";
+
+/// How a helper prints its progress to the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleOutput {
+    Silent,
+    Text,
+    Json,
+    /// Text, plus progress on the boot splash while upgrading offline.
+    Plymouth,
+}
+
+/// Command line of `apt_update` and `apt_full_upgrade`.
+///
+/// For compatibility with older GUIs the locale and the socket directory are also
+/// accepted as the first two positional arguments.
+#[derive(Debug, Clone, Default)]
+pub struct AptHelperArgs {
+    pub locale: Option<String>,
+    pub socket_dir: Option<PathBuf>,
+    pub exclusions_file: Option<PathBuf>,
+    pub dry_run: bool,
+    pub download_only: bool,
+    pub stage_offline: bool,
+    pub offline: bool,
+    /// Finish what an interrupted dpkg run left and fix broken dependencies, upgrade nothing.
+    pub repair: bool,
+    /// Only do the removals asked for below, upgrade nothing.
+    pub cleanup: bool,
+    pub autoremove: bool,
+    /// Remove installed kernels beyond this many of the newest, never the running one.
+    pub remove_old_kernels: Option<u32>,
+    /// Delete the downloaded archives in the APT cache.
+    pub clean_cache: bool,
+    pub include_phased_updates: bool,
+    /// Take a snapshot with this backend right before installing.
+    pub snapshot: Option<SnapshotBackendKind>,
+    pub json: bool,
+    pub assume_yes: bool,
+    pub no_progress_socket: bool,
+    pub help: bool,
+}
+
+impl AptHelperArgs {
+    pub fn from_env() -> Result<Self, String> {
+        Self::parse(env::args().skip(1))
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut positional: Vec<String> = Vec::new();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if arg.starts_with("--") => {
+                    (flag.to_owned(), Some(value.to_owned()))
+                }
+                _ => (arg.clone(), None),
+            };
+            let mut value = |name: &str| -> Result<String, String> {
+                match inline_value.clone().or_else(|| args.next()) {
+                    Some(t) if !t.is_empty() => Ok(t),
+                    _ => Err(format!("{} needs a value", name)),
+                }
+            };
+            match flag.as_str() {
+                "--locale" => parsed.locale = Some(value("--locale")?),
+                "--socket-dir" => parsed.socket_dir = Some(PathBuf::from(value("--socket-dir")?)),
+                "--exclusions-file" => {
+                    parsed.exclusions_file = Some(PathBuf::from(value("--exclusions-file")?))
+                }
+                "--dry-run" => parsed.dry_run = true,
+                "--download-only" => parsed.download_only = true,
+                "--stage-offline" => parsed.stage_offline = true,
+                "--offline" => parsed.offline = true,
+                "--repair" => parsed.repair = true,
+                "--cleanup" => parsed.cleanup = true,
+                "--autoremove" => parsed.autoremove = true,
+                "--remove-old-kernels" => {
+                    let keep = value("--remove-old-kernels")?;
+                    parsed.remove_old_kernels = Some(keep.parse::<u32>().map_err(|_| {
+                        format!("--remove-old-kernels needs a number, not {}", keep)
+                    })?)
+                }
+                "--clean-cache" => parsed.clean_cache = true,
+                "--include-phased-updates" => parsed.include_phased_updates = true,
+                "--snapshot" => {
+                    let backend = value("--snapshot")?;
+                    parsed.snapshot = match backend.as_str() {
+                        "none" => None,
+                        t => Some(
+                            SnapshotBackendKind::parse(t)
+                                .ok_or_else(|| format!("unknown snapshot backend {}", t))?,
+                        ),
+                    }
+                }
+                "--json" => parsed.json = true,
+                "-y" | "--assume-yes" => parsed.assume_yes = true,
+                "--no-progress-socket" => parsed.no_progress_socket = true,
+                "-h" | "--help" => parsed.help = true,
+                t if t.starts_with('-') => return Err(format!("unknown option {}", t)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        if let Some(locale) = positional.next() {
+            if parsed.locale.is_some() {
+                return Err(format!("unexpected argument {}", locale));
+            }
+            parsed.locale = Some(locale);
+        }
+        if let Some(socket_dir) = positional.next() {
+            if parsed.socket_dir.is_some() {
+                return Err(format!("unexpected argument {}", socket_dir));
+            }
+            parsed.socket_dir = Some(PathBuf::from(socket_dir));
+        }
+        if let Some(extra) = positional.next() {
+            return Err(format!("unexpected argument {}", extra));
+        }
+
+        if parsed.no_progress_socket {
+            parsed.socket_dir = None;
+        }
+        if parsed.dry_run && parsed.download_only {
+            return Err("--dry-run and --download-only can not be combined".to_owned());
+        }
+        if parsed.stage_offline && (parsed.dry_run || parsed.download_only || parsed.offline) {
+            return Err(
+                "--stage-offline can not be combined with --dry-run, --download-only or --offline"
+                    .to_owned(),
+            );
+        }
+        // The staged plan already says what to do
+        if parsed.offline && (parsed.exclusions_file.is_some() || parsed.dry_run) {
+            return Err(
+                "--offline can not be combined with --exclusions-file or --dry-run".to_owned(),
+            );
+        }
+        if parsed.repair
+            && (parsed.download_only
+                || parsed.stage_offline
+                || parsed.offline
+                || parsed.exclusions_file.is_some())
+        {
+            return Err(
+                "--repair can not be combined with --download-only, --stage-offline, --offline or --exclusions-file"
+                    .to_owned(),
+            );
+        }
+        if (parsed.cleanup || parsed.autoremove || parsed.clean_cache)
+            && (parsed.download_only || parsed.stage_offline || parsed.offline || parsed.repair)
+        {
+            return Err(
+                "--cleanup, --autoremove and --clean-cache can not be combined with --download-only, --stage-offline, --offline or --repair"
+                    .to_owned(),
+            );
+        }
+        // Staged upgrades keep their kernel policy in the plan
+        if parsed.remove_old_kernels.is_some() && (parsed.offline || parsed.repair) {
+            return Err(
+                "--remove-old-kernels can not be combined with --offline or --repair".to_owned(),
+            );
+        }
+        if parsed.cleanup && parsed.exclusions_file.is_some() {
+            return Err("--cleanup can not be combined with --exclusions-file".to_owned());
+        }
+        if parsed.cleanup && !parsed.cleans_up() {
+            return Err(
+                "--cleanup needs --autoremove, --remove-old-kernels or --clean-cache".to_owned(),
+            );
+        }
+        Ok(parsed)
+    }
+
+    /// Whether anything is to be removed besides what the upgrade itself removes.
+    pub fn cleans_up(&self) -> bool {
+        self.autoremove || self.remove_old_kernels.is_some() || self.clean_cache
+    }
+
+    /// Whether the cached archives get deleted, only once the transaction went through.
+    pub fn deletes_cached_archives(&self) -> bool {
+        self.clean_cache && !self.dry_run
+    }
+
+    /// Whether this removes or changes more than upgrading what is installed does, only an
+    /// administrator may ask for that.
+    pub fn needs_admin(&self) -> bool {
+        self.stage_offline
+            || self.offline
+            || self.repair
+            || self.cleanup
+            || self.autoremove
+            || self.remove_old_kernels.is_some()
+            || self.clean_cache
+            || self.snapshot.is_some()
+    }
+
+    /// Refuses what needs an administrator when started through the action that does not
+    /// ask for a password.
+    pub fn check_authorized(&self) -> Result<(), String> {
+        if self.needs_admin() && env::var_os(UNAUTHENTICATED_ENV).is_some() {
+            return Err(format!(
+                "this needs an administrator, start it through {}",
+                APT_FULL_UPGRADE_ADMIN_PATH
+            ));
+        }
+        Ok(())
+    }
+
+    /// The requested locale, or the one from `$LANG` like the GUI does.
+    pub fn locale(&self) -> String {
+        match &self.locale {
+            Some(t) => t.to_owned(),
+            None => match env::var("LANG") {
+                Ok(v) if !v.is_empty() => v.chars().take_while(|&ch| ch != '.').collect(),
+                _ => "en_US".to_owned(),
+            },
+        }
+    }
+
+    /// The GUI shows progress itself, everyone else gets it on the terminal.
+    pub fn console_output(&self) -> ConsoleOutput {
+        if self.json {
+            ConsoleOutput::Json
+        } else if self.offline {
+            ConsoleOutput::Plymouth
+        } else if self.socket_dir.is_some() {
+            ConsoleOutput::Silent
+        } else {
+            ConsoleOutput::Text
+        }
+    }
+}
+
+/// The wrapper pkexec has to start for `args`, a password is only asked for when needed.
+pub fn apt_full_upgrade_path(args: &[String]) -> &'static str {
+    match AptHelperArgs::parse(args.iter().cloned()) {
+        Ok(t) if !t.needs_admin() => APT_FULL_UPGRADE_PATH,
+        _ => APT_FULL_UPGRADE_ADMIN_PATH,
+    }
+}
+
+pub fn usage(bin_name: &str) -> String {
+    format!(
+        "Usage: {} [OPTIONS]
+
+Options:
+  --locale <LOCALE>            Language for progress messages (default: $LANG)
+  --socket-dir <DIR>           Session directory holding the GUI progress socket
+  --exclusions-file <FILE>     JSON file with packages to leave out or hold back
+  --dry-run                    Only show what would be done
+  --download-only              Download packages without installing them
+  --stage-offline              Download packages and install them on the next boot
+  --offline                    Install the staged upgrade, run at boot by systemd
+  --repair                     Finish an interrupted dpkg run and fix broken dependencies
+  --cleanup                    Only remove what the options below select, upgrade nothing
+  --autoremove                 Remove automatically installed packages nothing needs anymore
+  --remove-old-kernels <KEEP>  Remove kernels beyond the newest KEEP, never the running one
+  --clean-cache                Delete downloaded package files from the APT cache
+  --include-phased-updates     Do not defer phased updates
+  --snapshot <BACKEND>         Take a btrfs, snapper or timeshift snapshot before installing
+  --json                       Print progress and results as JSON lines
+  -y, --assume-yes             Do not ask for confirmation
+  --no-progress-socket         Never report to the GUI, even if a socket is given
+  -h, --help                   Show this help",
+        bin_name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<AptHelperArgs, String> {
+        AptHelperArgs::parse(args.iter().map(|t| t.to_string()))
+    }
+
+    #[test]
+    fn positional_locale_and_socket_dir() {
+        let args = parse(&["de_DE", "/run/user/1000/pikman-update-manager-x"]).unwrap();
+        assert_eq!(args.locale.as_deref(), Some("de_DE"));
+        assert_eq!(
+            args.socket_dir,
+            Some(PathBuf::from("/run/user/1000/pikman-update-manager-x"))
+        );
+        assert_eq!(args.console_output(), ConsoleOutput::Silent);
+
+        let args = parse(&["de_DE", "--dry-run"]).unwrap();
+        assert_eq!(args.locale.as_deref(), Some("de_DE"));
+        assert_eq!(args.socket_dir, None);
+        assert!(args.dry_run);
+    }
+
+    #[test]
+    fn positional_conflicts_with_flags() {
+        assert!(parse(&["--locale", "de_DE", "fr_FR"]).is_err());
+        assert!(parse(&["de_DE", "--socket-dir", "/tmp/a", "/tmp/b"]).is_err());
+        assert!(parse(&["de_DE", "/tmp/a", "extra"]).is_err());
+    }
+
+    #[test]
+    fn flag_values() {
+        let args = parse(&["--locale=de_DE", "--remove-old-kernels", "3"]).unwrap();
+        assert_eq!(args.locale.as_deref(), Some("de_DE"));
+        assert_eq!(args.remove_old_kernels, Some(3));
+        assert!(parse(&["--locale"]).is_err());
+        assert!(parse(&["--locale="]).is_err());
+        assert!(parse(&["--remove-old-kernels", "two"]).is_err());
+        assert!(parse(&["--snapshot", "zfs"]).is_err());
+        assert_eq!(parse(&["--snapshot", "none"]).unwrap().snapshot, None);
+    }
+
+    #[test]
+    fn unknown_flags() {
+        assert_eq!(
+            parse(&["--frobnicate"]).unwrap_err(),
+            "unknown option --frobnicate"
+        );
+        assert!(parse(&["-x"]).is_err());
+    }
+
+    #[test]
+    fn no_progress_socket_drops_socket_dir() {
+        let args = parse(&["--socket-dir", "/tmp/a", "--no-progress-socket"]).unwrap();
+        assert_eq!(args.socket_dir, None);
+        assert_eq!(args.console_output(), ConsoleOutput::Text);
+    }
+
+    #[test]
+    fn conflicting_modes() {
+        let conflicts: &[&[&str]] = &[
+            &["--dry-run", "--download-only"],
+            &["--stage-offline", "--dry-run"],
+            &["--stage-offline", "--download-only"],
+            &["--stage-offline", "--offline"],
+            &["--offline", "--exclusions-file", "/tmp/exclusions.json"],
+            &["--offline", "--dry-run"],
+            &["--repair", "--download-only"],
+            &["--repair", "--stage-offline"],
+            &["--repair", "--offline"],
+            &["--repair", "--exclusions-file", "/tmp/exclusions.json"],
+            &["--cleanup", "--autoremove", "--download-only"],
+            &["--cleanup", "--autoremove", "--stage-offline"],
+            &["--cleanup", "--autoremove", "--offline"],
+            &["--cleanup", "--autoremove", "--repair"],
+            &["--autoremove", "--download-only"],
+            &["--clean-cache", "--stage-offline"],
+            &["--remove-old-kernels", "2", "--offline"],
+            &["--remove-old-kernels", "2", "--repair"],
+            &[
+                "--cleanup",
+                "--autoremove",
+                "--exclusions-file",
+                "/tmp/exclusions.json",
+            ],
+            &["--cleanup"],
+        ];
+        for args in conflicts {
+            assert!(parse(args).is_err(), "{:?} was accepted", args);
+        }
+    }
+
+    #[test]
+    fn compatible_modes() {
+        let compatible: &[&[&str]] = &[
+            &["--dry-run", "--exclusions-file", "/tmp/exclusions.json"],
+            &["--stage-offline", "--remove-old-kernels", "2"],
+            &["--repair", "--dry-run"],
+            &["--cleanup", "--remove-old-kernels", "2"],
+            &["--cleanup", "--autoremove", "--clean-cache", "--dry-run"],
+            &["--autoremove", "--exclusions-file", "/tmp/exclusions.json"],
+        ];
+        for args in compatible {
+            assert!(parse(args).is_ok(), "{:?} was refused", args);
+        }
+    }
+
+    #[test]
+    fn dry_run_keeps_cached_archives() {
+        assert!(parse(&["--cleanup", "--clean-cache"])
+            .unwrap()
+            .deletes_cached_archives());
+        assert!(!parse(&["--cleanup", "--clean-cache", "--dry-run"])
+            .unwrap()
+            .deletes_cached_archives());
+        assert!(!parse(&["--cleanup", "--autoremove"])
+            .unwrap()
+            .deletes_cached_archives());
+    }
+
+    #[test]
+    fn wrapper_for_args() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|t| t.to_string()).collect() };
+        assert_eq!(
+            apt_full_upgrade_path(&args(&["--assume-yes", "--include-phased-updates"])),
+            APT_FULL_UPGRADE_PATH
+        );
+        for admin_args in [
+            &["--cleanup", "--autoremove"][..],
+            &["--clean-cache"],
+            &["--remove-old-kernels", "2"],
+            &["--repair"],
+            &["--stage-offline"],
+            &["--offline"],
+            &["--snapshot", "snapper"],
+        ] {
+            assert_eq!(
+                apt_full_upgrade_path(&args(admin_args)),
+                APT_FULL_UPGRADE_ADMIN_PATH,
+                "{:?}",
+                admin_args
+            );
+        }
+        // The helper refuses it anyway, no point in starting the one without a password
+        assert_eq!(
+            apt_full_upgrade_path(&args(&["--frobnicate"])),
+            APT_FULL_UPGRADE_ADMIN_PATH
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use crate::pika_unixsocket_tools::ProgressSocketClient;
+use crate::progress_protocol::ProgressMessage;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fmt;
+use std::process::exit;
+
+/// Exit codes pkexec itself returns when the user dismissed or failed the authentication.
+const PKEXEC_EXIT_CODE_DISMISSED: i32 = 126;
+const PKEXEC_EXIT_CODE_NOT_AUTHORIZED: i32 = 127;
+
+/// Everything that can go wrong in `apt_update` and `apt_full_upgrade`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AptHelperErrorKind {
+    LockHeld,
+    AuthCancelled,
+    NetworkFailure,
+    UnresolvableDependencies,
+    DpkgInterrupted,
+    DiskFull,
+    BadExclusionsFile,
+    /// The pre-upgrade snapshot could not be taken, nothing was installed.
+    SnapshotFailed,
+    /// Asked for something only an administrator may do without authenticating as one.
+    NotAuthorized,
+    /// The user said no at the helper's own confirmation prompt, nothing was changed.
+    Aborted,
+    Unknown,
+}
+
+impl AptHelperErrorKind {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AptHelperErrorKind::LockHeld => 60,
+            AptHelperErrorKind::AuthCancelled => PKEXEC_EXIT_CODE_DISMISSED,
+            AptHelperErrorKind::NetworkFailure => 61,
+            AptHelperErrorKind::UnresolvableDependencies => 62,
+            AptHelperErrorKind::DpkgInterrupted => 63,
+            AptHelperErrorKind::DiskFull => 64,
+            AptHelperErrorKind::BadExclusionsFile => 65,
+            AptHelperErrorKind::SnapshotFailed => 66,
+            AptHelperErrorKind::NotAuthorized => 67,
+            AptHelperErrorKind::Aborted => 68,
+            AptHelperErrorKind::Unknown => 69,
+        }
+    }
+
+    pub fn from_exit_code(exit_code: i32) -> Option<Self> {
+        match exit_code {
+            60 => Some(AptHelperErrorKind::LockHeld),
+            61 => Some(AptHelperErrorKind::NetworkFailure),
+            62 => Some(AptHelperErrorKind::UnresolvableDependencies),
+            63 => Some(AptHelperErrorKind::DpkgInterrupted),
+            64 => Some(AptHelperErrorKind::DiskFull),
+            65 => Some(AptHelperErrorKind::BadExclusionsFile),
+            66 => Some(AptHelperErrorKind::SnapshotFailed),
+            67 => Some(AptHelperErrorKind::NotAuthorized),
+            68 => Some(AptHelperErrorKind::Aborted),
+            69 => Some(AptHelperErrorKind::Unknown),
+            PKEXEC_EXIT_CODE_DISMISSED | PKEXEC_EXIT_CODE_NOT_AUTHORIZED => {
+                Some(AptHelperErrorKind::AuthCancelled)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the helper sends this error over the socket itself before exiting.
+    ///
+    /// Authentication failures happen before the helper even starts.
+    pub fn is_reported_by_helper(&self) -> bool {
+        *self != AptHelperErrorKind::AuthCancelled
+    }
+
+    /// Stable identifier, used for translation keys and the `--json` output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AptHelperErrorKind::LockHeld => "lock_held",
+            AptHelperErrorKind::AuthCancelled => "auth_cancelled",
+            AptHelperErrorKind::NetworkFailure => "network_failure",
+            AptHelperErrorKind::UnresolvableDependencies => "unresolvable_dependencies",
+            AptHelperErrorKind::DpkgInterrupted => "dpkg_interrupted",
+            AptHelperErrorKind::DiskFull => "disk_full",
+            AptHelperErrorKind::BadExclusionsFile => "bad_exclusions_file",
+            AptHelperErrorKind::SnapshotFailed => "snapshot_failed",
+            AptHelperErrorKind::NotAuthorized => "not_authorized",
+            AptHelperErrorKind::Aborted => "aborted",
+            AptHelperErrorKind::Unknown => "unknown",
+        }
+    }
+
+    /// Guesses the kind of a libapt error from its message, libapt only hands out text. The
+    /// helper scripts keep APT's messages untranslated, anything else may end up as `fallback`.
+    pub fn classify(message: &str, fallback: Self) -> Self {
+        let message = message.to_lowercase();
+        let matches_any = |needles: &[&str]| needles.iter().any(|n| message.contains(n));
+        if matches_any(&[
+            "could not get lock",
+            "unable to acquire the dpkg frontend lock",
+            "unable to lock",
+        ]) {
+            AptHelperErrorKind::LockHeld
+        } else if matches_any(&["dpkg was interrupted", "dpkg --configure -a"]) {
+            AptHelperErrorKind::DpkgInterrupted
+        } else if matches_any(&["no space left on device", "enough free space"]) {
+            AptHelperErrorKind::DiskFull
+        } else if matches_any(&[
+            "failed to fetch",
+            "temporary failure resolving",
+            "could not resolve",
+            "could not connect",
+            "connection timed out",
+            "network is unreachable",
+        ]) {
+            AptHelperErrorKind::NetworkFailure
+        } else if matches_any(&[
+            "unmet dependencies",
+            "broken packages",
+            "unable to correct problems",
+        ]) {
+            AptHelperErrorKind::UnresolvableDependencies
+        } else {
+            fallback
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AptHelperError {
+    pub kind: AptHelperErrorKind,
+    pub message: String,
+}
+
+impl AptHelperError {
+    pub fn new(kind: AptHelperErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// Builds an error out of a libapt message, using `fallback` if it can not be classified.
+    pub fn from_apt(message: impl Into<String>, fallback: AptHelperErrorKind) -> Self {
+        let message = message.into();
+        Self {
+            kind: AptHelperErrorKind::classify(&message, fallback),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for AptHelperError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind.as_str(), self.message)
+    }
+}
+
+impl std::error::Error for AptHelperError {}
+
+/// Reports `error` to the GUI and terminates the helper with the matching exit code.
+pub fn exit_with_error(socket_client: &RefCell<ProgressSocketClient>, error: AptHelperError) -> ! {
+    eprintln!("{}", error);
+    let mut socket_client = socket_client.borrow_mut();
+    socket_client.send(&ProgressMessage::Error {
+        error_kind: error.kind,
+        message: error.message,
+    });
+    socket_client.send(&ProgressMessage::Finished {
+        exit_code: error.kind.exit_code(),
+    });
+    exit(error.kind.exit_code())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_KINDS: [AptHelperErrorKind; 11] = [
+        AptHelperErrorKind::LockHeld,
+        AptHelperErrorKind::AuthCancelled,
+        AptHelperErrorKind::NetworkFailure,
+        AptHelperErrorKind::UnresolvableDependencies,
+        AptHelperErrorKind::DpkgInterrupted,
+        AptHelperErrorKind::DiskFull,
+        AptHelperErrorKind::BadExclusionsFile,
+        AptHelperErrorKind::SnapshotFailed,
+        AptHelperErrorKind::NotAuthorized,
+        AptHelperErrorKind::Aborted,
+        AptHelperErrorKind::Unknown,
+    ];
+
+    #[test]
+    fn apt_messages_are_classified() {
+        for (message, kind) in [
+            (
+                "E:Could not get lock /var/lib/dpkg/lock-frontend. It is held by process 1234 (apt)",
+                AptHelperErrorKind::LockHeld,
+            ),
+            (
+                "E:Unable to acquire the dpkg frontend lock (/var/lib/dpkg/lock-frontend), is another process using it?",
+                AptHelperErrorKind::LockHeld,
+            ),
+            (
+                "E:Unable to lock directory /var/cache/apt/archives/",
+                AptHelperErrorKind::LockHeld,
+            ),
+            (
+                "E:dpkg was interrupted, you must manually run 'dpkg --configure -a' to correct the problem.",
+                AptHelperErrorKind::DpkgInterrupted,
+            ),
+            (
+                "E:Write error - write (28: No space left on device)",
+                AptHelperErrorKind::DiskFull,
+            ),
+            (
+                "E:You don't have enough free space in /var/cache/apt/archives/.",
+                AptHelperErrorKind::DiskFull,
+            ),
+            (
+                "E:Failed to fetch http://ppa.pika-os.com/dists/pika/InRelease  Temporary failure resolving 'ppa.pika-os.com'",
+                AptHelperErrorKind::NetworkFailure,
+            ),
+            (
+                "W:Could not connect to ppa.pika-os.com:80, connection timed out",
+                AptHelperErrorKind::NetworkFailure,
+            ),
+            (
+                "E:Unable to correct problems, you have held broken packages.",
+                AptHelperErrorKind::UnresolvableDependencies,
+            ),
+            (
+                "E:Unmet dependencies. Try 'apt --fix-broken install' with no packages (or specify a solution).",
+                AptHelperErrorKind::UnresolvableDependencies,
+            ),
+            (
+                "E:Sub-process /usr/bin/dpkg returned an error code (1)",
+                AptHelperErrorKind::Unknown,
+            ),
+        ] {
+            assert_eq!(
+                AptHelperErrorKind::classify(message, AptHelperErrorKind::Unknown),
+                kind,
+                "{}",
+                message
+            );
+        }
+        assert_eq!(
+            AptHelperErrorKind::classify("", AptHelperErrorKind::SnapshotFailed),
+            AptHelperErrorKind::SnapshotFailed
+        );
+    }
+
+    #[test]
+    fn exit_codes_round_trip() {
+        for kind in ALL_KINDS {
+            assert_eq!(
+                AptHelperErrorKind::from_exit_code(kind.exit_code()),
+                Some(kind)
+            );
+        }
+        assert_eq!(
+            AptHelperErrorKind::from_exit_code(PKEXEC_EXIT_CODE_NOT_AUTHORIZED),
+            Some(AptHelperErrorKind::AuthCancelled)
+        );
+        assert_eq!(AptHelperErrorKind::from_exit_code(0), None);
+        assert_eq!(AptHelperErrorKind::from_exit_code(1), None);
+    }
+}
@@ -1,26 +1,22 @@
-use crate::pika_unixsocket_tools::*;
+use crate::apt_helper_error::{exit_with_error, AptHelperError, AptHelperErrorKind};
+use crate::pika_unixsocket_tools::ProgressSocketClient;
+use crate::progress_protocol::ProgressMessage;
 use rust_apt::progress::DynInstallProgress;
-use std::process::exit;
-use tokio::io::AsyncWriteExt;
-use tokio::net::UnixStream;
-use tokio::runtime::Runtime;
+use std::cell::RefCell;
 
 pub struct AptInstallProgressSocket<'a> {
-    percent_socket_path: &'a str,
-    status_socket_path: &'a str,
+    socket_client: &'a RefCell<ProgressSocketClient>,
     error_strfmt_trans_str: String,
 }
 
 impl<'a> AptInstallProgressSocket<'a> {
     /// Returns a new default progress instance.
     pub fn new(
-        percent_socket_path: &'a str,
-        status_socket_path: &'a str,
+        socket_client: &'a RefCell<ProgressSocketClient>,
         error_strfmt_trans_str: String,
     ) -> Self {
         let progress = Self {
-            percent_socket_path: percent_socket_path,
-            status_socket_path: status_socket_path,
+            socket_client: socket_client,
             error_strfmt_trans_str: error_strfmt_trans_str,
         };
         progress
@@ -36,61 +32,35 @@ impl<'a> DynInstallProgress for AptInstallProgressSocket<'a> {
         action: String,
     ) {
         let progress_percent: f32 = (steps_done as f32 * 100.0) / total_steps as f32;
-        Runtime::new().unwrap().block_on(send_progress_percent(
-            progress_percent,
-            self.percent_socket_path,
-        ));
-        Runtime::new()
-            .unwrap()
-            .block_on(send_progress_status(&action, self.status_socket_path));
+        self.socket_client
+            .borrow_mut()
+            .send(&ProgressMessage::Percent {
+                percent: progress_percent,
+            });
+        self.socket_client
+            .borrow_mut()
+            .send(&ProgressMessage::Status { message: action });
     }
 
     fn error(&mut self, pkgname: String, _steps_done: u64, _total_steps: u64, error: String) {
         let message = &strfmt::strfmt(
             &self.error_strfmt_trans_str,
             &std::collections::HashMap::from([
-                ("PKGNAME".to_string(), pkgname),
-                ("ERROR".to_string(), error),
+                ("PKGNAME".to_string(), pkgname.clone()),
+                ("ERROR".to_string(), error.clone()),
             ]),
         )
         .unwrap();
         eprintln!("{}", &message);
-        Runtime::new()
-            .unwrap()
-            .block_on(send_progress_status(&message, self.status_socket_path));
-        Runtime::new()
-            .unwrap()
-            .block_on(send_failed_to_socket(self.percent_socket_path));
-        Runtime::new()
-            .unwrap()
-            .block_on(send_failed_to_socket(self.status_socket_path));
-        exit(53)
+        self.socket_client
+            .borrow_mut()
+            .send(&ProgressMessage::PackageError {
+                package: pkgname,
+                error: error.clone(),
+            });
+        exit_with_error(
+            self.socket_client,
+            AptHelperError::from_apt(error, AptHelperErrorKind::Unknown),
+        )
     }
 }
-
-async fn send_progress_percent(progress_f32: f32, socket_path: &str) {
-    // Connect to the Unix socket
-    let mut stream = UnixStream::connect(socket_path)
-        .await
-        .expect("Could not connect to server");
-
-    let message = progress_f32.to_string();
-    // Send the message to the server
-    stream
-        .write_all(message.as_bytes())
-        .await
-        .expect("Failed to write to stream");
-}
-
-async fn send_progress_status(message: &str, socket_path: &str) {
-    // Connect to the Unix socket
-    let mut stream = UnixStream::connect(socket_path)
-        .await
-        .expect("Could not connect to server");
-
-    // Send the message to the server
-    stream
-        .write_all(message.as_bytes())
-        .await
-        .expect("Failed to write to stream");
-}
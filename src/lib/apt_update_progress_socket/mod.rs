@@ -1,17 +1,14 @@
-use crate::pika_unixsocket_tools::*;
+use crate::apt_helper_error::{exit_with_error, AptHelperError, AptHelperErrorKind};
+use crate::pika_unixsocket_tools::ProgressSocketClient;
+use crate::progress_protocol::ProgressMessage;
 use rust_apt::progress::DynAcquireProgress;
 use rust_apt::raw::{AcqTextStatus, ItemDesc, PkgAcquire};
-use std::process::exit;
-use tokio::io::AsyncWriteExt;
-use tokio::net::UnixStream;
-use tokio::runtime::Runtime;
+use std::cell::RefCell;
 
 pub struct AptUpdateProgressSocket<'a> {
     last_pulse_bytes: u64,
     pulse_interval: usize,
-    percent_socket_path: &'a str,
-    status_socket_path: &'a str,
-    speed_socket_path: &'a str,
+    socket_client: &'a RefCell<ProgressSocketClient>,
     hit_strfmt_trans_str: &'a str,
     fetch_strfmt_trans_str: &'a str,
     done_strfmt_trans_str: &'a str,
@@ -21,9 +18,7 @@ pub struct AptUpdateProgressSocket<'a> {
 impl<'a> AptUpdateProgressSocket<'a> {
     /// Returns a new default progress instance.
     pub fn new(
-        percent_socket_path: &'a str,
-        status_socket_path: &'a str,
-        speed_socket_path: &'a str,
+        socket_client: &'a RefCell<ProgressSocketClient>,
         hit_strfmt_trans_str: &'a str,
         fetch_strfmt_trans_str: &'a str,
         done_strfmt_trans_str: &'a str,
@@ -32,9 +27,7 @@ impl<'a> AptUpdateProgressSocket<'a> {
         let progress = Self {
             last_pulse_bytes: 0,
             pulse_interval: 1000000,
-            percent_socket_path: percent_socket_path,
-            status_socket_path: status_socket_path,
-            speed_socket_path: speed_socket_path,
+            socket_client: socket_client,
             hit_strfmt_trans_str: hit_strfmt_trans_str,
             fetch_strfmt_trans_str: fetch_strfmt_trans_str,
             done_strfmt_trans_str: done_strfmt_trans_str,
@@ -72,10 +65,11 @@ impl<'a> DynAcquireProgress for AptUpdateProgressSocket<'a> {
             ]),
         )
         .unwrap();
-        println!("{}", message);
-        Runtime::new()
-            .unwrap()
-            .block_on(send_progress_status(&message, self.status_socket_path));
+        self.socket_client
+            .borrow_mut()
+            .send(&ProgressMessage::ItemHit {
+                message: message.to_owned(),
+            });
     }
 
     /// Called when an Item has started to download
@@ -90,10 +84,11 @@ impl<'a> DynAcquireProgress for AptUpdateProgressSocket<'a> {
             ]),
         )
         .unwrap();
-        println!("{}", message);
-        Runtime::new()
-            .unwrap()
-            .block_on(send_progress_status(&message, self.status_socket_path));
+        self.socket_client
+            .borrow_mut()
+            .send(&ProgressMessage::ItemFetch {
+                message: message.to_owned(),
+            });
     }
 
     /// Called when an item is successfully and completely fetched.
@@ -108,10 +103,11 @@ impl<'a> DynAcquireProgress for AptUpdateProgressSocket<'a> {
             ]),
         )
         .unwrap();
-        println!("{}", message);
-        Runtime::new()
-            .unwrap()
-            .block_on(send_progress_status(&message, self.status_socket_path));
+        self.socket_client
+            .borrow_mut()
+            .send(&ProgressMessage::ItemDone {
+                message: message.to_owned(),
+            });
     }
 
     /// Called when progress has started.
@@ -141,16 +137,16 @@ impl<'a> DynAcquireProgress for AptUpdateProgressSocket<'a> {
         )
         .unwrap();
         eprintln!("{}", &message);
-        Runtime::new()
-            .unwrap()
-            .block_on(send_progress_status(&message, self.status_socket_path));
-        Runtime::new()
-            .unwrap()
-            .block_on(send_failed_to_socket(self.percent_socket_path));
-        Runtime::new()
-            .unwrap()
-            .block_on(send_failed_to_socket(self.status_socket_path));
-        exit(53)
+        self.socket_client
+            .borrow_mut()
+            .send(&ProgressMessage::ItemFail {
+                message: message.to_owned(),
+            });
+        // A failed download during an update or upgrade is always a fetch problem
+        exit_with_error(
+            self.socket_client,
+            AptHelperError::from_apt(message.to_owned(), AptHelperErrorKind::NetworkFailure),
+        )
     }
 
     /// Called periodically to provide the overall progress information
@@ -168,40 +164,15 @@ impl<'a> DynAcquireProgress for AptUpdateProgressSocket<'a> {
             status.current_bytes() as f64 - self.last_pulse_bytes as f64
         };
         self.last_pulse_bytes = status.current_bytes();
-        Runtime::new().unwrap().block_on(send_progress_percent(
-            progress_percent,
-            self.percent_socket_path,
-        ));
-        Runtime::new().unwrap().block_on(send_progress_status(
-            &(pretty_bytes::converter::convert(speed) + "ps").to_lowercase(),
-            self.speed_socket_path,
-        ));
+        self.socket_client
+            .borrow_mut()
+            .send(&ProgressMessage::Percent {
+                percent: progress_percent,
+            });
+        self.socket_client
+            .borrow_mut()
+            .send(&ProgressMessage::Speed {
+                speed: (pretty_bytes::converter::convert(speed) + "ps").to_lowercase(),
+            });
     }
 }
-
-async fn send_progress_percent(progress_f32: f32, socket_path: &str) {
-    // Connect to the Unix socket
-    let mut stream = UnixStream::connect(socket_path)
-        .await
-        .expect("Could not connect to server");
-
-    let message = progress_f32.to_string();
-    // Send the message to the server
-    stream
-        .write_all(message.as_bytes())
-        .await
-        .expect("Failed to write to stream");
-}
-
-async fn send_progress_status(message: &str, socket_path: &str) {
-    // Connect to the Unix socket
-    let mut stream = UnixStream::connect(socket_path)
-        .await
-        .expect("Could not connect to server");
-
-    // Send the message to the server
-    stream
-        .write_all(message.as_bytes())
-        .await
-        .expect("Failed to write to stream");
-}
@@ -0,0 +1,173 @@
+use crate::progress_protocol::ChangeAction;
+use crate::update_discovery::AptChange;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RelationKind {
+    /// `Depends` and `Pre-Depends`.
+    Depends,
+    Recommends,
+    /// `Conflicts` and `Breaks`.
+    Conflicts,
+}
+
+/// `package` depends on, recommends or conflicts with `target`, as declared by the version
+/// that is going to be installed, or the installed one for removals.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PackageRelation {
+    pub package: String,
+    pub kind: RelationKind,
+    pub target: String,
+    /// Version constraint like `>= 2.0`, for display only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub constraint: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExplainedChange {
+    Install,
+    Remove,
+    HeldBack,
+}
+
+/// Why a package is installed, removed or held back, as a chain of relations starting at
+/// the package that triggers it. An empty chain means no reason was found.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ChangeExplanation {
+    pub package: String,
+    pub change: ExplainedChange,
+    pub chain: Vec<PackageRelation>,
+}
+
+/// Explains new installs, removals and held-back packages of a resolved upgrade, `kept` and
+/// `held_back` are the packages left out on purpose and the ones that fell out because of them.
+pub fn explain_changes(
+    resolved: &[AptChange],
+    kept: &[AptChange],
+    held_back: &[AptChange],
+    relations: &[PackageRelation],
+) -> Vec<ChangeExplanation> {
+    let mut relations_by_package: HashMap<&str, Vec<&PackageRelation>> = HashMap::new();
+    for relation in relations {
+        relations_by_package
+            .entry(relation.package.as_str())
+            .or_default()
+            .push(relation);
+    }
+    let outgoing = |package: &str| -> Vec<&PackageRelation> {
+        relations_by_package
+            .get(package)
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    let new_installs: HashSet<&str> = resolved
+        .iter()
+        .filter(|t| t.action == ChangeAction::Install)
+        .map(|t| t.name.as_str())
+        .collect();
+    let removals: HashSet<&str> = resolved
+        .iter()
+        .filter(|t| t.action == ChangeAction::Remove)
+        .map(|t| t.name.as_str())
+        .collect();
+    let kept_names: HashSet<&str> = kept.iter().map(|t| t.name.as_str()).collect();
+
+    // Walk from every upgraded package through the dependencies it pulls in
+    let mut pulled_in_by: HashMap<&str, &PackageRelation> = HashMap::new();
+    let mut queue: VecDeque<&str> = resolved
+        .iter()
+        .filter(|t| matches!(t.action, ChangeAction::Upgrade | ChangeAction::Downgrade))
+        .map(|t| t.name.as_str())
+        .collect();
+    while let Some(package) = queue.pop_front() {
+        for relation in outgoing(package) {
+            if relation.kind != RelationKind::Conflicts
+                && new_installs.contains(relation.target.as_str())
+                && !pulled_in_by.contains_key(relation.target.as_str())
+            {
+                pulled_in_by.insert(relation.target.as_str(), relation);
+                queue.push_back(relation.target.as_str());
+            }
+        }
+    }
+    let install_chain = |package: &str| -> Vec<PackageRelation> {
+        let mut chain = Vec::new();
+        let mut current = package;
+        while let Some(relation) = pulled_in_by.get(current) {
+            chain.insert(0, (*relation).clone());
+            current = relation.package.as_str();
+        }
+        chain
+    };
+
+    let mut explanations: Vec<ChangeExplanation> = Vec::new();
+
+    for change in resolved
+        .iter()
+        .filter(|t| t.action == ChangeAction::Install)
+    {
+        explanations.push(ChangeExplanation {
+            package: change.name.to_owned(),
+            change: ExplainedChange::Install,
+            chain: install_chain(&change.name),
+        });
+    }
+
+    for change in resolved.iter().filter(|t| t.action == ChangeAction::Remove) {
+        // Something that stays installed conflicts with it, or it depends on another removal
+        let conflict = relations.iter().find(|t| {
+            t.kind == RelationKind::Conflicts
+                && t.target == change.name
+                && !removals.contains(t.package.as_str())
+        });
+        let chain = match conflict {
+            Some(relation) => {
+                let mut chain = install_chain(&relation.package);
+                chain.push(relation.clone());
+                chain
+            }
+            None => outgoing(&change.name)
+                .into_iter()
+                .find(|t| t.kind == RelationKind::Depends && removals.contains(t.target.as_str()))
+                .map(|t| vec![t.clone()])
+                .unwrap_or_default(),
+        };
+        explanations.push(ChangeExplanation {
+            package: change.name.to_owned(),
+            change: ExplainedChange::Remove,
+            chain,
+        });
+    }
+
+    let held_back_names: HashSet<&str> = held_back.iter().map(|t| t.name.as_str()).collect();
+    for change in held_back {
+        // It needs something that is kept or held back itself, or something kept conflicts with it
+        let chain = outgoing(&change.name)
+            .into_iter()
+            .find(|t| {
+                t.kind == RelationKind::Depends
+                    && (kept_names.contains(t.target.as_str())
+                        || held_back_names.contains(t.target.as_str()))
+            })
+            .or_else(|| {
+                relations.iter().find(|t| {
+                    t.kind == RelationKind::Conflicts
+                        && t.target == change.name
+                        && kept_names.contains(t.package.as_str())
+                })
+            })
+            .map(|t| vec![t.clone()])
+            .unwrap_or_default();
+        explanations.push(ChangeExplanation {
+            package: change.name.to_owned(),
+            change: ExplainedChange::HeldBack,
+            chain,
+        });
+    }
+
+    explanations
+}
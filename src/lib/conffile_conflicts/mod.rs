@@ -0,0 +1,158 @@
+use crate::progress_protocol::ConflictAnswer;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::Command;
+
+/// Where dpkg lists the configuration files of every installed package.
+pub const DPKG_INFO_DIR: &str = "/var/lib/dpkg/info";
+
+/// Makes dpkg keep configuration files the user changed instead of asking on a terminal
+/// nobody is watching, the package's version is left next to them as `.dpkg-dist`.
+/// rust-apt has no way to answer dpkg's prompt while it waits, so the user is asked once
+/// dpkg is done. Maintainer scripts have run against the kept file by then, a replaced one
+/// only takes effect when its service rereads it.
+pub const DPKG_CONFFILE_OPTIONS: [&str; 2] = ["--force-confdef", "--force-confold"];
+
+/// A changed configuration file that got a new version during this transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConffileConflict {
+    pub package: String,
+    pub path: String,
+}
+
+impl ConffileConflict {
+    pub fn dist_path(&self) -> String {
+        format!("{}.dpkg-dist", self.path)
+    }
+
+    /// Unified diff from the local file to the package's version.
+    pub fn diff(&self) -> String {
+        match Command::new("diff")
+            .args(["-u", &self.path, &self.dist_path()])
+            .output()
+        {
+            Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    /// Carries out the answer, like dpkg would have if it could have asked: replacing keeps
+    /// the local file as `.dpkg-old`, keeping or merging later leaves the `.dpkg-dist`.
+    pub fn apply(&self, answer: ConflictAnswer) -> io::Result<()> {
+        match answer {
+            ConflictAnswer::Keep | ConflictAnswer::MergeLater => Ok(()),
+            ConflictAnswer::Replace => {
+                fs::rename(&self.path, format!("{}.dpkg-old", self.path))?;
+                fs::rename(self.dist_path(), &self.path)
+            }
+        }
+    }
+}
+
+/// The configuration files of `packages` (`name:arch`) that dpkg left a `.dpkg-dist` for
+/// at or after `since`, in seconds since the epoch.
+pub fn find_conffile_conflicts(
+    packages: &[String],
+    dpkg_info_dir: &Path,
+    since: i64,
+) -> Vec<ConffileConflict> {
+    let mut conflicts = Vec::new();
+    for package in packages {
+        let (name, _) = package.split_once(':').unwrap_or((package, ""));
+        // Multi-arch: same packages keep the architecture in the file name
+        let conffiles = fs::read_to_string(dpkg_info_dir.join(format!("{}.conffiles", package)))
+            .or_else(|_| fs::read_to_string(dpkg_info_dir.join(format!("{}.conffiles", name))))
+            .unwrap_or_default();
+        for line in conffiles.lines() {
+            // Newer dpkg versions may put flags like `remove-on-upgrade` in front
+            let path = match line.find('/') {
+                Some(t) => line[t..].trim_end(),
+                None => continue,
+            };
+            let conflict = ConffileConflict {
+                package: package.to_owned(),
+                path: path.to_owned(),
+            };
+            // dpkg keeps the packaged mtime, only the ctime says when the file appeared
+            if fs::metadata(conflict.dist_path()).is_ok_and(|t| t.ctime() >= since) {
+                conflicts.push(conflict);
+            }
+        }
+    }
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "conffile-conflicts-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn conflict_in(dir: &Path) -> ConffileConflict {
+        let conflict = ConffileConflict {
+            package: "foo:amd64".to_string(),
+            path: dir.join("foo.conf").to_string_lossy().to_string(),
+        };
+        fs::write(&conflict.path, "local").unwrap();
+        fs::write(conflict.dist_path(), "packaged").unwrap();
+        conflict
+    }
+
+    #[test]
+    fn replace_installs_the_packaged_file_and_keeps_the_local_one() {
+        let dir = scratch_dir("replace");
+        let conflict = conflict_in(&dir);
+        conflict.apply(ConflictAnswer::Replace).unwrap();
+        assert_eq!(fs::read_to_string(&conflict.path).unwrap(), "packaged");
+        assert_eq!(
+            fs::read_to_string(format!("{}.dpkg-old", conflict.path)).unwrap(),
+            "local"
+        );
+        assert!(!Path::new(&conflict.dist_path()).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keep_leaves_both_files() {
+        let dir = scratch_dir("keep");
+        let conflict = conflict_in(&dir);
+        conflict.apply(ConflictAnswer::Keep).unwrap();
+        assert_eq!(fs::read_to_string(&conflict.path).unwrap(), "local");
+        assert_eq!(
+            fs::read_to_string(conflict.dist_path()).unwrap(),
+            "packaged"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn finds_conffiles_with_a_new_dist_file() {
+        let dir = scratch_dir("find");
+        let conflict = conflict_in(&dir);
+        fs::write(
+            dir.join("foo.conffiles"),
+            format!(
+                "{}\nremove-on-upgrade {}/gone.conf\n",
+                conflict.path,
+                dir.display()
+            ),
+        )
+        .unwrap();
+        let packages = vec!["foo:amd64".to_string()];
+        assert_eq!(find_conffile_conflicts(&packages, &dir, 0), vec![conflict]);
+        assert!(find_conffile_conflicts(&packages, &dir, i64::MAX).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
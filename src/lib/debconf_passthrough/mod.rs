@@ -0,0 +1,363 @@
+use crate::pika_unixsocket_tools::ProgressSocketClient;
+use crate::progress_protocol::{
+    DebconfQuestion, DebconfQuestionKind, ProgressMessage, ProgressReply,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Holds the sockets debconf's passthrough frontend connects to, root only.
+pub const DEBCONF_SOCKET_DIR: &str = "/run/pikman-update-manager";
+/// How long a page of questions may stay unanswered before debconf gets the defaults.
+const DEBCONF_ANSWER_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// What to do with a line debconf sent.
+#[derive(Debug, PartialEq)]
+pub enum DebconfStep {
+    /// Write this back right away.
+    Reply(String),
+    /// Show these questions, then `answer` them and reply.
+    Go(Vec<DebconfQuestion>),
+}
+
+/// The user interface end of debconf's passthrough protocol for a single connection, as
+/// spoken by `Debconf::FrontEnd::Passthrough`.
+#[derive(Debug, Default)]
+pub struct DebconfSession {
+    pub title: String,
+    questions: HashMap<String, DebconfQuestion>,
+    substitutions: HashMap<String, HashMap<String, String>>,
+    pending: Vec<String>,
+}
+
+impl DebconfSession {
+    pub fn handle(&mut self, line: &str) -> DebconfStep {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match command.to_uppercase().as_str() {
+            "TITLE" | "SETTITLE" => self.title = rest.to_owned(),
+            "DATA" => {
+                let mut fields = rest.splitn(3, ' ');
+                let (tag, field, value) = match (fields.next(), fields.next(), fields.next()) {
+                    (Some(tag), Some(field), value) => (tag, field, value.unwrap_or_default()),
+                    _ => return DebconfStep::Reply("20 missing arguments".to_owned()),
+                };
+                let question = self.question_mut(tag);
+                match field {
+                    "type" => question.kind = question_kind(value),
+                    "description" => question.description = unescape(value),
+                    "extended_description" => question.extended_description = unescape(value),
+                    "choices" => question.choices = split_choices(&unescape(value)),
+                    _ => {}
+                }
+            }
+            "SET" => {
+                let (tag, value) = rest.split_once(' ').unwrap_or((rest, ""));
+                self.question_mut(tag).value = unescape(value);
+            }
+            "SUBST" => {
+                let mut fields = rest.splitn(3, ' ');
+                if let (Some(tag), Some(var)) = (fields.next(), fields.next()) {
+                    self.substitutions
+                        .entry(tag.to_owned())
+                        .or_default()
+                        .insert(var.to_owned(), fields.next().unwrap_or_default().to_owned());
+                }
+            }
+            "INPUT" => {
+                let (_priority, tag) = rest.split_once(' ').unwrap_or(("", rest));
+                self.pending.push(tag.to_owned());
+            }
+            "GET" => {
+                let value = self
+                    .questions
+                    .get(rest)
+                    .map(|t| escape(&t.value))
+                    .unwrap_or_default();
+                return DebconfStep::Reply(format!("0 {}", value));
+            }
+            "GO" if !self.pending.is_empty() => {
+                let pending = std::mem::take(&mut self.pending);
+                return DebconfStep::Go(
+                    pending
+                        .iter()
+                        .filter_map(|tag| self.questions.get(tag))
+                        .map(|question| self.substituted(question))
+                        .collect(),
+                );
+            }
+            // CAPB, PROGRESS, INFO, STOP and anything newer need no answer
+            _ => {}
+        }
+        DebconfStep::Reply("0 ok".to_owned())
+    }
+
+    /// Takes the user's answers, questions left out keep their defaults.
+    pub fn answer(&mut self, values: &HashMap<String, String>) {
+        for (tag, value) in values {
+            if let Some(question) = self.questions.get_mut(tag) {
+                question.value = value.to_owned();
+            }
+        }
+    }
+
+    fn question_mut(&mut self, tag: &str) -> &mut DebconfQuestion {
+        self.questions
+            .entry(tag.to_owned())
+            .or_insert_with(|| DebconfQuestion {
+                tag: tag.to_owned(),
+                kind: DebconfQuestionKind::Note,
+                description: String::new(),
+                extended_description: String::new(),
+                choices: Vec::new(),
+                value: String::new(),
+            })
+    }
+
+    /// Fills in the `${VAR}` placeholders the script set with SUBST.
+    fn substituted(&self, question: &DebconfQuestion) -> DebconfQuestion {
+        let substitutions = match self.substitutions.get(&question.tag) {
+            Some(t) => t,
+            None => return question.clone(),
+        };
+        let substitute = |text: &str| -> String {
+            substitutions
+                .iter()
+                .fold(text.to_owned(), |text, (var, value)| {
+                    text.replace(&format!("${{{}}}", var), value)
+                })
+        };
+        DebconfQuestion {
+            description: substitute(&question.description),
+            extended_description: substitute(&question.extended_description),
+            choices: question.choices.iter().map(|t| substitute(t)).collect(),
+            ..question.clone()
+        }
+    }
+}
+
+fn question_kind(value: &str) -> DebconfQuestionKind {
+    match value {
+        "select" => DebconfQuestionKind::Select,
+        "multiselect" => DebconfQuestionKind::Multiselect,
+        "boolean" => DebconfQuestionKind::Boolean,
+        "string" => DebconfQuestionKind::String,
+        "password" => DebconfQuestionKind::Password,
+        _ => DebconfQuestionKind::Note,
+    }
+}
+
+/// The passthrough frontend sends newlines as `\n`.
+fn unescape(value: &str) -> String {
+    value.replace("\\n", "\n")
+}
+
+/// A value goes back on a single line, the other way round from `unescape`.
+fn escape(value: &str) -> String {
+    value.replace('\n', "\\n")
+}
+
+/// Splits `a, b\, c` into `a` and `b, c`, like debconf does.
+pub fn split_choices(choices: &str) -> Vec<String> {
+    let mut split = Vec::new();
+    let mut current = String::new();
+    let mut chars = choices.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if chars.peek() == Some(&',') => {
+                current.push(',');
+                chars.next();
+            }
+            ',' => {
+                split.push(current.trim().to_owned());
+                current.clear();
+            }
+            _ => current.push(ch),
+        }
+    }
+    if !current.trim().is_empty() {
+        split.push(current.trim().to_owned());
+    }
+    split
+}
+
+/// Binds a fresh socket for this helper run, returns it with the path for `DEBCONF_PIPE`.
+pub fn bind_debconf_socket() -> io::Result<(UnixListener, PathBuf)> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(DEBCONF_SOCKET_DIR)?;
+    let socket_path =
+        Path::new(DEBCONF_SOCKET_DIR).join(format!("debconf-{}.socket", std::process::id()));
+    match fs::remove_file(&socket_path) {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    Ok((UnixListener::bind(&socket_path)?, socket_path))
+}
+
+/// Writes a stand-in for `dpkg_path` that runs it with debconf's passthrough frontend
+/// pointed at `debconf_socket_path`. Handed to APT as `Dir::Bin::dpkg`, so only dpkg and
+/// the maintainer scripts it runs see the frontend, not the helper's own environment.
+pub fn write_dpkg_wrapper(debconf_socket_path: &Path, dpkg_path: &str) -> io::Result<PathBuf> {
+    let wrapper_path = Path::new(DEBCONF_SOCKET_DIR).join(format!("dpkg-{}", std::process::id()));
+    match fs::remove_file(&wrapper_path) {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let mut wrapper = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o700)
+        .open(&wrapper_path)?;
+    write!(
+        wrapper,
+        "#!/bin/sh\nexport DEBIAN_FRONTEND=passthrough\nexport DEBCONF_PIPE={}\nexec {} \"$@\"\n",
+        shell_quote(&debconf_socket_path.to_string_lossy()),
+        shell_quote(dpkg_path)
+    )?;
+    Ok(wrapper_path)
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Answers every debconf connection on `listener` by asking the GUI behind
+/// `progress_socket_path`. Blocks, meant for its own thread.
+pub fn serve_debconf(listener: UnixListener, progress_socket_path: &str) {
+    let mut socket_client = ProgressSocketClient::new(progress_socket_path);
+    let mut prompt_id: u32 = 0;
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(t) => t,
+            Err(e) => {
+                eprintln!("Debconf connection failed: {}", e);
+                continue;
+            }
+        };
+        let mut writer = match stream.try_clone() {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        let mut session = DebconfSession::default();
+        for line in BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(t) => t,
+                Err(_) => break,
+            };
+            let reply = match session.handle(&line) {
+                DebconfStep::Reply(t) => t,
+                DebconfStep::Go(questions) => {
+                    prompt_id += 1;
+                    socket_client.send(&ProgressMessage::DebconfPrompt {
+                        id: prompt_id,
+                        title: session.title.to_owned(),
+                        questions,
+                    });
+                    loop {
+                        match socket_client.wait_for_reply(DEBCONF_ANSWER_TIMEOUT) {
+                            Some(ProgressReply::DebconfAnswer { id, values })
+                                if id == prompt_id =>
+                            {
+                                session.answer(&values);
+                                break;
+                            }
+                            Some(_) => continue,
+                            // Nobody answered, debconf keeps the defaults
+                            None => break,
+                        }
+                    }
+                    "0 ok".to_owned()
+                }
+            };
+            if writeln!(writer, "{}", reply).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ok() -> DebconfStep {
+        DebconfStep::Reply("0 ok".to_owned())
+    }
+
+    #[test]
+    fn go_page_fills_in_substitutions() {
+        let mut session = DebconfSession::default();
+        for line in [
+            "TITLE Configuring keyboard-configuration",
+            "DATA kb/layout type select",
+            "DATA kb/layout description Keyboard layout for ${MODEL}:",
+            "DATA kb/layout choices ${DEFAULT}, English (US)\\, intl.",
+            "SUBST kb/layout MODEL Generic 105-key PC",
+            "SUBST kb/layout DEFAULT German",
+            "SET kb/layout German",
+            "INPUT high kb/layout",
+        ] {
+            assert_eq!(session.handle(line), ok());
+        }
+        assert_eq!(session.title, "Configuring keyboard-configuration");
+        assert_eq!(
+            session.handle("GO"),
+            DebconfStep::Go(vec![DebconfQuestion {
+                tag: "kb/layout".to_owned(),
+                kind: DebconfQuestionKind::Select,
+                description: "Keyboard layout for Generic 105-key PC:".to_owned(),
+                extended_description: String::new(),
+                choices: vec!["German".to_owned(), "English (US), intl.".to_owned()],
+                value: "German".to_owned(),
+            }])
+        );
+        // The page is gone once shown
+        assert_eq!(session.handle("GO"), ok());
+    }
+
+    #[test]
+    fn get_returns_the_answer() {
+        let mut session = DebconfSession::default();
+        session.handle("DATA pkg/motd type string");
+        session.handle("SET pkg/motd Hello\\nthere");
+        assert_eq!(
+            session.handle("GET pkg/motd"),
+            DebconfStep::Reply("0 Hello\\nthere".to_owned())
+        );
+
+        session.answer(&HashMap::from([
+            ("pkg/motd".to_owned(), "Welcome".to_owned()),
+            ("pkg/unknown".to_owned(), "ignored".to_owned()),
+        ]));
+        assert_eq!(
+            session.handle("GET pkg/motd"),
+            DebconfStep::Reply("0 Welcome".to_owned())
+        );
+        assert_eq!(
+            session.handle("GET pkg/unknown"),
+            DebconfStep::Reply("0 ".to_owned())
+        );
+    }
+
+    #[test]
+    fn set_unescapes_like_data() {
+        let mut session = DebconfSession::default();
+        session.handle("SET pkg/motd line one\\nline two");
+        assert_eq!(session.questions["pkg/motd"].value, "line one\nline two");
+    }
+
+    #[test]
+    fn choices_keep_escaped_commas() {
+        assert_eq!(split_choices("a, b\\, c"), vec!["a", "b, c"]);
+        assert_eq!(split_choices("a,b,"), vec!["a", "b"]);
+        assert!(split_choices("").is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+use rust_apt::cache::{Cache, PackageSort, Upgrade};
+use rust_apt::new_cache;
+use rust_apt::util::cmp_versions;
+use std::cmp::Ordering;
+use std::fs;
+
+/// The kernel image of a release is `linux-image-<release>`.
+pub const KERNEL_IMAGE_PREFIX: &str = "linux-image-";
+/// Release of the running kernel, what `uname -r` prints.
+pub const KERNEL_OSRELEASE_FILE: &str = "/proc/sys/kernel/osrelease";
+
+/// The release a kernel image package is for, `None` for meta packages like
+/// `linux-image-pikaos`.
+pub fn kernel_release(package_name: &str) -> Option<&str> {
+    let release = package_name.strip_prefix(KERNEL_IMAGE_PREFIX)?;
+    match release.chars().next() {
+        Some(t) if t.is_ascii_digit() => Some(release),
+        _ => None,
+    }
+}
+
+/// Whether a package (headers, modules, the image itself) belongs to this kernel release.
+pub fn belongs_to_release(package_name: &str, release: &str) -> bool {
+    package_name.starts_with("linux-")
+        && package_name
+            .strip_suffix(release)
+            .is_some_and(|t| t.ends_with('-'))
+}
+
+pub fn running_kernel_release() -> Option<String> {
+    fs::read_to_string(KERNEL_OSRELEASE_FILE)
+        .ok()
+        .map(|t| t.trim().to_owned())
+        .filter(|t| !t.is_empty())
+}
+
+/// A kernel release with everything installed for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstalledKernel {
+    pub release: String,
+    /// Version of the image package.
+    pub version: String,
+    /// `name:arch` of the image, modules, headers and so on.
+    pub packages: Vec<String>,
+    pub installed_size: u64,
+    pub running: bool,
+    /// Not installed yet, the marked upgrade brings it in.
+    pub pending: bool,
+}
+
+/// Installed kernels beyond the newest `keep` of `newest_first`. A kernel that is only about to
+/// be installed does not count, it may still be held back, and the running one always stays.
+pub fn kernels_to_remove(newest_first: &[InstalledKernel], keep: usize) -> Vec<InstalledKernel> {
+    newest_first
+        .iter()
+        .filter(|t| !t.pending)
+        .skip(keep.max(1))
+        .filter(|t| !t.running)
+        .cloned()
+        .collect()
+}
+
+/// Installed kernels and the ones `cache` is marked to install, newest first.
+pub fn installed_kernels(cache: &Cache) -> Vec<InstalledKernel> {
+    let running = running_kernel_release();
+    let linux_packages: Vec<_> = cache
+        .packages(&PackageSort::default())
+        .filter(|pkg| pkg.name().starts_with("linux-"))
+        .filter(|pkg| pkg.is_installed() || pkg.marked_install())
+        .collect();
+
+    let mut kernels = Vec::new();
+    for image in &linux_packages {
+        let release = match kernel_release(image.name()) {
+            Some(t) => t.to_owned(),
+            None => continue,
+        };
+        let version = match image.installed().or(image.candidate()) {
+            Some(t) => t.version().to_owned(),
+            None => continue,
+        };
+        let mut packages = Vec::new();
+        let mut installed_size = 0;
+        for pkg in linux_packages
+            .iter()
+            .filter(|pkg| belongs_to_release(pkg.name(), &release))
+        {
+            packages.push(format!("{}:{}", pkg.name(), pkg.arch()));
+            installed_size += match pkg.installed() {
+                Some(t) => t.installed_size(),
+                None => pkg.candidate().map(|t| t.installed_size()).unwrap_or(0),
+            };
+        }
+        kernels.push(InstalledKernel {
+            running: running.as_deref() == Some(release.as_str()),
+            pending: !image.is_installed(),
+            release,
+            version,
+            packages,
+            installed_size,
+        });
+    }
+    kernels.sort_by(|a, b| match cmp_versions(&b.version, &a.version) {
+        Ordering::Equal => b.release.cmp(&a.release),
+        t => t,
+    });
+    kernels
+}
+
+/// Kernels the keep-N policy lets go of.
+pub fn old_kernels(cache: &Cache, keep: usize) -> Vec<InstalledKernel> {
+    kernels_to_remove(&installed_kernels(cache), keep)
+}
+
+/// Kernels on the system, with the ones the pending full upgrade brings in.
+pub fn kernels_with_upgrade() -> Result<Vec<InstalledKernel>, String> {
+    let cache = new_cache!().map_err(|e| e.to_string())?;
+    // Still worth listing what is installed if the upgrade can not be resolved
+    if let Err(e) = cache.upgrade(Upgrade::FullUpgrade) {
+        eprintln!("{}", e);
+    }
+    Ok(installed_kernels(&cache))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kernel(release: &str, running: bool, pending: bool) -> InstalledKernel {
+        InstalledKernel {
+            release: release.to_owned(),
+            version: release.to_owned(),
+            packages: vec![format!("{}{}:amd64", KERNEL_IMAGE_PREFIX, release)],
+            installed_size: 0,
+            running,
+            pending,
+        }
+    }
+
+    fn releases(kernels: Vec<InstalledKernel>) -> Vec<String> {
+        kernels.into_iter().map(|t| t.release).collect()
+    }
+
+    #[test]
+    fn keeps_newest_kernels() {
+        let kernels = [
+            kernel("6.12.3-pikaos", true, false),
+            kernel("6.12.2-pikaos", false, false),
+            kernel("6.12.1-pikaos", false, false),
+            kernel("6.11.9-pikaos", false, false),
+        ];
+        assert_eq!(
+            releases(kernels_to_remove(&kernels, 2)),
+            vec!["6.12.1-pikaos", "6.11.9-pikaos"]
+        );
+    }
+
+    #[test]
+    fn never_removes_running_kernel_when_oldest() {
+        let kernels = [
+            kernel("6.12.3-pikaos", false, false),
+            kernel("6.12.2-pikaos", false, false),
+            kernel("6.12.1-pikaos", false, false),
+            kernel("6.11.9-pikaos", true, false),
+        ];
+        assert_eq!(
+            releases(kernels_to_remove(&kernels, 1)),
+            vec!["6.12.2-pikaos", "6.12.1-pikaos"]
+        );
+    }
+
+    #[test]
+    fn keeps_at_least_one_kernel() {
+        let kernels = [
+            kernel("6.12.3-pikaos", false, false),
+            kernel("6.12.2-pikaos", true, false),
+            kernel("6.12.1-pikaos", false, false),
+        ];
+        assert_eq!(
+            releases(kernels_to_remove(&kernels, 0)),
+            releases(kernels_to_remove(&kernels, 1))
+        );
+        assert_eq!(
+            releases(kernels_to_remove(&kernels, 0)),
+            vec!["6.12.1-pikaos"]
+        );
+    }
+
+    #[test]
+    fn pending_kernel_does_not_count() {
+        let kernels = [
+            kernel("6.13.0-pikaos", false, true),
+            kernel("6.12.3-pikaos", true, false),
+            kernel("6.12.2-pikaos", false, false),
+            kernel("6.12.1-pikaos", false, false),
+        ];
+        assert_eq!(
+            releases(kernels_to_remove(&kernels, 2)),
+            vec!["6.12.1-pikaos"]
+        );
+        // Not installed yet, so there is nothing to remove either
+        assert!(kernels_to_remove(&kernels, 1)
+            .iter()
+            .all(|t| !t.pending && !t.running));
+    }
+}
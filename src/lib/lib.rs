@@ -1,3 +1,20 @@
+pub mod apt_changelog;
+pub mod apt_helper_args;
+pub mod apt_helper_error;
 pub mod apt_install_progress_socket;
 pub mod apt_update_progress_socket;
+pub mod change_explanation;
+pub mod conffile_conflicts;
+pub mod debconf_passthrough;
+pub mod kernels;
+pub mod offline_upgrade;
+pub mod phased_updates;
 pub mod pika_unixsocket_tools;
+pub mod progress_protocol;
+pub mod session_socket;
+pub mod snapshots;
+pub mod system_cleanup;
+pub mod system_health;
+pub mod transaction_history;
+pub mod update_discovery;
+pub mod update_holds;
@@ -0,0 +1,190 @@
+use crate::apt_helper_error::AptHelperErrorKind;
+use crate::pika_unixsocket_tools::ProgressObserver;
+use crate::progress_protocol::{PlannedChange, ProgressMessage};
+use crate::snapshots::SnapshotBackendKind;
+use crate::update_holds::ExclusionsFile;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::os::unix::fs::{symlink, DirBuilderExt, PermissionsExt};
+use std::path::Path;
+
+/// Where `apt_full_upgrade --stage-offline` leaves the transaction for the next boot.
+pub const OFFLINE_UPGRADE_DIR: &str = "/var/lib/pikman-update-manager/offline-upgrade";
+/// Makes systemd boot into `system-update.target`, see systemd.offline-updates(7).
+pub const SYSTEM_UPDATE_LINK: &str = "/system-update";
+/// How the last offline upgrade went, readable by everyone so the GUI can show it.
+pub const OFFLINE_UPGRADE_RESULT_FILE: &str =
+    "/var/lib/pikman-update-manager/offline-upgrade-result.json";
+
+const PLAN_FILE_NAME: &str = "plan.json";
+
+/// What to apply on the next boot, the archives are already in the APT cache.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OfflineUpgradePlan {
+    pub staged_at: String,
+    #[serde(default)]
+    pub exclusions_file: ExclusionsFile,
+    #[serde(default)]
+    pub include_phased_updates: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<SnapshotBackendKind>,
+    /// Who staged it, for the transaction history.
+    #[serde(default)]
+    pub user: String,
+    /// Kernels to keep, older ones are removed with the upgrade.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remove_old_kernels: Option<u32>,
+}
+
+impl OfflineUpgradePlan {
+    pub fn new(
+        exclusions_file: ExclusionsFile,
+        include_phased_updates: bool,
+        snapshot: Option<SnapshotBackendKind>,
+        user: String,
+        remove_old_kernels: Option<u32>,
+    ) -> Self {
+        Self {
+            staged_at: chrono::offset::Local::now().to_rfc3339(),
+            exclusions_file,
+            include_phased_updates,
+            snapshot,
+            user,
+            remove_old_kernels,
+        }
+    }
+}
+
+/// Written by the early-boot run, shown by the GUI at the next login.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OfflineUpgradeResult {
+    pub finished_at: String,
+    pub exit_code: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<AptHelperErrorKind>,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub changes: Vec<PlannedChange>,
+}
+
+impl OfflineUpgradeResult {
+    pub fn is_success(&self) -> bool {
+        self.exit_code == 0
+    }
+
+    /// Picks up what the helper reports, returns true once the run is over.
+    pub fn record(&mut self, message: &ProgressMessage) -> bool {
+        match message {
+            ProgressMessage::TransactionPlan { changes, .. } => self.changes = changes.clone(),
+            ProgressMessage::Error {
+                error_kind,
+                message,
+            } => {
+                self.error_kind = Some(*error_kind);
+                self.message = message.to_owned();
+            }
+            ProgressMessage::PackageError { package, error } if self.message.is_empty() => {
+                self.message = format!("{}: {}", package, error);
+            }
+            ProgressMessage::Finished { exit_code } => {
+                self.exit_code = *exit_code;
+                self.finished_at = chrono::offset::Local::now().to_rfc3339();
+                return true;
+            }
+            _ => {}
+        }
+        false
+    }
+
+    pub fn write(&self) -> io::Result<()> {
+        if let Some(parent) = Path::new(OFFLINE_UPGRADE_RESULT_FILE).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            OFFLINE_UPGRADE_RESULT_FILE,
+            serde_json::to_string_pretty(self).unwrap(),
+        )?;
+        fs::set_permissions(
+            OFFLINE_UPGRADE_RESULT_FILE,
+            fs::Permissions::from_mode(0o644),
+        )
+    }
+
+    pub fn load() -> Option<Self> {
+        serde_json::from_str(&fs::read_to_string(OFFLINE_UPGRADE_RESULT_FILE).ok()?).ok()
+    }
+}
+
+/// Keeps track of an offline run and saves how it went once it is over.
+impl ProgressObserver for OfflineUpgradeResult {
+    fn observe(&mut self, message: &ProgressMessage) {
+        if self.record(message) {
+            if let Err(e) = self.write() {
+                eprintln!("Couldn't save the offline upgrade result: {}", e);
+            }
+        }
+    }
+}
+
+/// Saves the plan and points `/system-update` at it, root only. Refuses when another
+/// offline updater already staged something there.
+pub fn stage_offline_upgrade(plan: &OfflineUpgradePlan) -> io::Result<()> {
+    let staged_by_us = check_system_update_link()?;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o755)
+        .create(OFFLINE_UPGRADE_DIR)?;
+    fs::write(
+        Path::new(OFFLINE_UPGRADE_DIR).join(PLAN_FILE_NAME),
+        serde_json::to_string_pretty(plan).unwrap(),
+    )?;
+    if staged_by_us {
+        fs::remove_file(SYSTEM_UPDATE_LINK)?;
+    }
+    symlink(OFFLINE_UPGRADE_DIR, SYSTEM_UPDATE_LINK)
+}
+
+/// Whether `/system-update` is ours, false when there is none and an error when it belongs
+/// to someone else.
+fn check_system_update_link() -> io::Result<bool> {
+    match fs::read_link(SYSTEM_UPDATE_LINK) {
+        Ok(t) if t == Path::new(OFFLINE_UPGRADE_DIR) => Ok(true),
+        Ok(t) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} points at {}, another offline update is staged",
+                SYSTEM_UPDATE_LINK,
+                t.display()
+            ),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        // Not a link at all
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a link", SYSTEM_UPDATE_LINK),
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether `/system-update` points at our staged upgrade.
+pub fn is_offline_upgrade_staged() -> bool {
+    fs::read_link(SYSTEM_UPDATE_LINK).is_ok_and(|t| t == Path::new(OFFLINE_UPGRADE_DIR))
+        && Path::new(OFFLINE_UPGRADE_DIR).join(PLAN_FILE_NAME).exists()
+}
+
+/// Unstages the upgrade and hands back its plan. The plan goes first, without it the
+/// service does not start again, so a failed run boots normally next time instead of
+/// looping. The service already cleared our link, one pointing elsewhere means another
+/// updater's turn and is refused.
+pub fn take_offline_upgrade_plan() -> io::Result<OfflineUpgradePlan> {
+    let plan_path = Path::new(OFFLINE_UPGRADE_DIR).join(PLAN_FILE_NAME);
+    let plan = fs::read_to_string(&plan_path);
+    fs::remove_file(&plan_path)?;
+    if check_system_update_link()? {
+        fs::remove_file(SYSTEM_UPDATE_LINK)?;
+    }
+    serde_json::from_str(&plan?).map_err(io::Error::other)
+}
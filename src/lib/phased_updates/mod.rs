@@ -0,0 +1,37 @@
+use rust_apt::config::Config;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// libapt's own switch, with it unset the resolver keeps back the phased updates this
+/// machine is not due for yet.
+pub const ALWAYS_INCLUDE_PHASED_UPDATES: &str = "APT::Get::Always-Include-Phased-Updates";
+
+/// Whether `apply` set the switch, so an admin's apt.conf setting is never cleared.
+static INCLUDE_SET: AtomicBool = AtomicBool::new(false);
+
+/// Whether this machine waits for phased updates, shared by the update list and
+/// `apt_full_upgrade` so both agree.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PhasingPolicy {
+    /// Take every phased update right away.
+    pub include_phased_updates: bool,
+}
+
+impl PhasingPolicy {
+    pub fn new(include_phased_updates: bool) -> Self {
+        Self {
+            include_phased_updates,
+        }
+    }
+
+    /// Hands the choice to libapt, it holds for every cache resolved in this process
+    /// afterwards. Deferred phased updates then show up as kept back.
+    pub fn apply(&self) {
+        let apt_config = Config::new();
+        if self.include_phased_updates {
+            apt_config.set(ALWAYS_INCLUDE_PHASED_UPDATES, "true");
+            INCLUDE_SET.store(true, Ordering::SeqCst);
+        } else if INCLUDE_SET.swap(false, Ordering::SeqCst) {
+            apt_config.clear(ALWAYS_INCLUDE_PHASED_UPDATES);
+        }
+    }
+}
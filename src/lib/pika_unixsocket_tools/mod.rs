@@ -1,172 +1,483 @@
+use crate::apt_helper_args::ConsoleOutput;
+use crate::progress_protocol::{
+    decode_message, decode_reply, encode_message, encode_reply, LineBuffer, ProgressMessage,
+    ProgressReply,
+};
+use crate::session_socket::PeerCredPolicy;
 use chrono;
+use std::collections::VecDeque;
 use std::fs;
 use std::fs::OpenOptions;
-use std::io::Write;
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener as StdUnixListener, UnixStream as StdUnixStream};
 use std::path::Path;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{UnixListener, UnixStream};
 use tokio::task;
 
-pub async fn send_successful_to_socket(socket_path: &str) {
-    // Connect to the Unix socket
-    let mut stream = UnixStream::connect(socket_path)
-        .await
-        .expect("Could not connect to server");
+/// How many frames are kept around while the GUI is unreachable.
+const MAX_PENDING_FRAMES: usize = 512;
+/// Minimum time between two reconnect attempts.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+/// A stuck GUI must never stall dpkg for longer than this.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
 
-    let message = "FN_OVERRIDE_SUCCESSFUL";
+/// Something besides the GUI that follows a helper's progress, like the terminal or a
+/// record of the run.
+pub trait ProgressObserver {
+    fn observe(&mut self, message: &ProgressMessage);
+}
 
-    // Send the message to the server
-    stream
-        .write_all(message.as_bytes())
-        .await
-        .expect("Failed to write to stream");
+/// Long lived connection from a helper binary to the GUI progress socket.
+///
+/// The client never panics: if the GUI goes away frames are buffered and the
+/// connection is retried, and once the buffer is full the oldest frames are dropped.
+/// Everything that would be logged is also appended to the fallback log while
+/// nobody is listening, so a closed window can not abort a running transaction.
+pub struct ProgressSocketClient {
+    socket_path: Option<String>,
+    stream: Option<StdUnixStream>,
+    pending: VecDeque<Vec<u8>>,
+    last_connect_attempt: Option<Instant>,
+    fallback_log_path: Option<String>,
+    observers: Vec<Box<dyn ProgressObserver>>,
+    replies: LineBuffer,
 }
 
-pub async fn send_failed_to_socket(socket_path: &str) {
-    // Connect to the Unix socket
-    let mut stream = UnixStream::connect(socket_path)
-        .await
-        .expect("Could not connect to server");
+impl ProgressSocketClient {
+    pub fn new(socket_path: &str) -> Self {
+        let mut client = Self {
+            socket_path: Some(socket_path.to_owned()),
+            stream: None,
+            pending: VecDeque::new(),
+            last_connect_attempt: None,
+            fallback_log_path: None,
+            observers: Vec::new(),
+            replies: LineBuffer::default(),
+        };
+        client.try_connect();
+        client
+    }
 
-    let message = "FN_OVERRIDE_FAILED";
+    /// A client that only ever writes to the fallback log.
+    pub fn disconnected() -> Self {
+        Self {
+            socket_path: None,
+            stream: None,
+            pending: VecDeque::new(),
+            last_connect_attempt: None,
+            fallback_log_path: None,
+            observers: Vec::new(),
+            replies: LineBuffer::default(),
+        }
+    }
 
-    // Send the message to the server
-    stream
-        .write_all(message.as_bytes())
-        .await
-        .expect("Failed to write to stream");
-}
+    pub fn with_fallback_log(mut self, fallback_log_path: &str) -> Self {
+        self.fallback_log_path = Some(fallback_log_path.to_owned());
+        self
+    }
 
-// Function to handle a single client connection
-pub async fn handle_client(
-    mut stream: UnixStream,
-    buffer_sender: async_channel::Sender<String>,
-    log_file_path: String,
-) {
-    // Buffer to store incoming data
-    let mut buffer = [0; 1024];
-
-    if !Path::new(&log_file_path).exists() {
-        match fs::File::create(&log_file_path) {
-            Ok(_) => {}
-            Err(_) => {
-                eprintln!("Warning: {} file couldn't be created", log_file_path);
+    /// Also print every message to stdout, for helpers started from a terminal.
+    pub fn with_console_output(mut self, console_output: ConsoleOutput) -> Self {
+        self.add_observer(ConsoleProgress::new(console_output));
+        self
+    }
+
+    /// Show every message sent from now on to `observer` as well.
+    pub fn add_observer(&mut self, observer: impl ProgressObserver + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// The GUI's progress socket, `None` for a disconnected client.
+    pub fn socket_path(&self) -> Option<&str> {
+        self.socket_path.as_deref()
+    }
+
+    pub fn send(&mut self, message: &ProgressMessage) {
+        for observer in &mut self.observers {
+            observer.observe(message);
+        }
+        if self.socket_path.is_none() {
+            self.write_fallback_log(message);
+            return;
+        }
+        if self.pending.len() >= MAX_PENDING_FRAMES {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(encode_message(message));
+
+        if self.stream.is_none() {
+            self.try_connect();
+        }
+        if !self.flush() {
+            self.write_fallback_log(message);
+        }
+    }
+
+    /// Waits for the GUI to answer a prompt, `None` if nobody is listening, the
+    /// connection drops or `timeout` runs out.
+    pub fn wait_for_reply(&mut self, timeout: Duration) -> Option<ProgressReply> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(line) = self.replies.next_line() {
+                match decode_reply(&line) {
+                    Ok(t) => return Some(t),
+                    Err(e) => {
+                        eprintln!("Dropping reply frame: {}", e);
+                        continue;
+                    }
+                }
+            }
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            let stream = self.stream.as_mut()?;
+            // A zero timeout would mean blocking forever
+            let _ = stream.set_read_timeout(Some(remaining.max(Duration::from_millis(1))));
+            let mut chunk = [0u8; 4096];
+            match stream.read(&mut chunk) {
+                Ok(0) => {
+                    self.stream = None;
+                    return None;
+                }
+                Ok(n) => self.replies.extend(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return None
+                }
+                Err(_) => {
+                    self.stream = None;
+                    return None;
+                }
             }
+        }
+    }
+
+    fn try_connect(&mut self) {
+        let socket_path = match &self.socket_path {
+            Some(t) => t,
+            None => return,
         };
+        if let Some(last_attempt) = self.last_connect_attempt {
+            if last_attempt.elapsed() < RECONNECT_INTERVAL {
+                return;
+            }
+        }
+        self.last_connect_attempt = Some(Instant::now());
+        match StdUnixStream::connect(socket_path) {
+            Ok(stream) => {
+                let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+                self.stream = Some(stream);
+            }
+            Err(_) => self.stream = None,
+        }
     }
 
-    // Read data from the stream
-    match stream.read(&mut buffer).await {
-        Ok(size) => {
-            let message = String::from_utf8_lossy(&buffer[..size]).to_string();
-            // Send to async buffer sender
-            buffer_sender
-                .send_blocking(message.clone())
-                .expect("Buffer channel closed");
-            // Write to log file
-            let mut log_file = OpenOptions::new()
-                .write(true)
-                .append(true)
-                .open(&log_file_path)
-                .unwrap();
-
-            if let Err(e) = writeln!(
+    /// Writes out every pending frame, returns false if the connection dropped on the way.
+    fn flush(&mut self) -> bool {
+        let mut stream = match self.stream.take() {
+            Some(t) => t,
+            None => return false,
+        };
+        while let Some(frame) = self.pending.front() {
+            match stream.write_all(frame) {
+                Ok(_) => {
+                    self.pending.pop_front();
+                }
+                Err(_) => return false,
+            }
+        }
+        self.stream = Some(stream);
+        true
+    }
+
+    fn write_fallback_log(&self, message: &ProgressMessage) {
+        let (log_file_path, log_line) = match (&self.fallback_log_path, message.log_line()) {
+            (Some(p), Some(l)) => (p, l),
+            _ => return,
+        };
+        if let Some(parent) = Path::new(log_file_path).parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Ok(mut log_file) = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_file_path)
+        {
+            let _ = writeln!(
                 log_file,
                 "[{}] {}",
                 chrono::offset::Local::now().format("%Y/%m/%d_%H:%M"),
-                message
-            ) {
-                eprintln!("Couldn't write to file: {}", e);
-            }
+                log_line
+            );
+        }
+    }
+}
+
+/// Prints a helper's progress for whoever started it, on a terminal, as JSON or on the
+/// Plymouth boot splash during offline upgrades.
+pub struct ConsoleProgress {
+    console_output: ConsoleOutput,
+    last_plymouth_percent: Option<u32>,
+}
+
+impl ConsoleProgress {
+    pub fn new(console_output: ConsoleOutput) -> Self {
+        Self {
+            console_output,
+            last_plymouth_percent: None,
         }
-        Err(e) => {
-            // Print error message if reading fails
-            eprintln!("Failed to read from stream: {}", e);
+    }
+}
+
+impl ProgressObserver for ConsoleProgress {
+    fn observe(&mut self, message: &ProgressMessage) {
+        match self.console_output {
+            ConsoleOutput::Silent => {}
+            ConsoleOutput::Text => {
+                if let Some(log_line) = message.log_line() {
+                    println!("{}", log_line);
+                }
+            }
+            ConsoleOutput::Plymouth => {
+                if let Some(log_line) = message.log_line() {
+                    println!("{}", log_line);
+                }
+                // Plymouth may not be running at all, that is fine
+                match message {
+                    ProgressMessage::Percent { percent } => {
+                        let percent = *percent as u32;
+                        if self.last_plymouth_percent != Some(percent) {
+                            self.last_plymouth_percent = Some(percent);
+                            let _ = Command::new("plymouth")
+                                .args(["system-update", &format!("--progress={}", percent)])
+                                .status();
+                        }
+                    }
+                    ProgressMessage::Status { message } => {
+                        let _ = Command::new("plymouth")
+                            .args(["display-message", &format!("--text={}", message)])
+                            .status();
+                    }
+                    _ => {}
+                }
+            }
+            ConsoleOutput::Json => {
+                let mut stdout = io::stdout().lock();
+                let _ = stdout.write_all(&encode_message(message));
+                let _ = stdout.flush();
+            }
         }
     }
 }
 
-pub async fn start_socket_server(
-    buffer_sender: async_channel::Sender<String>,
-    socket_path: &str,
-    log_file_path: &str,
+// Function to handle a single client connection
+pub async fn handle_client(
+    stream: UnixStream,
+    buffer_sender: async_channel::Sender<ProgressMessage>,
+    reply_receiver: Option<async_channel::Receiver<ProgressReply>>,
+    log_file_path: Option<String>,
 ) {
-    // Remove the socket file if it already exists
-    if Path::new(socket_path).exists() {
-        fs::remove_file(socket_path).expect("Could not remove existing socket file");
+    if let Some(log_file_path) = &log_file_path {
+        if !Path::new(log_file_path).exists() {
+            match fs::File::create(log_file_path) {
+                Ok(_) => {}
+                Err(_) => {
+                    eprintln!("Warning: {} file couldn't be created", log_file_path);
+                }
+            };
+        }
     }
 
-    // Bind the Unix listener to the socket path
-    let listener = UnixListener::bind(socket_path).expect("Could not bind");
+    let (read_half, mut write_half) = stream.into_split();
+    // Every frame is a single line, so messages can be neither truncated nor merged
+    let mut lines = BufReader::new(read_half).split(b'\n');
 
-    println!("Server listening on {}", socket_path);
-
-    // Loop to accept incoming connections
     loop {
-        // Accept an incoming connection
-        match listener.accept().await {
-            Ok((stream, _)) => {
-                // Handle the connection in a separate task
-                task::spawn(handle_client(
-                    stream,
-                    buffer_sender.clone(),
-                    log_file_path.to_owned(),
-                ));
+        let segment = tokio::select! {
+            segment = lines.next_segment() => segment,
+            Some(reply) = next_reply(&reply_receiver) => {
+                if let Err(e) = write_half.write_all(&encode_reply(&reply)).await {
+                    eprintln!("Failed to answer the helper: {}", e);
+                }
+                continue;
+            }
+        };
+        match segment {
+            Ok(Some(line)) => {
+                if line.is_empty() {
+                    continue;
+                }
+                let message = match decode_message(&line) {
+                    Ok(t) => t,
+                    Err(e) => {
+                        eprintln!("Dropping progress frame: {}", e);
+                        continue;
+                    }
+                };
+                // Write to log file
+                if let (Some(log_file_path), Some(log_line)) = (&log_file_path, message.log_line())
+                {
+                    match OpenOptions::new()
+                        .write(true)
+                        .append(true)
+                        .open(log_file_path)
+                    {
+                        Ok(mut log_file) => {
+                            if let Err(e) = writeln!(
+                                log_file,
+                                "[{}] {}",
+                                chrono::offset::Local::now().format("%Y/%m/%d_%H:%M"),
+                                log_line
+                            ) {
+                                eprintln!("Couldn't write to file: {}", e);
+                            }
+                        }
+                        Err(e) => eprintln!("Couldn't open {}: {}", log_file_path, e),
+                    }
+                }
+                // Send to async buffer sender, the page that listened might be gone already
+                if buffer_sender.send(message).await.is_err() {
+                    break;
+                }
             }
+            Ok(None) => break,
             Err(e) => {
-                // Print error message if a connection fails
-                eprintln!("Connection failed: {}", e);
+                // Print error message if reading fails
+                eprintln!("Failed to read from stream: {}", e);
+                break;
             }
         }
     }
 }
 
-pub async fn handle_client_no_log(
-    mut stream: UnixStream,
-    buffer_sender: async_channel::Sender<String>,
-) {
-    // Buffer to store incoming data
-    let mut buffer = [0; 1024];
+/// The next answer the GUI wants to send, never resolves for sockets that take none.
+async fn next_reply(
+    reply_receiver: &Option<async_channel::Receiver<ProgressReply>>,
+) -> Option<ProgressReply> {
+    match reply_receiver {
+        Some(t) => t.recv().await.ok(),
+        None => std::future::pending().await,
+    }
+}
 
-    // Read data from the stream
-    match stream.read(&mut buffer).await {
-        Ok(size) => {
-            let message = String::from_utf8_lossy(&buffer[..size]).to_string();
-            // Write to log file
+pub async fn start_socket_server(
+    buffer_sender: async_channel::Sender<ProgressMessage>,
+    listener: StdUnixListener,
+    log_file_path: &str,
+    peer_policy: PeerCredPolicy,
+) {
+    socket_server_loop(
+        buffer_sender,
+        None,
+        listener,
+        Some(log_file_path.to_owned()),
+        peer_policy,
+    )
+    .await
+}
 
-            // Send to async buffer sender
-            buffer_sender
-                .send_blocking(message)
-                .expect("Buffer channel closed")
-        }
-        Err(e) => {
-            // Print error message if reading fails
-            eprintln!("Failed to read from stream: {}", e);
-        }
-    }
+/// Like `start_socket_server`, and also passes the GUI's answers to prompts back to the helper.
+pub async fn start_interactive_socket_server(
+    buffer_sender: async_channel::Sender<ProgressMessage>,
+    reply_receiver: async_channel::Receiver<ProgressReply>,
+    listener: StdUnixListener,
+    log_file_path: &str,
+    peer_policy: PeerCredPolicy,
+) {
+    socket_server_loop(
+        buffer_sender,
+        Some(reply_receiver),
+        listener,
+        Some(log_file_path.to_owned()),
+        peer_policy,
+    )
+    .await
 }
 
 pub async fn start_socket_server_no_log(
-    buffer_sender: async_channel::Sender<String>,
-    socket_path: &str,
+    buffer_sender: async_channel::Sender<ProgressMessage>,
+    listener: StdUnixListener,
+    peer_policy: PeerCredPolicy,
 ) {
-    // Remove the socket file if it already exists
-    if Path::new(socket_path).exists() {
-        fs::remove_file(socket_path).expect("Could not remove existing socket file");
-    }
+    socket_server_loop(buffer_sender, None, listener, None, peer_policy).await
+}
 
-    // Bind the Unix listener to the socket path
-    let listener = UnixListener::bind(socket_path).expect("Could not bind");
+async fn socket_server_loop(
+    buffer_sender: async_channel::Sender<ProgressMessage>,
+    reply_receiver: Option<async_channel::Receiver<ProgressReply>>,
+    listener: StdUnixListener,
+    log_file_path: Option<String>,
+    peer_policy: PeerCredPolicy,
+) {
+    // The listener is bound by the caller inside its private session directory
+    listener
+        .set_nonblocking(true)
+        .expect("Could not make listener non-blocking");
+    let listener = UnixListener::from_std(listener).expect("Could not register listener");
 
-    println!("Server listening on {}", socket_path);
+    // Every connection sees every reply, a helper can talk to us over more than one and
+    // each of them skips the answers it did not ask for
+    let connection_reply_senders: Arc<Mutex<Vec<async_channel::Sender<ProgressReply>>>> =
+        Arc::new(Mutex::new(Vec::new()));
+    if let Some(reply_receiver) = reply_receiver.clone() {
+        let connection_reply_senders = connection_reply_senders.clone();
+        task::spawn(async move {
+            while let Ok(reply) = reply_receiver.recv().await {
+                connection_reply_senders
+                    .lock()
+                    .unwrap()
+                    .retain(|t| t.try_send(reply.clone()).is_ok());
+            }
+        });
+    }
 
     // Loop to accept incoming connections
     loop {
         // Accept an incoming connection
         match listener.accept().await {
             Ok((stream, _)) => {
+                match stream.peer_cred() {
+                    Ok(cred) if peer_policy.allows(cred.uid(), cred.pid()) => {}
+                    Ok(cred) => {
+                        eprintln!(
+                            "Rejected progress connection from uid {} pid {:?}",
+                            cred.uid(),
+                            cred.pid()
+                        );
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("Couldn't read peer credentials: {}", e);
+                        continue;
+                    }
+                }
+                let connection_reply_receiver = reply_receiver.as_ref().map(|_| {
+                    let (connection_reply_sender, connection_reply_receiver) =
+                        async_channel::unbounded();
+                    connection_reply_senders
+                        .lock()
+                        .unwrap()
+                        .push(connection_reply_sender);
+                    connection_reply_receiver
+                });
                 // Handle the connection in a separate task
-                task::spawn(handle_client_no_log(stream, buffer_sender.clone()));
+                task::spawn(handle_client(
+                    stream,
+                    buffer_sender.clone(),
+                    connection_reply_receiver,
+                    log_file_path.clone(),
+                ));
             }
             Err(e) => {
                 // Print error message if a connection fails
@@ -0,0 +1,455 @@
+use crate::apt_helper_error::AptHelperErrorKind;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Version of the progress schema, bump it whenever `ProgressMessage` changes in a way
+/// an older GUI could not understand.
+pub const PROGRESS_PROTOCOL_VERSION: u32 = 5;
+
+/// What is going to happen to a single package.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeAction {
+    Upgrade,
+    Install,
+    Downgrade,
+    Remove,
+}
+
+impl ChangeAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeAction::Upgrade => "upgrade",
+            ChangeAction::Install => "install",
+            ChangeAction::Downgrade => "downgrade",
+            ChangeAction::Remove => "remove",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlannedChange {
+    pub package: String,
+    pub arch: String,
+    pub action: ChangeAction,
+    pub current_version: Option<String>,
+    pub new_version: Option<String>,
+    pub download_size: u64,
+}
+
+/// What to do with a configuration file the user changed and a package ships anew.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictAnswer {
+    /// Keep the local file, the package's version stays next to it as `.dpkg-dist`.
+    Keep,
+    /// Install the package's version, the local file is kept as `.dpkg-old`.
+    Replace,
+    /// Keep the local file for now and note the conflict in the history.
+    MergeLater,
+}
+
+impl ConflictAnswer {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConflictAnswer::Keep => "keep",
+            ConflictAnswer::Replace => "replace",
+            ConflictAnswer::MergeLater => "merge_later",
+        }
+    }
+}
+
+/// How a debconf question wants to be answered.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DebconfQuestionKind {
+    Select,
+    Multiselect,
+    Boolean,
+    String,
+    Password,
+    /// Notes, texts and errors, nothing to answer.
+    Note,
+}
+
+/// A question a maintainer script asked through debconf.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DebconfQuestion {
+    pub tag: String,
+    pub kind: DebconfQuestionKind,
+    pub description: String,
+    #[serde(default)]
+    pub extended_description: String,
+    #[serde(default)]
+    pub choices: Vec<String>,
+    /// The current answer, `true`/`false` for booleans and comma separated for multiselects.
+    #[serde(default)]
+    pub value: String,
+}
+
+/// Every message a helper binary can send to the GUI over the progress socket.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProgressMessage {
+    Percent {
+        percent: f32,
+    },
+    Status {
+        message: String,
+    },
+    Speed {
+        speed: String,
+    },
+    ItemHit {
+        message: String,
+    },
+    ItemFetch {
+        message: String,
+    },
+    ItemDone {
+        message: String,
+    },
+    ItemFail {
+        message: String,
+    },
+    PackageError {
+        package: String,
+        error: String,
+    },
+    Error {
+        error_kind: AptHelperErrorKind,
+        message: String,
+    },
+    TransactionPlan {
+        changes: Vec<PlannedChange>,
+        download_size: u64,
+        installed_size_change: i64,
+        /// Upgrades the resolver had to leave out because of an exclusion or hold.
+        #[serde(default)]
+        held_back: Vec<String>,
+    },
+    Finished {
+        exit_code: i32,
+    },
+    ConflictPrompt {
+        package: String,
+        path: String,
+        diff: String,
+    },
+    /// Questions debconf shows together on one page.
+    DebconfPrompt {
+        id: u32,
+        title: String,
+        questions: Vec<DebconfQuestion>,
+    },
+}
+
+impl ProgressMessage {
+    /// Returns the line that should end up in the transaction log file, if any.
+    ///
+    /// Percent and speed updates are too noisy to be logged.
+    pub fn log_line(&self) -> Option<String> {
+        match self {
+            ProgressMessage::Percent { .. } | ProgressMessage::Speed { .. } => None,
+            ProgressMessage::Status { message }
+            | ProgressMessage::ItemHit { message }
+            | ProgressMessage::ItemFetch { message }
+            | ProgressMessage::ItemDone { message }
+            | ProgressMessage::ItemFail { message } => Some(message.to_owned()),
+            ProgressMessage::PackageError { package, error } => {
+                Some(format!("{}: {}", package, error))
+            }
+            ProgressMessage::Error {
+                error_kind,
+                message,
+            } => Some(format!("{}: {}", error_kind.as_str(), message)),
+            ProgressMessage::TransactionPlan {
+                changes, held_back, ..
+            } if changes.is_empty() && held_back.is_empty() => None,
+            ProgressMessage::TransactionPlan {
+                changes, held_back, ..
+            } => Some(
+                changes
+                    .iter()
+                    .map(|change| {
+                        format!(
+                            "{} {}:{} {} -> {}",
+                            change.action.as_str(),
+                            change.package,
+                            change.arch,
+                            change.current_version.as_deref().unwrap_or("-"),
+                            change.new_version.as_deref().unwrap_or("-")
+                        )
+                    })
+                    .chain(
+                        held_back
+                            .iter()
+                            .map(|package| format!("held back {}", package)),
+                    )
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            ),
+            ProgressMessage::Finished { exit_code } => Some(format!("exit code: {}", exit_code)),
+            ProgressMessage::ConflictPrompt { package, path, .. } => {
+                Some(format!("{}: {}", package, path))
+            }
+            ProgressMessage::DebconfPrompt { questions, .. } => Some(
+                questions
+                    .iter()
+                    .map(|question| format!("debconf {}: {}", question.tag, question.description))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            ),
+        }
+    }
+}
+
+/// What the GUI answers on the same connection, only sent after a prompt.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProgressReply {
+    ConflictAnswer {
+        path: String,
+        answer: ConflictAnswer,
+    },
+    /// The new values by question tag, questions left out keep theirs.
+    DebconfAnswer {
+        id: u32,
+        values: HashMap<String, String>,
+    },
+}
+
+/// A single message on the wire, one JSON object per line.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProgressFrame {
+    pub version: u32,
+    pub message: ProgressMessage,
+}
+
+/// A single reply on the wire, framed like `ProgressFrame`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProgressReplyFrame {
+    pub version: u32,
+    pub reply: ProgressReply,
+}
+
+#[derive(Debug)]
+pub enum ProgressDecodeError {
+    Json(serde_json::Error),
+    VersionMismatch(u32),
+}
+
+impl fmt::Display for ProgressDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgressDecodeError::Json(e) => write!(f, "malformed progress frame: {}", e),
+            ProgressDecodeError::VersionMismatch(v) => write!(
+                f,
+                "progress protocol version {} is not supported (expected {})",
+                v, PROGRESS_PROTOCOL_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProgressDecodeError {}
+
+/// Encodes a message as a newline terminated frame ready to be written to the socket.
+pub fn encode_message(message: &ProgressMessage) -> Vec<u8> {
+    let frame = ProgressFrame {
+        version: PROGRESS_PROTOCOL_VERSION,
+        message: message.clone(),
+    };
+    // Serializing a plain enum of strings and numbers can not fail
+    let mut bytes = serde_json::to_vec(&frame).expect("Failed to serialize progress frame");
+    bytes.push(b'\n');
+    bytes
+}
+
+/// Decodes a single frame, the trailing newline is optional.
+pub fn decode_message(line: &[u8]) -> Result<ProgressMessage, ProgressDecodeError> {
+    let frame: ProgressFrame =
+        serde_json::from_slice(line.trim_ascii_end()).map_err(ProgressDecodeError::Json)?;
+    if frame.version != PROGRESS_PROTOCOL_VERSION {
+        return Err(ProgressDecodeError::VersionMismatch(frame.version));
+    }
+    Ok(frame.message)
+}
+
+/// Same framing as `encode_message`, for the answers the GUI sends back.
+pub fn encode_reply(reply: &ProgressReply) -> Vec<u8> {
+    let frame = ProgressReplyFrame {
+        version: PROGRESS_PROTOCOL_VERSION,
+        reply: reply.clone(),
+    };
+    let mut bytes = serde_json::to_vec(&frame).expect("Failed to serialize reply frame");
+    bytes.push(b'\n');
+    bytes
+}
+
+pub fn decode_reply(line: &[u8]) -> Result<ProgressReply, ProgressDecodeError> {
+    let frame: ProgressReplyFrame =
+        serde_json::from_slice(line.trim_ascii_end()).map_err(ProgressDecodeError::Json)?;
+    if frame.version != PROGRESS_PROTOCOL_VERSION {
+        return Err(ProgressDecodeError::VersionMismatch(frame.version));
+    }
+    Ok(frame.reply)
+}
+
+/// Bytes read off a connection, handed out a frame at a time once a whole one arrived.
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    bytes: Vec<u8>,
+}
+
+impl LineBuffer {
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// The next complete line, newline included.
+    pub fn next_line(&mut self) -> Option<Vec<u8>> {
+        let newline = self.bytes.iter().position(|t| *t == b'\n')?;
+        Some(self.bytes.drain(..=newline).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::io::{BufRead, BufReader, Read};
+
+    /// Hands out one chunk per `read`, like a socket that got the bytes in pieces.
+    struct ChunkedReader {
+        chunks: VecDeque<Vec<u8>>,
+    }
+
+    impl Read for ChunkedReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let chunk = match self.chunks.pop_front() {
+                Some(t) => t,
+                None => return Ok(0),
+            };
+            let n = chunk.len().min(buf.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            if n < chunk.len() {
+                self.chunks.push_front(chunk[n..].to_vec());
+            }
+            Ok(n)
+        }
+    }
+
+    fn read_frames(chunks: Vec<Vec<u8>>) -> Vec<ProgressMessage> {
+        BufReader::new(ChunkedReader {
+            chunks: chunks.into(),
+        })
+        .split(b'\n')
+        .map(|line| decode_message(&line.unwrap()).unwrap())
+        .collect()
+    }
+
+    fn messages() -> Vec<ProgressMessage> {
+        vec![
+            ProgressMessage::Percent { percent: 42.5 },
+            ProgressMessage::Status {
+                message: "Unpacking\u{2026} \"quoted\"\nnext line".to_owned(),
+            },
+            ProgressMessage::Error {
+                error_kind: AptHelperErrorKind::LockHeld,
+                message: "E: Could not get lock".to_owned(),
+            },
+            ProgressMessage::TransactionPlan {
+                changes: vec![PlannedChange {
+                    package: "mesa".to_owned(),
+                    arch: "amd64".to_owned(),
+                    action: ChangeAction::Upgrade,
+                    current_version: Some("1.0".to_owned()),
+                    new_version: Some("2.0".to_owned()),
+                    download_size: 1024,
+                }],
+                download_size: 1024,
+                installed_size_change: -512,
+                held_back: vec!["gcc".to_owned()],
+            },
+            ProgressMessage::Finished { exit_code: 0 },
+        ]
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        for message in messages() {
+            let frame = encode_message(&message);
+            assert_eq!(frame.last(), Some(&b'\n'));
+            assert_eq!(frame.iter().filter(|t| **t == b'\n').count(), 1);
+            assert_eq!(decode_message(&frame).unwrap(), message);
+            assert_eq!(decode_message(&frame[..frame.len() - 1]).unwrap(), message);
+        }
+        let reply = ProgressReply::ConflictAnswer {
+            path: "/etc/foo.conf".to_owned(),
+            answer: ConflictAnswer::Replace,
+        };
+        assert_eq!(decode_reply(&encode_reply(&reply)).unwrap(), reply);
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let frame = format!(
+            "{{\"version\":{},\"message\":{{\"kind\":\"finished\",\"exit_code\":0}}}}\n",
+            PROGRESS_PROTOCOL_VERSION + 1
+        );
+        assert!(matches!(
+            decode_message(frame.as_bytes()),
+            Err(ProgressDecodeError::VersionMismatch(v)) if v == PROGRESS_PROTOCOL_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn truncated_frame_is_rejected() {
+        let frame = encode_message(&ProgressMessage::Status {
+            message: "Setting up mesa".to_owned(),
+        });
+        assert!(matches!(
+            decode_message(&frame[..frame.len() / 2]),
+            Err(ProgressDecodeError::Json(_))
+        ));
+        assert!(matches!(
+            decode_message(b"not json\n"),
+            Err(ProgressDecodeError::Json(_))
+        ));
+    }
+
+    #[test]
+    fn several_frames_in_one_read() {
+        let bytes: Vec<u8> = messages().iter().flat_map(encode_message).collect();
+        assert_eq!(read_frames(vec![bytes]), messages());
+    }
+
+    #[test]
+    fn frame_split_across_reads() {
+        let bytes: Vec<u8> = messages().iter().flat_map(encode_message).collect();
+        // Every boundary falls somewhere inside a frame
+        let chunks: Vec<Vec<u8>> = bytes.chunks(7).map(|t| t.to_vec()).collect();
+        assert_eq!(read_frames(chunks), messages());
+        let single_bytes: Vec<Vec<u8>> = bytes.iter().map(|t| vec![*t]).collect();
+        assert_eq!(read_frames(single_bytes), messages());
+    }
+
+    #[test]
+    fn line_buffer_waits_for_whole_frames() {
+        let reply = encode_reply(&ProgressReply::ConflictAnswer {
+            path: "/etc/foo.conf".to_owned(),
+            answer: ConflictAnswer::Keep,
+        });
+        let mut line_buffer = LineBuffer::default();
+        line_buffer.extend(&reply[..5]);
+        assert_eq!(line_buffer.next_line(), None);
+        line_buffer.extend(&reply[5..]);
+        line_buffer.extend(&reply[..5]);
+        assert_eq!(line_buffer.next_line(), Some(reply.clone()));
+        assert_eq!(line_buffer.next_line(), None);
+        line_buffer.extend(&reply[5..]);
+        assert_eq!(line_buffer.next_line(), Some(reply));
+    }
+}
@@ -0,0 +1,215 @@
+use std::env;
+use std::fs;
+use std::fs::DirBuilder;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+
+/// Name of the progress socket inside a session directory.
+pub const PROGRESS_SOCKET_NAME: &str = "progress.sock";
+
+const RUNTIME_DIR_NAME: &str = "pikman-update-manager";
+
+pub fn current_uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and can not fail
+    unsafe { libc::geteuid() }
+}
+
+/// The uid of the user who started a privileged helper through pkexec or sudo.
+pub fn invoking_uid() -> u32 {
+    for var in ["PKEXEC_UID", "SUDO_UID"] {
+        if let Some(uid) = env::var(var).ok().and_then(|v| v.parse::<u32>().ok()) {
+            return uid;
+        }
+    }
+    current_uid()
+}
+
+fn runtime_base_dir() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(t) if !t.is_empty() => PathBuf::from(t),
+        _ => PathBuf::from(format!("/run/user/{}", current_uid())),
+    }
+}
+
+/// Checks that `dir` is a real directory owned by `uid` that nobody else can enter.
+fn check_private_dir(dir: &Path, uid: u32) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(dir)?;
+    if !metadata.file_type().is_dir() {
+        return Err(io::Error::other(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+    if metadata.uid() != uid {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not owned by uid {}", dir.display(), uid),
+        ));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is accessible by other users", dir.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Creates a fresh directory only the current user can access, to hold the sockets of
+/// a single helper run.
+pub fn create_session_socket_dir() -> io::Result<PathBuf> {
+    let app_dir = runtime_base_dir().join(RUNTIME_DIR_NAME);
+    match DirBuilder::new().mode(0o700).create(&app_dir) {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+    check_private_dir(&app_dir, current_uid())?;
+
+    let session_dir = app_dir.join(format!(
+        "session-{}-{}",
+        std::process::id(),
+        chrono::offset::Local::now().format("%Y%m%d%H%M%S%f")
+    ));
+    // `create` fails if the path already exists, so we never reuse someone else's directory
+    DirBuilder::new().mode(0o700).create(&session_dir)?;
+    check_private_dir(&session_dir, current_uid())?;
+    Ok(session_dir)
+}
+
+pub fn remove_session_socket_dir(dir: &Path) {
+    if let Err(e) = fs::remove_dir_all(dir) {
+        eprintln!("Couldn't remove {}: {}", dir.display(), e);
+    }
+}
+
+pub fn progress_socket_path(dir: &Path) -> PathBuf {
+    dir.join(PROGRESS_SOCKET_NAME)
+}
+
+/// Binds the progress socket inside a session directory created by `create_session_socket_dir`.
+pub fn bind_progress_socket(dir: &Path) -> io::Result<UnixListener> {
+    check_private_dir(dir, current_uid())?;
+    let socket_path = progress_socket_path(dir);
+    // Only ever replace a stale socket, never some other file
+    if let Ok(metadata) = fs::symlink_metadata(&socket_path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", socket_path.display()),
+            ));
+        }
+        fs::remove_file(&socket_path)?;
+    }
+    UnixListener::bind(&socket_path)
+}
+
+/// Used by the privileged helpers to make sure the directory they were handed really
+/// belongs to the user who started them, returns the progress socket path inside it.
+pub fn validate_session_socket_dir(dir: &Path, expected_uid: u32) -> Result<PathBuf, String> {
+    check_private_dir(dir, expected_uid).map_err(|e| e.to_string())?;
+    let socket_path = progress_socket_path(dir);
+    let metadata = fs::symlink_metadata(&socket_path)
+        .map_err(|e| format!("{}: {}", socket_path.display(), e))?;
+    if !metadata.file_type().is_socket() || metadata.uid() != expected_uid {
+        return Err(format!(
+            "{} is not a socket owned by uid {}",
+            socket_path.display(),
+            expected_uid
+        ));
+    }
+    Ok(socket_path)
+}
+
+/// Which peers are allowed to talk to a progress socket.
+#[derive(Clone, Debug)]
+pub struct PeerCredPolicy {
+    allowed_uids: Vec<u32>,
+    ancestor_pid: Option<u32>,
+}
+
+impl PeerCredPolicy {
+    pub fn new(allowed_uids: Vec<u32>) -> Self {
+        Self {
+            allowed_uids,
+            ancestor_pid: None,
+        }
+    }
+
+    /// Only accept processes that are `pid` itself or one of its descendants.
+    pub fn with_ancestor_pid(mut self, pid: u32) -> Self {
+        self.ancestor_pid = Some(pid);
+        self
+    }
+
+    pub fn allows(&self, uid: u32, pid: Option<i32>) -> bool {
+        if !self.allowed_uids.contains(&uid) {
+            return false;
+        }
+        match self.ancestor_pid {
+            Some(ancestor) => match pid {
+                Some(pid) if pid > 0 => is_same_or_descendant_of(pid as u32, ancestor),
+                _ => false,
+            },
+            None => true,
+        }
+    }
+}
+
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name is wrapped in parentheses and may contain spaces
+    let after_comm = &stat[stat.rfind(')')? + 1..];
+    after_comm.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn is_same_or_descendant_of(pid: u32, ancestor: u32) -> bool {
+    let mut current = pid;
+    // Bounded walk, pid 1 has no parent
+    for _ in 0..32 {
+        if current == ancestor {
+            return true;
+        }
+        match parent_pid(current) {
+            Some(t) if t != 0 && t != current => current = t,
+            _ => return false,
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_cred_policy_checks_the_uid() {
+        let policy = PeerCredPolicy::new(vec![current_uid()]);
+        assert!(policy.allows(current_uid(), None));
+        assert!(!policy.allows(current_uid() + 1, None));
+    }
+
+    #[test]
+    fn peer_cred_policy_only_trusts_root_when_asked_to() {
+        let policy = PeerCredPolicy::new(vec![0]);
+        assert!(policy.allows(0, None));
+        assert!(!policy.allows(1000, None));
+        assert!(!PeerCredPolicy::new(vec![1000]).allows(0, None));
+    }
+
+    #[test]
+    fn peer_cred_policy_checks_the_ancestor() {
+        let own_pid = std::process::id();
+        let policy = PeerCredPolicy::new(vec![current_uid()]).with_ancestor_pid(own_pid);
+        assert!(policy.allows(current_uid(), Some(own_pid as i32)));
+        // Without a pid, or with one from outside the tree, the peer is refused
+        assert!(!policy.allows(current_uid(), None));
+        assert!(!policy.allows(current_uid(), Some(0)));
+        let parent = parent_pid(own_pid).unwrap();
+        assert!(!policy.allows(current_uid(), Some(parent as i32)));
+        // The right process tree does not help another uid
+        assert!(!policy.allows(current_uid() + 1, Some(own_pid as i32)));
+    }
+}
@@ -0,0 +1,251 @@
+use crate::progress_protocol::PlannedChange;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Every snapshot taken before an upgrade, one JSON object per line, oldest first.
+pub const SNAPSHOT_RECORDS_FILE: &str = "/var/lib/pikman-update-manager/snapshots.jsonl";
+/// Where the btrfs backend keeps its read-only snapshots of `/`.
+pub const BTRFS_SNAPSHOT_DIR: &str = "/.snapshots";
+
+/// Which tool takes the snapshot, picked in the settings.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotBackendKind {
+    Btrfs,
+    Snapper,
+    Timeshift,
+}
+
+impl SnapshotBackendKind {
+    pub const ALL: [SnapshotBackendKind; 3] = [
+        SnapshotBackendKind::Btrfs,
+        SnapshotBackendKind::Snapper,
+        SnapshotBackendKind::Timeshift,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnapshotBackendKind::Btrfs => "btrfs",
+            SnapshotBackendKind::Snapper => "snapper",
+            SnapshotBackendKind::Timeshift => "timeshift",
+        }
+    }
+
+    /// `None` for `none` or anything unknown.
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.as_str() == value)
+    }
+
+    pub fn backend(&self) -> Box<dyn SnapshotBackend> {
+        match self {
+            SnapshotBackendKind::Btrfs => Box::new(BtrfsSnapshotBackend {
+                snapshot_dir: PathBuf::from(BTRFS_SNAPSHOT_DIR),
+            }),
+            SnapshotBackendKind::Snapper => Box::new(CommandSnapshotBackend::snapper()),
+            SnapshotBackendKind::Timeshift => Box::new(CommandSnapshotBackend::timeshift()),
+        }
+    }
+}
+
+/// Takes and restores whole-system snapshots, runs as root.
+pub trait SnapshotBackend {
+    /// Returns the id the snapshot can be restored with.
+    fn create(&self, description: &str) -> Result<String, String>;
+
+    /// Makes the next boot use the snapshot.
+    fn restore(&self, id: &str) -> Result<(), String>;
+}
+
+/// Read-only btrfs snapshots of the root subvolume.
+pub struct BtrfsSnapshotBackend {
+    pub snapshot_dir: PathBuf,
+}
+
+impl SnapshotBackend for BtrfsSnapshotBackend {
+    fn create(&self, _description: &str) -> Result<String, String> {
+        fs::create_dir_all(&self.snapshot_dir).map_err(|e| e.to_string())?;
+        let id = format!(
+            "pikman-{}",
+            chrono::offset::Local::now().format("%Y-%m-%d_%H-%M-%S")
+        );
+        run_command(&[
+            "btrfs",
+            "subvolume",
+            "snapshot",
+            "-r",
+            "/",
+            &self.snapshot_dir.join(&id).to_string_lossy(),
+        ])?;
+        Ok(id)
+    }
+
+    // Only takes effect when `/` is mounted without an explicit `subvol=`
+    fn restore(&self, id: &str) -> Result<(), String> {
+        // A snapshot can be restored more than once, every restore gets its own subvolume
+        let restored = self.snapshot_dir.join(format!(
+            "{}-restored-{}",
+            id,
+            chrono::offset::Local::now().format("%Y-%m-%d_%H-%M-%S")
+        ));
+        run_command(&[
+            "btrfs",
+            "subvolume",
+            "snapshot",
+            &self.snapshot_dir.join(id).to_string_lossy(),
+            &restored.to_string_lossy(),
+        ])?;
+        run_command(&[
+            "btrfs",
+            "subvolume",
+            "set-default",
+            &restored.to_string_lossy(),
+        ])
+        .map(|_| ())
+    }
+}
+
+/// A snapshot tool driven through its command line, `{DESCRIPTION}` and `{ID}` are
+/// filled in.
+pub struct CommandSnapshotBackend {
+    pub create_command: Vec<String>,
+    pub restore_command: Vec<String>,
+    /// Finds the new snapshot id in what the create command prints.
+    pub id_pattern: Regex,
+}
+
+impl CommandSnapshotBackend {
+    pub fn snapper() -> Self {
+        Self {
+            create_command: [
+                "snapper",
+                "--no-dbus",
+                "create",
+                "--type",
+                "single",
+                "--cleanup-algorithm",
+                "number",
+                "--print-number",
+                "--description",
+                "{DESCRIPTION}",
+            ]
+            .map(String::from)
+            .to_vec(),
+            restore_command: ["snapper", "--no-dbus", "rollback", "{ID}"]
+                .map(String::from)
+                .to_vec(),
+            id_pattern: Regex::new(r"(?m)^\s*(\d+)\s*$").unwrap(),
+        }
+    }
+
+    pub fn timeshift() -> Self {
+        Self {
+            create_command: [
+                "timeshift",
+                "--create",
+                "--scripted",
+                "--comments",
+                "{DESCRIPTION}",
+            ]
+            .map(String::from)
+            .to_vec(),
+            restore_command: [
+                "timeshift",
+                "--restore",
+                "--snapshot",
+                "{ID}",
+                "--scripted",
+                "--yes",
+            ]
+            .map(String::from)
+            .to_vec(),
+            id_pattern: Regex::new(r"Tagged snapshot '([^']+)'").unwrap(),
+        }
+    }
+
+    fn fill_in(command: &[String], key: &str, value: &str) -> Vec<String> {
+        command.iter().map(|t| t.replace(key, value)).collect()
+    }
+}
+
+impl SnapshotBackend for CommandSnapshotBackend {
+    fn create(&self, description: &str) -> Result<String, String> {
+        let command = Self::fill_in(&self.create_command, "{DESCRIPTION}", description);
+        let stdout = run_command(&command.iter().map(|t| t.as_str()).collect::<Vec<&str>>())?;
+        self.id_pattern
+            .captures(&stdout)
+            .and_then(|t| t.get(1))
+            .map(|t| t.as_str().to_owned())
+            .ok_or_else(|| format!("{} did not report a snapshot id:\n{}", command[0], stdout))
+    }
+
+    fn restore(&self, id: &str) -> Result<(), String> {
+        let command = Self::fill_in(&self.restore_command, "{ID}", id);
+        run_command(&command.iter().map(|t| t.as_str()).collect::<Vec<&str>>()).map(|_| ())
+    }
+}
+
+fn run_command(command: &[&str]) -> Result<String, String> {
+    let output = Command::new(command[0])
+        .args(&command[1..])
+        .output()
+        .map_err(|e| format!("{}: {}", command[0], e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    if output.status.success() {
+        Ok(stdout)
+    } else {
+        Err(format!(
+            "{} failed: {}{}",
+            command[0],
+            stdout,
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+}
+
+/// A snapshot and the upgrade it was taken for.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotRecord {
+    pub id: String,
+    pub backend: SnapshotBackendKind,
+    pub created_at: String,
+    #[serde(default)]
+    pub changes: Vec<PlannedChange>,
+}
+
+impl SnapshotRecord {
+    pub fn new(id: String, backend: SnapshotBackendKind, changes: Vec<PlannedChange>) -> Self {
+        Self {
+            id,
+            backend,
+            created_at: chrono::offset::Local::now().to_rfc3339(),
+            changes,
+        }
+    }
+
+    pub fn append(&self) -> io::Result<()> {
+        let path = Path::new(SNAPSHOT_RECORDS_FILE);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut records_file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(records_file, "{}", serde_json::to_string(self).unwrap())?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o644))
+    }
+}
+
+/// Newest first, lines that do not parse are skipped.
+pub fn load_snapshot_records() -> Vec<SnapshotRecord> {
+    let mut records: Vec<SnapshotRecord> = fs::read_to_string(SNAPSHOT_RECORDS_FILE)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|t| serde_json::from_str(t).ok())
+        .collect();
+    records.reverse();
+    records
+}
@@ -0,0 +1,122 @@
+use crate::kernels::installed_kernels;
+use crate::update_discovery::FlatpakInstallationKind;
+use libflatpak::prelude::*;
+use rust_apt::cache::{Cache, PackageSort};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Something the cleanup can remove and the space it takes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CleanupItem {
+    pub name: String,
+    pub size: u64,
+}
+
+pub fn total_size(items: &[CleanupItem]) -> u64 {
+    items.iter().map(|t| t.size).sum()
+}
+
+fn archive_paths(archives_dir: &Path) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    // Half finished downloads end up in partial/
+    for dir in [archives_dir.to_path_buf(), archives_dir.join("partial")] {
+        let entries = match fs::read_dir(&dir) {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_file() && path.extension().is_some_and(|t| t == "deb") {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+    paths
+}
+
+/// The downloaded `.deb` files in `archives_dir`, what `apt-get clean` would delete.
+pub fn cached_archives(archives_dir: &Path) -> Vec<CleanupItem> {
+    archive_paths(archives_dir)
+        .into_iter()
+        .map(|path| CleanupItem {
+            size: fs::metadata(&path).map(|t| t.len()).unwrap_or(0),
+            name: path
+                .file_name()
+                .map(|t| t.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Deletes the cached archives, returns how many bytes that freed.
+pub fn clean_cached_archives(archives_dir: &Path) -> io::Result<u64> {
+    let mut freed = 0;
+    for path in archive_paths(archives_dir) {
+        let size = fs::metadata(&path).map(|t| t.len()).unwrap_or(0);
+        fs::remove_file(&path)?;
+        freed += size;
+    }
+    Ok(freed)
+}
+
+/// Auto-installed packages nothing needs anymore. Kernels are left to the keep-N policy, so
+/// the running one is never among them.
+pub fn autoremovable_packages(cache: &Cache) -> Vec<CleanupItem> {
+    let kernel_packages: Vec<String> = installed_kernels(cache)
+        .into_iter()
+        .flat_map(|t| t.packages)
+        .collect();
+    cache
+        .packages(&PackageSort::default().installed())
+        .filter(|pkg| pkg.is_auto_removable())
+        .map(|pkg| CleanupItem {
+            name: format!("{}:{}", pkg.name(), pkg.arch()),
+            size: pkg.installed().map(|t| t.installed_size()).unwrap_or(0),
+        })
+        .filter(|item| !kernel_packages.contains(&item.name))
+        .collect()
+}
+
+/// A runtime or extension no installed app uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnusedFlatpakRef {
+    pub installation: FlatpakInstallationKind,
+    pub ref_format: String,
+    pub installed_size: u64,
+}
+
+/// Unused refs of both installations, like `flatpak uninstall --unused` finds them.
+pub fn unused_flatpak_refs() -> Vec<UnusedFlatpakRef> {
+    let cancellable_no = libflatpak::gio::Cancellable::NONE;
+    let mut unused_refs = Vec::new();
+    for installation_kind in [
+        FlatpakInstallationKind::System,
+        FlatpakInstallationKind::User,
+    ] {
+        let installation = match installation_kind {
+            FlatpakInstallationKind::System => libflatpak::Installation::new_system(cancellable_no),
+            FlatpakInstallationKind::User => libflatpak::Installation::new_user(cancellable_no),
+        };
+        let flatpak_refs = match installation
+            .and_then(|installation| installation.list_unused_refs(None, cancellable_no))
+        {
+            Ok(t) => t,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        for flatpak_ref in flatpak_refs {
+            if let Some(ref_format) = flatpak_ref.format_ref() {
+                unused_refs.push(UnusedFlatpakRef {
+                    installation: installation_kind,
+                    ref_format: ref_format.to_string(),
+                    installed_size: flatpak_ref.installed_size(),
+                });
+            }
+        }
+    }
+    unused_refs
+}
@@ -0,0 +1,156 @@
+use rust_apt::cache::PackageSort;
+use rust_apt::new_cache;
+use std::fs;
+use std::path::Path;
+
+/// dpkg's view of every package it knows about.
+pub const DPKG_STATUS_FILE: &str = "/var/lib/dpkg/status";
+/// dpkg journals status changes here and only clears them once a run completes.
+pub const DPKG_UPDATES_DIR: &str = "/var/lib/dpkg/updates";
+
+/// Package states `dpkg --audit` complains about, a run stopped halfway leaves these behind.
+const UNFINISHED_STATES: [&str; 5] = [
+    "half-installed",
+    "unpacked",
+    "half-configured",
+    "triggers-awaited",
+    "triggers-pending",
+];
+
+/// What an interrupted upgrade left behind, checked before listing updates.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SystemHealth {
+    /// dpkg was stopped mid-run, APT refuses to do anything until it is finished.
+    pub dpkg_interrupted: bool,
+    /// `name:arch` of packages that are not fully installed or configured.
+    pub unfinished_packages: Vec<String>,
+    /// `name:arch` of installed packages whose dependencies are not satisfied.
+    pub broken_packages: Vec<String>,
+    /// The APT cache could not even be opened.
+    pub cache_error: Option<String>,
+}
+
+impl SystemHealth {
+    pub fn check() -> Self {
+        let (broken_packages, cache_error) = match broken_packages() {
+            Ok(t) => (t, None),
+            Err(e) => (Vec::new(), Some(e)),
+        };
+        Self {
+            dpkg_interrupted: dpkg_journal_pending(Path::new(DPKG_UPDATES_DIR)),
+            unfinished_packages: unfinished_packages(
+                &fs::read_to_string(DPKG_STATUS_FILE).unwrap_or_default(),
+            ),
+            broken_packages,
+            cache_error,
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        !self.dpkg_interrupted
+            && self.unfinished_packages.is_empty()
+            && self.broken_packages.is_empty()
+            && self.cache_error.is_none()
+    }
+}
+
+/// Whether dpkg left journal entries, the same test it uses for "dpkg was interrupted".
+pub fn dpkg_journal_pending(updates_dir: &Path) -> bool {
+    match fs::read_dir(updates_dir) {
+        Ok(entries) => entries.flatten().any(|entry| {
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            !file_name.is_empty() && file_name.chars().all(|t| t.is_ascii_digit())
+        }),
+        Err(_) => false,
+    }
+}
+
+/// Packages in `dpkg_status` (the contents of dpkg's status file) that a run left unfinished
+/// or that need to be reinstalled.
+pub fn unfinished_packages(dpkg_status: &str) -> Vec<String> {
+    let mut packages = Vec::new();
+    for stanza in dpkg_status.split("\n\n") {
+        let field = |name: &str| -> Option<&str> {
+            stanza
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .map(|t| t.trim())
+        };
+        let (package, status) = match (field("Package"), field("Status")) {
+            (Some(package), Some(status)) => (package, status),
+            _ => continue,
+        };
+        // Want, error flag and state, e.g. "install reinstreq half-installed"
+        let mut status = status.split_whitespace();
+        let error_flag = status.nth(1).unwrap_or_default();
+        let state = status.next().unwrap_or_default();
+        if error_flag == "reinstreq" || UNFINISHED_STATES.contains(&state) {
+            packages.push(match field("Architecture") {
+                Some(arch) => format!("{}:{}", package, arch),
+                None => package.to_owned(),
+            });
+        }
+    }
+    packages
+}
+
+fn broken_packages() -> Result<Vec<String>, String> {
+    let cache = new_cache!().map_err(|e| e.to_string())?;
+    Ok(cache
+        .packages(&PackageSort::default().installed())
+        .filter(|pkg| pkg.is_now_broken())
+        .map(|pkg| format!("{}:{}", pkg.name(), pkg.arch()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unfinished_packages_are_found() {
+        let dpkg_status = "\
+Package: bash
+Status: install ok installed
+Architecture: amd64
+
+Package: grub-pc
+Status: install reinstreq half-installed
+Architecture: amd64
+
+Package: mesa-vulkan-drivers
+Status: install ok half-configured
+Multi-Arch: same
+Architecture: i386
+
+Package: base-files
+Status: install reinstreq installed
+";
+        assert_eq!(
+            unfinished_packages(dpkg_status),
+            vec![
+                "grub-pc:amd64",
+                "mesa-vulkan-drivers:i386",
+                // No Architecture field, the name alone still finds it
+                "base-files",
+            ]
+        );
+        assert!(unfinished_packages("").is_empty());
+    }
+
+    #[test]
+    fn only_numbered_journal_files_count() {
+        let dir = std::env::temp_dir().join(format!("dpkg-updates-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(!dpkg_journal_pending(&dir));
+        // dpkg keeps its temporary file next to the journal
+        std::fs::write(dir.join("tmp.i"), "").unwrap();
+        assert!(!dpkg_journal_pending(&dir));
+        std::fs::write(dir.join("0003"), "").unwrap();
+        assert!(dpkg_journal_pending(&dir));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(!dpkg_journal_pending(&dir));
+    }
+}
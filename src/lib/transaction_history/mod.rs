@@ -0,0 +1,275 @@
+use crate::apt_helper_error::AptHelperErrorKind;
+use crate::pika_unixsocket_tools::ProgressObserver;
+use crate::progress_protocol::{ChangeAction, PlannedChange, ProgressMessage};
+use crate::update_holds::ExclusionsFile;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// APT transactions, written by `apt_full_upgrade` as root, one JSON object per line.
+pub const SYSTEM_HISTORY_FILE: &str = "/var/lib/pikman-update-manager/history.jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+    Apt,
+    Flatpak,
+}
+
+impl TransactionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionKind::Apt => "apt",
+            TransactionKind::Flatpak => "flatpak",
+        }
+    }
+}
+
+/// A package or Flatpak ref and the versions on either side of the transaction.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryPackage {
+    pub name: String,
+    pub action: ChangeAction,
+    pub version_before: Option<String>,
+    pub version_after: Option<String>,
+}
+
+impl From<&PlannedChange> for HistoryPackage {
+    fn from(change: &PlannedChange) -> Self {
+        Self {
+            name: format!("{}:{}", change.package, change.arch),
+            action: change.action,
+            version_before: change.current_version.clone(),
+            version_after: change.new_version.clone(),
+        }
+    }
+}
+
+/// One finished transaction, as shown on the History page.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub kind: TransactionKind,
+    pub started_at: String,
+    #[serde(default)]
+    pub finished_at: String,
+    pub user: String,
+    pub packages: Vec<HistoryPackage>,
+    #[serde(default)]
+    pub exclusions: ExclusionsFile,
+    #[serde(default)]
+    pub exit_code: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<AptHelperErrorKind>,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot_id: Option<String>,
+    #[serde(default)]
+    pub offline: bool,
+    /// A repair after an interrupted run rather than an upgrade.
+    #[serde(default)]
+    pub repair: bool,
+    /// Configuration files whose new version still waits next to them as `.dpkg-dist`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unresolved_conffiles: Vec<String>,
+    #[serde(default)]
+    pub log: Vec<String>,
+}
+
+impl HistoryEntry {
+    pub fn new(kind: TransactionKind, user: String, packages: Vec<HistoryPackage>) -> Self {
+        Self {
+            kind,
+            started_at: chrono::offset::Local::now().to_rfc3339(),
+            finished_at: String::new(),
+            user,
+            packages,
+            exclusions: ExclusionsFile::default(),
+            exit_code: 0,
+            error_kind: None,
+            message: String::new(),
+            snapshot_id: None,
+            offline: false,
+            repair: false,
+            unresolved_conffiles: Vec::new(),
+            log: Vec::new(),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.exit_code == 0
+    }
+
+    /// Picks up what the helper reports, returns true once the run is over.
+    pub fn record(&mut self, message: &ProgressMessage) -> bool {
+        match message {
+            ProgressMessage::Error {
+                error_kind,
+                message,
+            } => {
+                self.error_kind = Some(*error_kind);
+                self.message = message.to_owned();
+            }
+            ProgressMessage::PackageError { package, error } if self.message.is_empty() => {
+                self.message = format!("{}: {}", package, error);
+            }
+            ProgressMessage::Finished { exit_code } => {
+                self.finish(*exit_code);
+                return true;
+            }
+            _ => {}
+        }
+        if let Some(log_line) = message.log_line() {
+            self.log.push(log_line);
+        }
+        false
+    }
+
+    pub fn finish(&mut self, exit_code: i32) {
+        self.exit_code = exit_code;
+        self.finished_at = chrono::offset::Local::now().to_rfc3339();
+    }
+
+    /// Case-insensitive search over the user, the packages, their versions and the error.
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return true;
+        }
+        let contains = |value: &str| value.to_lowercase().contains(&query);
+        contains(self.kind.as_str())
+            || contains(&self.user)
+            || contains(&self.started_at)
+            || contains(&self.message)
+            || self.packages.iter().any(|t| {
+                contains(&t.name)
+                    || t.version_before.as_deref().is_some_and(contains)
+                    || t.version_after.as_deref().is_some_and(contains)
+            })
+    }
+
+    pub fn append_to(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut history_file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(history_file, "{}", serde_json::to_string(self).unwrap())?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o644))
+    }
+}
+
+/// Logs the rest of a helper's run into `entry` and appends it to the history once the run
+/// is over. The helper keeps its own handle to fill in what only it knows, like a snapshot.
+pub struct HistoryRecorder {
+    entry: Rc<RefCell<HistoryEntry>>,
+    history_path: PathBuf,
+    saved: bool,
+}
+
+impl HistoryRecorder {
+    pub fn new(entry: Rc<RefCell<HistoryEntry>>, history_path: &Path) -> Self {
+        Self {
+            entry,
+            history_path: history_path.to_owned(),
+            saved: false,
+        }
+    }
+}
+
+impl ProgressObserver for HistoryRecorder {
+    fn observe(&mut self, message: &ProgressMessage) {
+        if self.saved {
+            return;
+        }
+        let mut entry = self.entry.borrow_mut();
+        if entry.record(message) {
+            self.saved = true;
+            if let Err(e) = entry.append_to(&self.history_path) {
+                eprintln!("Couldn't save the transaction history: {}", e);
+            }
+        }
+    }
+}
+
+/// Flatpak transactions run as the user, so they are kept in the user's data directory.
+pub fn user_history_path() -> PathBuf {
+    let data_dir = match std::env::var("XDG_DATA_HOME") {
+        Ok(t) if !t.is_empty() => PathBuf::from(t),
+        _ => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(".local/share"),
+    };
+    data_dir.join("pikman-update-manager").join("history.jsonl")
+}
+
+fn load_history_file(path: &Path) -> Vec<HistoryEntry> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|t| serde_json::from_str(t).ok())
+        .collect()
+}
+
+/// Both logs merged, newest first, lines that do not parse are skipped.
+pub fn load_history() -> Vec<HistoryEntry> {
+    let mut entries = load_history_file(Path::new(SYSTEM_HISTORY_FILE));
+    entries.extend(load_history_file(&user_history_path()));
+    entries.sort_by_key(|t| chrono::DateTime::parse_from_rfc3339(&t.started_at).ok());
+    entries.reverse();
+    entries
+}
+
+/// Writes the entries out as a pretty-printed JSON array.
+pub fn export_history(entries: &[HistoryEntry], path: &Path) -> io::Result<()> {
+    fs::write(path, serde_json::to_string_pretty(entries).unwrap())
+}
+
+/// Looks the login name up in `/etc/passwd`, falls back to the bare uid.
+pub fn user_name(uid: u32) -> String {
+    fs::read_to_string("/etc/passwd")
+        .unwrap_or_default()
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            (fields.nth(1)?.parse::<u32>().ok()? == uid).then(|| name.to_owned())
+        })
+        .unwrap_or_else(|| uid.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pika_unixsocket_tools::ProgressSocketClient;
+
+    #[test]
+    fn history_recorder_saves_the_run_once() {
+        let history_path =
+            std::env::temp_dir().join(format!("transaction-history-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&history_path);
+        let entry = Rc::new(RefCell::new(HistoryEntry::new(
+            TransactionKind::Apt,
+            "user".to_owned(),
+            Vec::new(),
+        )));
+        let mut socket_client = ProgressSocketClient::disconnected();
+        socket_client.add_observer(HistoryRecorder::new(entry.clone(), &history_path));
+
+        socket_client.send(&ProgressMessage::Status {
+            message: "Setting up mesa".to_owned(),
+        });
+        entry.borrow_mut().snapshot_id = Some("42".to_owned());
+        socket_client.send(&ProgressMessage::Finished { exit_code: 1 });
+        socket_client.send(&ProgressMessage::Finished { exit_code: 0 });
+
+        let history = load_history_file(&history_path);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].exit_code, 1);
+        assert_eq!(history[0].snapshot_id.as_deref(), Some("42"));
+        assert_eq!(history[0].log, vec!["Setting up mesa".to_owned()]);
+        fs::remove_file(history_path).unwrap();
+    }
+}
@@ -0,0 +1,1414 @@
+use crate::apt_helper_error::{AptHelperError, AptHelperErrorKind};
+use crate::change_explanation::{
+    explain_changes, ChangeExplanation, PackageRelation, RelationKind,
+};
+use crate::kernels::old_kernels;
+use crate::progress_protocol::ChangeAction;
+use crate::update_holds::{Exclusion, ForcedUpgrade, UpdateHolds};
+use libflatpak::prelude::*;
+use rust_apt::cache::{PackageSort, Upgrade};
+use rust_apt::new_cache;
+use rust_apt::records::RecordField;
+use rust_apt::{DepType, PkgSelectedState};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::path::Path;
+
+/// A single package change as seen by the APT resolver.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AptChange {
+    pub name: String,
+    pub arch: String,
+    /// The source package the binary package is built from.
+    pub source_name: String,
+    pub action: ChangeAction,
+    pub installed_version: Option<String>,
+    pub candidate_version: Option<String>,
+    pub description: Option<String>,
+    pub source_uri: String,
+    pub origin: Option<String>,
+    pub archive: Option<String>,
+    pub label: Option<String>,
+    pub update_class: UpdateClass,
+    pub maintainer: Option<String>,
+    /// Share of systems a phased update is rolled out to, `None` if it is not phased.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phased_update_percentage: Option<u8>,
+    /// Size of the candidate archive, 0 for removals.
+    pub download_size: u64,
+    /// The candidate archive is already in the APT cache, nothing is left to download.
+    pub archive_cached: bool,
+    /// Disk usage of the installed version, 0 if not installed.
+    pub installed_size_before: u64,
+    /// Disk usage after the change, 0 for removals.
+    pub installed_size_after: u64,
+}
+
+/// Summary of what a full upgrade is going to do, shown before anything is run.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct AptChangesInfo {
+    pub package_count_upgrade: u64,
+    pub package_count_install: u64,
+    pub package_count_downgrade: u64,
+    pub package_count_remove: u64,
+    pub package_count_security: u64,
+    /// What is left to download, archives already in the APT cache are not counted.
+    pub total_download_size: u64,
+    /// Archives a background download already put in the APT cache.
+    pub cached_download_size: u64,
+    pub total_installed_size: i64,
+    pub changes: Vec<(ChangeAction, String)>,
+    /// Removals the full upgrade asked for by itself, not the ones the resolver added.
+    pub to_be_removed_packages: Vec<String>,
+    /// Upgrades the resolver had to leave out because of an exclusion or hold.
+    pub held_back_packages: Vec<String>,
+    /// Why new installs, removals and held-back packages happen.
+    pub explanations: Vec<ChangeExplanation>,
+}
+
+impl AptChangesInfo {
+    /// Everything was downloaded beforehand, installing can start right away.
+    pub fn is_ready_to_install(&self) -> bool {
+        self.total_download_size == 0 && self.cached_download_size > 0
+    }
+}
+
+/// Which kind of repository an update comes from.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateClass {
+    Security,
+    #[default]
+    Regular,
+    ThirdParty,
+}
+
+impl UpdateClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpdateClass::Security => "security",
+            UpdateClass::Regular => "regular",
+            UpdateClass::ThirdParty => "third_party",
+        }
+    }
+}
+
+/// The `Origin`, `Archive` and `Label` of one Release file a candidate is available from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PackageOrigin {
+    pub origin: Option<String>,
+    pub archive: Option<String>,
+    pub label: Option<String>,
+}
+
+impl PackageOrigin {
+    pub fn is_security(&self) -> bool {
+        let contains_security = |value: &Option<String>| {
+            value
+                .as_deref()
+                .is_some_and(|t| t.to_lowercase().contains("security"))
+        };
+        contains_security(&self.archive) || contains_security(&self.label)
+    }
+}
+
+/// Origins that belong to the distribution itself, everything else is a third-party repo.
+pub fn distro_origins() -> Vec<String> {
+    let os_release = std::fs::read_to_string("/etc/os-release").unwrap_or_default();
+    distro_origins_from_os_release(&os_release)
+}
+
+pub fn distro_origins_from_os_release(os_release: &str) -> Vec<String> {
+    let mut origins: Vec<String> = vec!["Debian".to_owned(), "Ubuntu".to_owned()];
+    for line in os_release.lines() {
+        if let Some((key, value)) = line.split_once('=') {
+            if matches!(key, "ID" | "NAME") {
+                origins.push(value.trim_matches('"').to_owned());
+            }
+        }
+    }
+    origins
+}
+
+/// A candidate available from any security pocket is a security update, even if it is
+/// also in the regular one.
+pub fn classify_update(
+    package_origins: &[PackageOrigin],
+    distro_origins: &[String],
+) -> UpdateClass {
+    if package_origins.iter().any(|t| t.is_security()) {
+        return UpdateClass::Security;
+    }
+    let mut origins = package_origins
+        .iter()
+        .filter_map(|t| t.origin.as_deref())
+        .peekable();
+    if origins.peek().is_none() {
+        return UpdateClass::Regular;
+    }
+    if origins.any(|origin| {
+        distro_origins
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(origin))
+    }) {
+        UpdateClass::Regular
+    } else {
+        UpdateClass::ThirdParty
+    }
+}
+
+/// Leaves out every upgrade that is not a security update, for the "security updates only"
+/// mode. New packages stay in, a security update may need a dependency from elsewhere.
+pub fn security_only_exclusions(changes: &[AptChange]) -> Vec<Exclusion> {
+    changes
+        .iter()
+        .filter(|change| {
+            matches!(
+                change.action,
+                ChangeAction::Upgrade | ChangeAction::Downgrade
+            ) && change.update_class != UpdateClass::Security
+        })
+        .map(|change| Exclusion::exact(&change.name, Some(&change.arch)))
+        .collect()
+}
+
+/// Why the resolver leaves an upgradable package out of the full upgrade.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeptBackReason {
+    /// Put on hold with `apt-mark hold`.
+    DpkgHold,
+    /// The candidate is still being phased in.
+    Phased,
+    /// Upgrading it removes other packages.
+    NeedsRemovals,
+    /// Its dependencies can not be satisfied.
+    BrokenDependencies,
+    Unknown,
+}
+
+/// An upgradable package the full upgrade leaves alone.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct KeptBackPackage {
+    /// The upgrade as it would look when asked for explicitly.
+    pub change: AptChange,
+    pub reason: KeptBackReason,
+    /// The phasing percentage, the packages that would be removed or the resolver error.
+    pub detail: Option<String>,
+    /// Whether the upgrade resolves when asked for explicitly.
+    pub can_force: bool,
+}
+
+impl KeptBackPackage {
+    /// `trial` holds what upgrading only this package ran into, the packages it removes
+    /// or the resolver error.
+    pub fn new(
+        change: AptChange,
+        dpkg_hold: bool,
+        phased_update_percentage: Option<u8>,
+        trial: Result<Vec<String>, String>,
+    ) -> Self {
+        let can_force = !dpkg_hold && trial.is_ok();
+        let (reason, detail) = match (dpkg_hold, phased_update_percentage, trial) {
+            (true, _, _) => (KeptBackReason::DpkgHold, None),
+            (false, Some(percentage), _) if percentage < 100 => {
+                (KeptBackReason::Phased, Some(format!("{}%", percentage)))
+            }
+            (false, _, Err(e)) => (KeptBackReason::BrokenDependencies, Some(e)),
+            (false, _, Ok(removals)) if !removals.is_empty() => {
+                (KeptBackReason::NeedsRemovals, Some(removals.join(", ")))
+            }
+            _ => (KeptBackReason::Unknown, None),
+        };
+        Self {
+            change,
+            reason,
+            detail,
+            can_force,
+        }
+    }
+}
+
+/// Where the APT package state comes from, so the calculations can run against a fake cache.
+pub trait AptBackend {
+    /// Every change a plain full upgrade would make.
+    fn full_upgrade_changes(&self) -> Result<Vec<AptChange>, AptHelperError>;
+
+    /// Keeps and protects `kept` on a fresh cache, marks `selected` and, with
+    /// `kernels_to_keep`, the kernels the keep-N policy lets go of, then lets the resolver
+    /// fill in the rest. Returns the resulting changes and the kernel packages removed.
+    fn resolve_selected_changes(
+        &self,
+        selected: &[AptChange],
+        kept: &[AptChange],
+        kernels_to_keep: Option<u32>,
+    ) -> Result<(Vec<AptChange>, Vec<String>), AptHelperError>;
+
+    /// Dependencies and conflicts of the versions `changes` are going to install, or of the
+    /// installed version for removals.
+    fn relations(&self, changes: &[AptChange]) -> Result<Vec<PackageRelation>, AptHelperError>;
+
+    /// Upgradable packages a plain full upgrade does not touch.
+    fn kept_back_changes(&self) -> Result<Vec<KeptBackPackage>, AptHelperError>;
+
+    /// Debian version ordering of `a` against `b`.
+    fn compare_versions(&self, a: &str, b: &str) -> Ordering;
+}
+
+pub fn is_excluded(package_name: &str, arch: &str, excluded_updates_vec: &[Exclusion]) -> bool {
+    excluded_updates_vec
+        .iter()
+        .any(|e| e.matches(package_name, arch))
+}
+
+/// Packages the update page lists, removals are only shown in the confirmation dialog.
+pub fn upgradable_packages(
+    backend: &impl AptBackend,
+    update_holds: &UpdateHolds,
+) -> Result<Vec<AptChange>, AptHelperError> {
+    Ok(backend
+        .full_upgrade_changes()?
+        .into_iter()
+        .filter(|change| change.action != ChangeAction::Remove)
+        .filter(|change| !is_held(backend, change, update_holds))
+        .collect())
+}
+
+/// Packages the held-back section lists, the user's own holds are left out.
+pub fn kept_back_packages(
+    backend: &impl AptBackend,
+    update_holds: &UpdateHolds,
+) -> Result<Vec<KeptBackPackage>, AptHelperError> {
+    Ok(backend
+        .kept_back_changes()?
+        .into_iter()
+        .filter(|kept_back| !is_held(backend, &kept_back.change, update_holds))
+        .collect())
+}
+
+pub fn is_held(backend: &impl AptBackend, change: &AptChange, update_holds: &UpdateHolds) -> bool {
+    update_holds.holds_back(&change.name, change.candidate_version.as_deref(), |a, b| {
+        backend.compare_versions(a, b)
+    })
+}
+
+/// Simulates the full upgrade the root helper is going to run, without touching the system.
+/// Phased updates the resolver defers are kept back, forcing one selects it.
+pub fn simulate_full_upgrade(
+    backend: &impl AptBackend,
+    excluded_updates_vec: &[Exclusion],
+    forced_upgrades_vec: &[ForcedUpgrade],
+    update_holds: &UpdateHolds,
+    kernels_to_keep: Option<u32>,
+) -> Result<AptChangesInfo, AptHelperError> {
+    let mut apt_changes_info = AptChangesInfo::default();
+
+    let is_forced = |change: &AptChange| {
+        forced_upgrades_vec
+            .iter()
+            .any(|t| t.matches(&change.name, &change.arch))
+    };
+    let (kept, mut selected): (Vec<AptChange>, Vec<AptChange>) = backend
+        .full_upgrade_changes()?
+        .into_iter()
+        .partition(|change| {
+            is_excluded(&change.name, &change.arch, excluded_updates_vec)
+                || is_held(backend, change, update_holds)
+        });
+    if !forced_upgrades_vec.is_empty() {
+        selected.extend(
+            backend
+                .kept_back_changes()?
+                .into_iter()
+                .filter(|kept_back| kept_back.can_force && is_forced(&kept_back.change))
+                .map(|kept_back| kept_back.change),
+        );
+    }
+    apt_changes_info.to_be_removed_packages = selected
+        .iter()
+        .filter(|change| change.action == ChangeAction::Remove)
+        .map(|change| change.name.to_owned())
+        .collect();
+
+    let (resolved, old_kernel_packages) =
+        backend.resolve_selected_changes(&selected, &kept, kernels_to_keep)?;
+    // The helper removes these along with the upgrade, so they are confirmed like the rest
+    apt_changes_info.to_be_removed_packages.extend(
+        old_kernel_packages
+            .iter()
+            .map(|t| t.split(':').next().unwrap_or_default().to_owned()),
+    );
+    let held_back: Vec<AptChange> = selected
+        .into_iter()
+        .filter(|change| {
+            !resolved
+                .iter()
+                .any(|t| t.name == change.name && t.arch == change.arch)
+        })
+        .collect();
+    apt_changes_info.held_back_packages = held_back
+        .iter()
+        .map(|change| change.name.to_owned())
+        .collect();
+
+    let relations = backend.relations(
+        &resolved
+            .iter()
+            .chain(kept.iter())
+            .chain(held_back.iter())
+            .cloned()
+            .collect::<Vec<AptChange>>(),
+    )?;
+    apt_changes_info.explanations = explain_changes(&resolved, &kept, &held_back, &relations);
+
+    for change in resolved {
+        apt_changes_info.total_installed_size -= change.installed_size_before as i64;
+        apt_changes_info.total_installed_size += change.installed_size_after as i64;
+        if change.archive_cached {
+            apt_changes_info.cached_download_size += change.download_size;
+        } else {
+            apt_changes_info.total_download_size += change.download_size;
+        }
+        match change.action {
+            ChangeAction::Upgrade => apt_changes_info.package_count_upgrade += 1,
+            ChangeAction::Install => apt_changes_info.package_count_install += 1,
+            ChangeAction::Downgrade => apt_changes_info.package_count_downgrade += 1,
+            ChangeAction::Remove => apt_changes_info.package_count_remove += 1,
+        }
+        if change.update_class == UpdateClass::Security && change.action != ChangeAction::Remove {
+            apt_changes_info.package_count_security += 1;
+        }
+        apt_changes_info.changes.push((change.action, change.name));
+    }
+
+    Ok(apt_changes_info)
+}
+
+/// Where APT keeps downloaded archives.
+pub const APT_ARCHIVES_DIR: &str = "/var/cache/apt/archives";
+
+/// The name APT stores a downloaded archive under, with `_`, `:` and unprintable
+/// characters escaped the way `QuoteString` does it.
+pub fn archive_file_name(package: &str, version: &str, arch: &str) -> String {
+    let quote = |value: &str, bad: &str| -> String {
+        let mut quoted = String::new();
+        for byte in value.bytes() {
+            if bad.as_bytes().contains(&byte) || byte == b'%' || byte <= 0x20 || byte >= 0x7f {
+                quoted.push_str(&format!("%{:02x}", byte));
+            } else {
+                quoted.push(byte as char);
+            }
+        }
+        quoted
+    };
+    format!(
+        "{}_{}_{}.deb",
+        quote(package, "_:"),
+        quote(version, "_:"),
+        quote(arch, "_:.")
+    )
+}
+
+/// Whether `archives_dir` holds a complete copy of the archive, a partial download is
+/// still in `partial/`.
+pub fn is_archive_cached(archives_dir: &Path, file_name: &str, size: u64) -> bool {
+    size > 0
+        && std::fs::metadata(archives_dir.join(file_name))
+            .is_ok_and(|t| t.is_file() && t.len() == size)
+}
+
+/// The real system cache.
+pub struct RustAptBackend;
+
+macro_rules! collect_apt_changes {
+    ($cache:expr) => {{
+        let distro_origins = distro_origins();
+        $cache
+            .get_changes(false)
+            .filter_map(|pkg| {
+                let action = if pkg.marked_delete() {
+                    ChangeAction::Remove
+                } else if pkg.marked_downgrade() {
+                    ChangeAction::Downgrade
+                } else if pkg.marked_upgrade() && pkg.is_installed() {
+                    ChangeAction::Upgrade
+                } else if pkg.marked_install() || pkg.marked_upgrade() {
+                    ChangeAction::Install
+                } else {
+                    return None;
+                };
+                let installed = pkg.installed();
+                let candidate = match action {
+                    ChangeAction::Remove => None,
+                    _ => pkg.candidate(),
+                };
+                let package_origins: Vec<PackageOrigin> = candidate
+                    .as_ref()
+                    .map(|t| {
+                        t.package_files()
+                            .map(|package_file| PackageOrigin {
+                                origin: package_file.origin().map(|t| t.to_string()),
+                                archive: package_file.archive().map(|t| t.to_string()),
+                                label: package_file.label().map(|t| t.to_string()),
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let package_origin = package_origins.first().cloned().unwrap_or_default();
+                Some(AptChange {
+                    name: pkg.name().to_string(),
+                    arch: pkg.arch().to_string(),
+                    source_name: candidate
+                        .as_ref()
+                        .or(installed.as_ref())
+                        .map(|t| t.source_name().to_string())
+                        .unwrap_or_else(|| pkg.name().to_string()),
+                    action,
+                    installed_version: installed.as_ref().map(|t| t.version().to_string()),
+                    candidate_version: candidate.as_ref().map(|t| t.version().to_string()),
+                    description: candidate.as_ref().and_then(|t| t.description()),
+                    source_uri: candidate
+                        .as_ref()
+                        .map(|t| t.uris().collect::<Vec<String>>().join("\n"))
+                        .unwrap_or_default(),
+                    update_class: classify_update(&package_origins, &distro_origins),
+                    origin: package_origin.origin,
+                    archive: package_origin.archive,
+                    label: package_origin.label,
+                    maintainer: candidate
+                        .as_ref()
+                        .and_then(|t| t.get_record(RecordField::Maintainer)),
+                    phased_update_percentage: candidate
+                        .as_ref()
+                        .and_then(|t| t.get_record("Phased-Update-Percentage"))
+                        .and_then(|t| t.trim().parse::<u8>().ok()),
+                    download_size: candidate.as_ref().map(|t| t.size()).unwrap_or(0),
+                    archive_cached: candidate.as_ref().is_some_and(|t| {
+                        is_archive_cached(
+                            Path::new(APT_ARCHIVES_DIR),
+                            &archive_file_name(pkg.name(), t.version(), t.arch()),
+                            t.size(),
+                        )
+                    }),
+                    installed_size_before: installed
+                        .as_ref()
+                        .map(|t| t.installed_size())
+                        .unwrap_or(0),
+                    installed_size_after: candidate
+                        .as_ref()
+                        .map(|t| t.installed_size())
+                        .unwrap_or(0),
+                })
+            })
+            .collect::<Vec<AptChange>>()
+    }};
+}
+
+impl AptBackend for RustAptBackend {
+    fn full_upgrade_changes(&self) -> Result<Vec<AptChange>, AptHelperError> {
+        let cache = new_cache!()
+            .map_err(|e| AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::Unknown))?;
+        cache.upgrade(Upgrade::FullUpgrade).map_err(|e| {
+            AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::UnresolvableDependencies)
+        })?;
+        cache.resolve(true).map_err(|e| {
+            AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::UnresolvableDependencies)
+        })?;
+        Ok(collect_apt_changes!(cache))
+    }
+
+    fn resolve_selected_changes(
+        &self,
+        selected: &[AptChange],
+        kept: &[AptChange],
+        kernels_to_keep: Option<u32>,
+    ) -> Result<(Vec<AptChange>, Vec<String>), AptHelperError> {
+        let cache = new_cache!()
+            .map_err(|e| AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::Unknown))?;
+        for change in kept {
+            if let Some(pkg) = cache.get(&format!("{}:{}", change.name, change.arch)) {
+                pkg.mark_keep();
+                pkg.protect();
+            }
+        }
+        for change in selected {
+            let pkg = match cache.get(&format!("{}:{}", change.name, change.arch)) {
+                Some(t) => t,
+                None => continue,
+            };
+            match change.action {
+                ChangeAction::Remove => pkg.mark_delete(false),
+                _ => pkg.mark_install(true, false),
+            };
+        }
+        // Like the helper, after the upgrade is marked so a new kernel counts as pending
+        let old_kernel_packages: Vec<String> = match kernels_to_keep {
+            Some(keep) => old_kernels(&cache, keep as usize)
+                .into_iter()
+                .flat_map(|t| t.packages)
+                .collect(),
+            None => Vec::new(),
+        };
+        for full_name in &old_kernel_packages {
+            if let Some(pkg) = cache.get(full_name) {
+                pkg.mark_delete(false);
+            }
+        }
+        cache.resolve(true).map_err(|e| {
+            AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::UnresolvableDependencies)
+        })?;
+        Ok((collect_apt_changes!(cache), old_kernel_packages))
+    }
+
+    fn relations(&self, changes: &[AptChange]) -> Result<Vec<PackageRelation>, AptHelperError> {
+        let cache = new_cache!()
+            .map_err(|e| AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::Unknown))?;
+        let mut relations = Vec::new();
+        for change in changes {
+            let pkg = match cache.get(&format!("{}:{}", change.name, change.arch)) {
+                Some(t) => t,
+                None => continue,
+            };
+            let version = match change.action {
+                ChangeAction::Remove => pkg.installed(),
+                _ => pkg.candidate(),
+            };
+            let version = match version {
+                Some(t) => t,
+                None => continue,
+            };
+            for (dep_type, kind) in [
+                (DepType::PreDepends, RelationKind::Depends),
+                (DepType::Depends, RelationKind::Depends),
+                (DepType::Recommends, RelationKind::Recommends),
+                (DepType::Conflicts, RelationKind::Conflicts),
+                (DepType::DpkgBreaks, RelationKind::Conflicts),
+            ] {
+                for dependency in version.get_depends(&dep_type).into_iter().flatten() {
+                    for base_dep in dependency.iter() {
+                        relations.push(PackageRelation {
+                            package: change.name.to_owned(),
+                            kind,
+                            target: base_dep.name().to_owned(),
+                            constraint: base_dep
+                                .comp_type()
+                                .zip(base_dep.version())
+                                .map(|(comp_type, version)| format!("{} {}", comp_type, version)),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(relations)
+    }
+
+    fn kept_back_changes(&self) -> Result<Vec<KeptBackPackage>, AptHelperError> {
+        let cache = new_cache!()
+            .map_err(|e| AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::Unknown))?;
+        cache.upgrade(Upgrade::FullUpgrade).map_err(|e| {
+            AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::UnresolvableDependencies)
+        })?;
+        let changed: Vec<String> = cache
+            .get_changes(false)
+            .map(|pkg| format!("{}:{}", pkg.name(), pkg.arch()))
+            .collect();
+        let kept_back_names: Vec<String> = cache
+            .packages(&PackageSort::default().upgradable())
+            .map(|pkg| format!("{}:{}", pkg.name(), pkg.arch()))
+            .filter(|full_name| !changed.contains(full_name))
+            .collect();
+
+        let mut kept_back = Vec::new();
+        for full_name in kept_back_names {
+            // A fresh cache for every trial, so they do not pile up
+            let trial_cache = new_cache!().map_err(|e| {
+                AptHelperError::from_apt(e.to_string(), AptHelperErrorKind::Unknown)
+            })?;
+            let pkg = match trial_cache.get(&full_name) {
+                Some(t) => t,
+                None => continue,
+            };
+            let dpkg_hold = pkg.selected_state() == PkgSelectedState::Hold;
+            let phased_update_percentage = pkg
+                .candidate()
+                .and_then(|t| t.get_record("Phased-Update-Percentage"))
+                .and_then(|t| t.trim().parse::<u8>().ok());
+            pkg.mark_install(false, true);
+            let change = match collect_apt_changes!(trial_cache)
+                .into_iter()
+                .find(|t| format!("{}:{}", t.name, t.arch) == full_name)
+            {
+                Some(t) => t,
+                None => continue,
+            };
+            pkg.mark_install(true, true);
+            let trial = match trial_cache.resolve(true) {
+                Ok(_) => Ok(collect_apt_changes!(trial_cache)
+                    .into_iter()
+                    .filter(|t| t.action == ChangeAction::Remove)
+                    .map(|t| t.name)
+                    .collect()),
+                Err(e) => Err(e.to_string()),
+            };
+            kept_back.push(KeptBackPackage::new(
+                change,
+                dpkg_hold,
+                phased_update_percentage,
+                trial,
+            ));
+        }
+        Ok(kept_back)
+    }
+
+    fn compare_versions(&self, a: &str, b: &str) -> Ordering {
+        rust_apt::util::cmp_versions(a, b)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FlatpakInstallationKind {
+    System,
+    User,
+}
+
+/// A Flatpak ref with a pending update.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FlatpakUpdate {
+    pub installation: FlatpakInstallationKind,
+    pub ref_format: String,
+    pub ref_name: Option<String>,
+    pub name: Option<String>,
+    pub arch: Option<String>,
+    pub branch: Option<String>,
+    pub summary: Option<String>,
+    pub origin: Option<String>,
+    pub remote_name: Option<String>,
+    pub installed_version: Option<String>,
+    pub installed_commit: Option<String>,
+    pub remote_commit: Option<String>,
+    pub installed_size_installed: u64,
+    pub installed_size_remote: u64,
+    pub download_size: u64,
+}
+
+/// Where the Flatpak state comes from.
+pub trait FlatpakBackend {
+    fn list_updates(
+        &self,
+        installation: FlatpakInstallationKind,
+    ) -> Result<Vec<FlatpakUpdate>, String>;
+}
+
+/// Pending updates of both installations, an unreadable installation is skipped.
+pub fn list_flatpak_updates(backend: &impl FlatpakBackend) -> Vec<FlatpakUpdate> {
+    let mut flatpak_updates = Vec::new();
+    for installation in [
+        FlatpakInstallationKind::System,
+        FlatpakInstallationKind::User,
+    ] {
+        match backend.list_updates(installation) {
+            Ok(t) => flatpak_updates.extend(t),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    flatpak_updates
+}
+
+/// The real libflatpak installations.
+pub struct LibflatpakBackend;
+
+impl LibflatpakBackend {
+    /// Looks up the remote side of an installed ref, the first enabled remote that has it wins.
+    fn fetch_remote_ref(
+        installation: &libflatpak::Installation,
+        flatpak_ref: &libflatpak::InstalledRef,
+    ) -> Option<libflatpak::RemoteRef> {
+        let cancellable_no = libflatpak::gio::Cancellable::NONE;
+        let remotes = installation.list_remotes(cancellable_no).ok()?;
+        for remote in remotes {
+            if remote.is_disabled() {
+                continue;
+            };
+            match installation.fetch_remote_ref_sync(
+                &match remote.name() {
+                    Some(t) => t,
+                    None => continue,
+                },
+                flatpak_ref.kind(),
+                &flatpak_ref.name()?,
+                flatpak_ref.arch().as_deref(),
+                flatpak_ref.branch().as_deref(),
+                cancellable_no,
+            ) {
+                Ok(t) => return Some(t),
+                Err(_) => continue,
+            }
+        }
+        None
+    }
+}
+
+impl FlatpakBackend for LibflatpakBackend {
+    fn list_updates(
+        &self,
+        installation_kind: FlatpakInstallationKind,
+    ) -> Result<Vec<FlatpakUpdate>, String> {
+        let cancellable_no = libflatpak::gio::Cancellable::NONE;
+        let installation = match installation_kind {
+            FlatpakInstallationKind::System => libflatpak::Installation::new_system(cancellable_no),
+            FlatpakInstallationKind::User => libflatpak::Installation::new_user(cancellable_no),
+        }
+        .map_err(|e| e.to_string())?;
+        let flatpak_refs = installation
+            .list_installed_refs_for_update(cancellable_no)
+            .map_err(|e| e.to_string())?;
+
+        let mut flatpak_updates = Vec::new();
+        for flatpak_ref in flatpak_refs {
+            let ref_format = match flatpak_ref.format_ref() {
+                Some(t) => t.to_string(),
+                None => continue,
+            };
+            let remote_flatpak_ref = Self::fetch_remote_ref(&installation, &flatpak_ref);
+            flatpak_updates.push(FlatpakUpdate {
+                installation: installation_kind,
+                ref_format,
+                ref_name: flatpak_ref.name().map(|t| t.to_string()),
+                name: flatpak_ref
+                    .appdata_name()
+                    .or(flatpak_ref.name())
+                    .map(|t| t.to_string()),
+                arch: flatpak_ref.arch().map(|t| t.to_string()),
+                branch: flatpak_ref.branch().map(|t| t.to_string()),
+                summary: flatpak_ref.appdata_summary().map(|t| t.to_string()),
+                origin: flatpak_ref.origin().map(|t| t.to_string()),
+                remote_name: remote_flatpak_ref
+                    .as_ref()
+                    .and_then(|t| t.remote_name())
+                    .map(|t| t.to_string()),
+                installed_version: flatpak_ref.appdata_version().map(|t| t.to_string()),
+                installed_commit: flatpak_ref.commit().map(|t| t.to_string()),
+                remote_commit: remote_flatpak_ref
+                    .as_ref()
+                    .and_then(|t| t.commit())
+                    .map(|t| t.to_string()),
+                installed_size_installed: flatpak_ref.installed_size(),
+                installed_size_remote: remote_flatpak_ref
+                    .as_ref()
+                    .map(|t| t.installed_size())
+                    .unwrap_or(0),
+                download_size: remote_flatpak_ref
+                    .as_ref()
+                    .map(|t| t.download_size())
+                    .unwrap_or(0),
+            });
+        }
+        Ok(flatpak_updates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::update_holds::UpdateHold;
+    use std::cell::RefCell;
+
+    fn change(name: &str, action: ChangeAction, before: u64, after: u64) -> AptChange {
+        AptChange {
+            name: name.to_owned(),
+            arch: "amd64".to_owned(),
+            source_name: name.to_owned(),
+            action,
+            installed_version: (before != 0).then(|| "1.0".to_owned()),
+            candidate_version: (action != ChangeAction::Remove).then(|| "2.0".to_owned()),
+            description: None,
+            source_uri: String::new(),
+            origin: None,
+            archive: None,
+            label: None,
+            update_class: UpdateClass::Regular,
+            maintainer: None,
+            phased_update_percentage: None,
+            download_size: if action == ChangeAction::Remove {
+                0
+            } else {
+                10
+            },
+            archive_cached: false,
+            installed_size_before: before,
+            installed_size_after: after,
+        }
+    }
+
+    /// Resolves a selection by returning it as is, plus whatever `extra` holds.
+    struct FakeAptBackend {
+        full_upgrade: Vec<AptChange>,
+        extra: Vec<AptChange>,
+        relations: Vec<PackageRelation>,
+        held_back: Vec<String>,
+        kept_back: Vec<KeptBackPackage>,
+        /// Removed when a kernels-to-keep policy is given.
+        old_kernels: Vec<AptChange>,
+        selected: RefCell<Vec<String>>,
+        kept: RefCell<Vec<String>>,
+    }
+
+    impl FakeAptBackend {
+        fn new(full_upgrade: Vec<AptChange>) -> Self {
+            Self {
+                full_upgrade,
+                extra: Vec::new(),
+                relations: Vec::new(),
+                held_back: Vec::new(),
+                kept_back: Vec::new(),
+                old_kernels: Vec::new(),
+                selected: RefCell::new(Vec::new()),
+                kept: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl AptBackend for FakeAptBackend {
+        fn full_upgrade_changes(&self) -> Result<Vec<AptChange>, AptHelperError> {
+            Ok(self.full_upgrade.clone())
+        }
+
+        fn resolve_selected_changes(
+            &self,
+            selected: &[AptChange],
+            kept: &[AptChange],
+            kernels_to_keep: Option<u32>,
+        ) -> Result<(Vec<AptChange>, Vec<String>), AptHelperError> {
+            *self.selected.borrow_mut() = selected.iter().map(|t| t.name.to_owned()).collect();
+            *self.kept.borrow_mut() = kept.iter().map(|t| t.name.to_owned()).collect();
+            let old_kernels: &[AptChange] = match kernels_to_keep {
+                Some(_) => &self.old_kernels,
+                None => &[],
+            };
+            Ok((
+                selected
+                    .iter()
+                    .filter(|t| !self.held_back.contains(&t.name))
+                    .chain(self.extra.iter())
+                    .chain(old_kernels.iter())
+                    .cloned()
+                    .collect(),
+                old_kernels
+                    .iter()
+                    .map(|t| format!("{}:{}", t.name, t.arch))
+                    .collect(),
+            ))
+        }
+
+        fn relations(
+            &self,
+            _changes: &[AptChange],
+        ) -> Result<Vec<PackageRelation>, AptHelperError> {
+            Ok(self.relations.clone())
+        }
+
+        fn kept_back_changes(&self) -> Result<Vec<KeptBackPackage>, AptHelperError> {
+            Ok(self.kept_back.clone())
+        }
+
+        fn compare_versions(&self, a: &str, b: &str) -> Ordering {
+            a.cmp(b)
+        }
+    }
+
+    struct FailingAptBackend;
+
+    impl AptBackend for FailingAptBackend {
+        fn full_upgrade_changes(&self) -> Result<Vec<AptChange>, AptHelperError> {
+            Err(AptHelperError::from_apt(
+                "E: Could not get lock /var/lib/dpkg/lock-frontend",
+                AptHelperErrorKind::Unknown,
+            ))
+        }
+
+        fn resolve_selected_changes(
+            &self,
+            _selected: &[AptChange],
+            _kept: &[AptChange],
+            _kernels_to_keep: Option<u32>,
+        ) -> Result<(Vec<AptChange>, Vec<String>), AptHelperError> {
+            unreachable!()
+        }
+
+        fn relations(
+            &self,
+            _changes: &[AptChange],
+        ) -> Result<Vec<PackageRelation>, AptHelperError> {
+            unreachable!()
+        }
+
+        fn kept_back_changes(&self) -> Result<Vec<KeptBackPackage>, AptHelperError> {
+            unreachable!()
+        }
+
+        fn compare_versions(&self, a: &str, b: &str) -> Ordering {
+            a.cmp(b)
+        }
+    }
+
+    #[test]
+    fn upgradable_packages_skips_removals() {
+        let backend = FakeAptBackend::new(vec![
+            change("foo", ChangeAction::Upgrade, 100, 120),
+            change("bar", ChangeAction::Remove, 50, 0),
+            change("baz", ChangeAction::Install, 0, 30),
+        ]);
+        let names: Vec<String> = upgradable_packages(&backend, &UpdateHolds::default())
+            .unwrap()
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(names, vec!["foo", "baz"]);
+    }
+
+    #[test]
+    fn simulation_counts_and_sizes() {
+        let mut backend = FakeAptBackend::new(vec![
+            change("foo", ChangeAction::Upgrade, 100, 120),
+            change("bar", ChangeAction::Remove, 50, 0),
+            change("baz", ChangeAction::Downgrade, 40, 30),
+        ]);
+        backend.extra = vec![change("dep", ChangeAction::Install, 0, 5)];
+        let info =
+            simulate_full_upgrade(&backend, &[], &[], &UpdateHolds::default(), None).unwrap();
+        assert_eq!(info.package_count_upgrade, 1);
+        assert_eq!(info.package_count_install, 1);
+        assert_eq!(info.package_count_downgrade, 1);
+        assert_eq!(info.package_count_remove, 1);
+        assert_eq!(info.total_download_size, 30);
+        assert_eq!(info.total_installed_size, 120 - 100 - 50 + 30 - 40 + 5);
+        assert_eq!(info.to_be_removed_packages, vec!["bar"]);
+        assert_eq!(info.changes.len(), 4);
+    }
+
+    #[test]
+    fn prefetched_archives_are_not_downloaded_again() {
+        let cached = |name: &str| AptChange {
+            archive_cached: true,
+            ..change(name, ChangeAction::Upgrade, 100, 120)
+        };
+        let backend = FakeAptBackend::new(vec![
+            cached("foo"),
+            change("bar", ChangeAction::Upgrade, 100, 120),
+        ]);
+        let info =
+            simulate_full_upgrade(&backend, &[], &[], &UpdateHolds::default(), None).unwrap();
+        assert_eq!(info.total_download_size, 10);
+        assert_eq!(info.cached_download_size, 10);
+        assert!(!info.is_ready_to_install());
+
+        let backend = FakeAptBackend::new(vec![cached("foo"), cached("bar")]);
+        let info =
+            simulate_full_upgrade(&backend, &[], &[], &UpdateHolds::default(), None).unwrap();
+        assert_eq!(info.total_download_size, 0);
+        assert!(info.is_ready_to_install());
+    }
+
+    #[test]
+    fn archive_names_match_apt() {
+        assert_eq!(
+            archive_file_name("libfoo1", "1:2.0-1", "amd64"),
+            "libfoo1_1%3a2.0-1_amd64.deb"
+        );
+        assert_eq!(
+            archive_file_name("foo", "2.0~beta_1", "all"),
+            "foo_2.0~beta%5f1_all.deb"
+        );
+
+        let dir = std::env::temp_dir().join(format!("pika-archives-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("foo_2.0_amd64.deb"), [0u8; 4]).unwrap();
+        assert!(is_archive_cached(&dir, "foo_2.0_amd64.deb", 4));
+        // A different size is a stale or truncated copy
+        assert!(!is_archive_cached(&dir, "foo_2.0_amd64.deb", 5));
+        assert!(!is_archive_cached(&dir, "bar_2.0_amd64.deb", 4));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn excluded_packages_are_kept() {
+        let backend = FakeAptBackend::new(vec![
+            change("foo", ChangeAction::Upgrade, 100, 120),
+            change("bar", ChangeAction::Remove, 50, 0),
+            change("libfoo1", ChangeAction::Upgrade, 10, 10),
+        ]);
+        let info = simulate_full_upgrade(
+            &backend,
+            &[Exclusion::exact("bar", None)],
+            &[],
+            &UpdateHolds::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(*backend.selected.borrow(), vec!["foo", "libfoo1"]);
+        assert_eq!(*backend.kept.borrow(), vec!["bar"]);
+        assert!(info.to_be_removed_packages.is_empty());
+        assert_eq!(info.package_count_remove, 0);
+    }
+
+    #[test]
+    fn exclusions_match_exact_names_only() {
+        let backend = FakeAptBackend::new(vec![
+            change("gcc", ChangeAction::Upgrade, 100, 120),
+            change("gcc-13", ChangeAction::Upgrade, 100, 120),
+            change("libgcc-s1", ChangeAction::Upgrade, 10, 10),
+        ]);
+        simulate_full_upgrade(
+            &backend,
+            &[Exclusion::parse("gcc")],
+            &[],
+            &UpdateHolds::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(*backend.kept.borrow(), vec!["gcc"]);
+
+        simulate_full_upgrade(
+            &backend,
+            &[Exclusion::parse("gcc:i386")],
+            &[],
+            &UpdateHolds::default(),
+            None,
+        )
+        .unwrap();
+        assert!(backend.kept.borrow().is_empty());
+
+        simulate_full_upgrade(
+            &backend,
+            &[Exclusion::parse("gcc*")],
+            &[],
+            &UpdateHolds::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(*backend.kept.borrow(), vec!["gcc", "gcc-13"]);
+
+        simulate_full_upgrade(
+            &backend,
+            &[Exclusion::regex("lib.*")],
+            &[],
+            &UpdateHolds::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(*backend.kept.borrow(), vec!["libgcc-s1"]);
+    }
+
+    #[test]
+    fn resolver_fallout_is_reported() {
+        let mut backend = FakeAptBackend::new(vec![
+            change("gcc", ChangeAction::Upgrade, 100, 120),
+            change("gcc-13", ChangeAction::Upgrade, 100, 120),
+        ]);
+        backend.held_back = vec!["gcc".to_owned()];
+        let info = simulate_full_upgrade(
+            &backend,
+            &[Exclusion::exact("gcc-13", Some("amd64"))],
+            &[],
+            &UpdateHolds::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(info.held_back_packages, vec!["gcc"]);
+        assert_eq!(info.package_count_upgrade, 0);
+    }
+
+    #[test]
+    fn kept_back_packages_are_explained_and_can_be_forced() {
+        let upgrade = |name: &str| change(name, ChangeAction::Upgrade, 100, 120);
+        let phased = KeptBackPackage::new(upgrade("mesa"), false, Some(30), Ok(Vec::new()));
+        assert_eq!(phased.reason, KeptBackReason::Phased);
+        assert_eq!(phased.detail.as_deref(), Some("30%"));
+        assert!(phased.can_force);
+        let on_hold = KeptBackPackage::new(upgrade("grub"), true, None, Ok(Vec::new()));
+        assert_eq!(on_hold.reason, KeptBackReason::DpkgHold);
+        assert!(!on_hold.can_force);
+        let broken = KeptBackPackage::new(upgrade("gdb"), false, None, Err("E: broken".to_owned()));
+        assert_eq!(broken.reason, KeptBackReason::BrokenDependencies);
+        assert!(!broken.can_force);
+        let removing =
+            KeptBackPackage::new(upgrade("vlc"), false, None, Ok(vec!["libvlc5".to_owned()]));
+        assert_eq!(removing.reason, KeptBackReason::NeedsRemovals);
+        assert_eq!(removing.detail.as_deref(), Some("libvlc5"));
+
+        let mut backend = FakeAptBackend::new(vec![upgrade("foo")]);
+        backend.kept_back = vec![phased, on_hold, removing];
+        let mut update_holds = UpdateHolds::default();
+        update_holds.add(UpdateHold {
+            package: "vlc".to_owned(),
+            until_version: None,
+        });
+        let names: Vec<String> = kept_back_packages(&backend, &update_holds)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.change.name)
+            .collect();
+        assert_eq!(names, vec!["mesa", "grub"]);
+
+        let info = simulate_full_upgrade(
+            &backend,
+            &[],
+            &[
+                ForcedUpgrade::new("mesa", "amd64"),
+                ForcedUpgrade::new("grub", "amd64"),
+            ],
+            &UpdateHolds::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(*backend.selected.borrow(), vec!["foo", "mesa"]);
+        assert_eq!(info.package_count_upgrade, 2);
+    }
+
+    #[test]
+    fn deferred_phased_updates_are_not_exclusions() {
+        let phased = KeptBackPackage::new(
+            change("mesa", ChangeAction::Upgrade, 100, 120),
+            false,
+            Some(30),
+            Ok(Vec::new()),
+        );
+        let mut backend = FakeAptBackend::new(vec![change("foo", ChangeAction::Upgrade, 100, 120)]);
+        backend.kept_back = vec![phased];
+
+        let info =
+            simulate_full_upgrade(&backend, &[], &[], &UpdateHolds::default(), None).unwrap();
+        assert!(backend.kept.borrow().is_empty());
+        assert!(info.held_back_packages.is_empty());
+        assert_eq!(*backend.selected.borrow(), vec!["foo"]);
+    }
+
+    #[test]
+    fn old_kernels_are_removed_with_the_upgrade() {
+        let mut backend = FakeAptBackend::new(vec![change("foo", ChangeAction::Upgrade, 100, 120)]);
+        backend.old_kernels = vec![
+            change("linux-image-6.8.0-pikaos", ChangeAction::Remove, 500, 0),
+            change("linux-headers-6.8.0-pikaos", ChangeAction::Remove, 300, 0),
+        ];
+
+        let info =
+            simulate_full_upgrade(&backend, &[], &[], &UpdateHolds::default(), None).unwrap();
+        assert!(info.to_be_removed_packages.is_empty());
+        assert_eq!(info.package_count_remove, 0);
+
+        let info =
+            simulate_full_upgrade(&backend, &[], &[], &UpdateHolds::default(), Some(2)).unwrap();
+        assert_eq!(
+            info.to_be_removed_packages,
+            vec!["linux-image-6.8.0-pikaos", "linux-headers-6.8.0-pikaos"]
+        );
+        assert_eq!(info.package_count_upgrade, 1);
+        assert_eq!(info.package_count_remove, 2);
+        assert_eq!(info.total_installed_size, 120 - 100 - 500 - 300);
+    }
+
+    #[test]
+    fn updates_are_classified_by_origin() {
+        let distro_origins =
+            distro_origins_from_os_release("NAME=\"PikaOS\"\nID=pika\nVERSION_ID=4\n");
+        let package_origin = |origin: &str, archive: &str, label: &str| PackageOrigin {
+            origin: Some(origin.to_owned()),
+            archive: Some(archive.to_owned()),
+            label: Some(label.to_owned()),
+        };
+        assert_eq!(
+            classify_update(
+                &[
+                    package_origin("Debian", "stable-updates", "Debian"),
+                    package_origin("Debian", "stable-security", "Debian-Security"),
+                ],
+                &distro_origins
+            ),
+            UpdateClass::Security
+        );
+        assert_eq!(
+            classify_update(
+                &[package_origin("PikaOS", "pika", "PikaOS")],
+                &distro_origins
+            ),
+            UpdateClass::Regular
+        );
+        assert_eq!(
+            classify_update(
+                &[package_origin("Google LLC", "stable", "Google")],
+                &distro_origins
+            ),
+            UpdateClass::ThirdParty
+        );
+        assert_eq!(classify_update(&[], &distro_origins), UpdateClass::Regular);
+    }
+
+    #[test]
+    fn security_only_mode_keeps_everything_else() {
+        let mut openssl = change("openssl", ChangeAction::Upgrade, 100, 120);
+        openssl.update_class = UpdateClass::Security;
+        let changes = vec![openssl, change("foo", ChangeAction::Upgrade, 100, 120)];
+        let backend = FakeAptBackend::new(changes.clone());
+        let info = simulate_full_upgrade(
+            &backend,
+            &security_only_exclusions(&changes),
+            &[],
+            &UpdateHolds::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(*backend.selected.borrow(), vec!["openssl"]);
+        assert_eq!(*backend.kept.borrow(), vec!["foo"]);
+        assert_eq!(info.package_count_security, 1);
+    }
+
+    #[test]
+    fn security_only_mode_lets_new_dependencies_in() {
+        let mut openssl = change("openssl", ChangeAction::Upgrade, 100, 120);
+        openssl.update_class = UpdateClass::Security;
+        let changes = vec![
+            openssl,
+            change("libssl4", ChangeAction::Install, 0, 50),
+            change("foo", ChangeAction::Upgrade, 100, 120),
+            change("bar", ChangeAction::Downgrade, 100, 90),
+        ];
+        assert_eq!(
+            security_only_exclusions(&changes),
+            vec![
+                Exclusion::exact("foo", Some("amd64")),
+                Exclusion::exact("bar", Some("amd64")),
+            ]
+        );
+    }
+
+    #[test]
+    fn changes_are_explained_by_dependency_chains() {
+        let relation = |package: &str, kind: RelationKind, target: &str| PackageRelation {
+            package: package.to_owned(),
+            kind,
+            target: target.to_owned(),
+            constraint: None,
+        };
+        let mut backend = FakeAptBackend::new(vec![
+            change("gcc", ChangeAction::Upgrade, 100, 120),
+            change("gcc-13", ChangeAction::Upgrade, 100, 120),
+            change("clang", ChangeAction::Upgrade, 100, 120),
+        ]);
+        backend.extra = vec![
+            change("gcc-14", ChangeAction::Install, 0, 100),
+            change("libgcc-14-dev", ChangeAction::Install, 0, 100),
+            change("libgcc-13-dev", ChangeAction::Remove, 100, 0),
+        ];
+        backend.held_back = vec!["clang".to_owned()];
+        backend.relations = vec![
+            relation("gcc", RelationKind::Depends, "gcc-14"),
+            relation("gcc-14", RelationKind::Depends, "libgcc-14-dev"),
+            relation("libgcc-14-dev", RelationKind::Conflicts, "libgcc-13-dev"),
+            relation("clang", RelationKind::Depends, "gcc-13"),
+        ];
+        let info = simulate_full_upgrade(
+            &backend,
+            &[Exclusion::exact("gcc-13", None)],
+            &[],
+            &UpdateHolds::default(),
+            None,
+        )
+        .unwrap();
+        let explanation = |package: &str| -> Vec<String> {
+            info.explanations
+                .iter()
+                .find(|t| t.package == package)
+                .unwrap()
+                .chain
+                .iter()
+                .map(|t| format!("{}>{}", t.package, t.target))
+                .collect()
+        };
+        assert_eq!(explanation("gcc-14"), vec!["gcc>gcc-14"]);
+        assert_eq!(
+            explanation("libgcc-13-dev"),
+            vec![
+                "gcc>gcc-14",
+                "gcc-14>libgcc-14-dev",
+                "libgcc-14-dev>libgcc-13-dev"
+            ]
+        );
+        assert_eq!(explanation("clang"), vec!["clang>gcc-13"]);
+    }
+
+    #[test]
+    fn holds_apply_until_their_version_is_available() {
+        let backend = FakeAptBackend::new(vec![
+            change("foo", ChangeAction::Upgrade, 100, 120),
+            change("bar", ChangeAction::Upgrade, 50, 60),
+            change("baz", ChangeAction::Upgrade, 40, 30),
+        ]);
+        let mut update_holds = UpdateHolds::default();
+        update_holds.add(UpdateHold {
+            package: "foo".to_owned(),
+            until_version: None,
+        });
+        update_holds.add(UpdateHold {
+            package: "bar".to_owned(),
+            until_version: Some("3.0".to_owned()),
+        });
+        update_holds.add(UpdateHold {
+            package: "baz".to_owned(),
+            until_version: Some("2.0".to_owned()),
+        });
+        let names: Vec<String> = upgradable_packages(&backend, &update_holds)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(names, vec!["baz"]);
+        simulate_full_upgrade(&backend, &[], &[], &update_holds, None).unwrap();
+        assert_eq!(*backend.selected.borrow(), vec!["baz"]);
+    }
+
+    #[test]
+    fn backend_errors_are_passed_on() {
+        let error =
+            simulate_full_upgrade(&FailingAptBackend, &[], &[], &UpdateHolds::default(), None)
+                .unwrap_err();
+        assert_eq!(error.kind, AptHelperErrorKind::LockHeld);
+    }
+
+    struct FakeFlatpakBackend;
+
+    impl FlatpakBackend for FakeFlatpakBackend {
+        fn list_updates(
+            &self,
+            installation: FlatpakInstallationKind,
+        ) -> Result<Vec<FlatpakUpdate>, String> {
+            match installation {
+                FlatpakInstallationKind::System => Ok(vec![FlatpakUpdate {
+                    installation,
+                    ref_format: "app/org.example.App/x86_64/stable".to_owned(),
+                    ref_name: Some("org.example.App".to_owned()),
+                    name: None,
+                    arch: Some("x86_64".to_owned()),
+                    branch: Some("stable".to_owned()),
+                    summary: None,
+                    origin: Some("flathub".to_owned()),
+                    remote_name: Some("flathub".to_owned()),
+                    installed_version: None,
+                    installed_commit: None,
+                    remote_commit: None,
+                    installed_size_installed: 1,
+                    installed_size_remote: 2,
+                    download_size: 3,
+                }]),
+                FlatpakInstallationKind::User => Err("no user installation".to_owned()),
+            }
+        }
+    }
+
+    #[test]
+    fn flatpak_updates_skip_broken_installations() {
+        let updates = list_flatpak_updates(&FakeFlatpakBackend);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].installation, FlatpakInstallationKind::System);
+    }
+}
@@ -0,0 +1,349 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::path::Path;
+
+/// Where the hold list lives, system-wide so the root helper reads it itself instead of
+/// trusting a copy handed over by the GUI.
+pub const UPDATE_HOLDS_PATH: &str = "/etc/pikman-update-manager/holds.json";
+/// Replaces the hold list with the JSON on its stdin, run through pkexec.
+pub const UPDATE_HOLDS_SCRIPT_PATH: &str =
+    "/usr/lib/pika/pikman-update-manager/scripts/update_holds";
+
+/// A package the user never wants updated, or not before `until_version` is available.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UpdateHold {
+    pub package: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until_version: Option<String>,
+}
+
+impl UpdateHold {
+    /// Whether an update of this package to `candidate_version` is held back.
+    pub fn holds_back(
+        &self,
+        candidate_version: Option<&str>,
+        compare_versions: impl Fn(&str, &str) -> Ordering,
+    ) -> bool {
+        match (&self.until_version, candidate_version) {
+            (Some(until_version), Some(candidate_version)) => {
+                compare_versions(candidate_version, until_version) == Ordering::Less
+            }
+            _ => true,
+        }
+    }
+}
+
+/// The persistent hold list, kept in `UPDATE_HOLDS_PATH`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct UpdateHolds {
+    #[serde(default)]
+    pub holds: Vec<UpdateHold>,
+}
+
+impl UpdateHolds {
+    /// Loads the system's holds, a missing or broken file means no holds.
+    pub fn load() -> Self {
+        let path = Path::new(UPDATE_HOLDS_PATH);
+        if !path.exists() {
+            return Self::default();
+        }
+        match Self::load_from(path) {
+            Ok(t) => t,
+            Err(e) => {
+                eprintln!("Ignoring {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&data).map_err(|e| e.to_string())
+    }
+
+    /// What `UPDATE_HOLDS_SCRIPT_PATH` expects on its stdin.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn get(&self, package: &str) -> Option<&UpdateHold> {
+        self.holds.iter().find(|t| t.package == package)
+    }
+
+    /// Adds a hold, replacing an older one for the same package.
+    pub fn add(&mut self, hold: UpdateHold) {
+        self.remove(&hold.package);
+        self.holds.push(hold);
+        self.holds.sort_by(|a, b| a.package.cmp(&b.package));
+    }
+
+    pub fn remove(&mut self, package: &str) {
+        self.holds.retain(|t| t.package != package);
+    }
+
+    pub fn holds_back(
+        &self,
+        package: &str,
+        candidate_version: Option<&str>,
+        compare_versions: impl Fn(&str, &str) -> Ordering,
+    ) -> bool {
+        match self.get(package) {
+            Some(hold) => hold.holds_back(candidate_version, compare_versions),
+            None => false,
+        }
+    }
+}
+
+/// How an exclusion's `package` is matched against package names.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExclusionPattern {
+    #[default]
+    Exact,
+    Glob,
+    Regex,
+}
+
+/// A package left out of a single upgrade, matched by exact name unless asked otherwise.
+///
+/// Build it with the constructors, they compile the pattern.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "ExclusionSpec")]
+pub struct Exclusion {
+    pub package: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    #[serde(default)]
+    pub pattern: ExclusionPattern,
+    /// Glob and regex patterns compiled once, they are matched against every upgrade.
+    #[serde(skip)]
+    matcher: Option<Result<regex::Regex, regex::Error>>,
+}
+
+/// An `Exclusion` as it is stored, before the pattern is compiled.
+#[derive(Deserialize)]
+struct ExclusionSpec {
+    package: String,
+    #[serde(default)]
+    arch: Option<String>,
+    #[serde(default)]
+    pattern: ExclusionPattern,
+}
+
+impl From<ExclusionSpec> for Exclusion {
+    fn from(spec: ExclusionSpec) -> Self {
+        Self::new(&spec.package, spec.arch.as_deref(), spec.pattern)
+    }
+}
+
+impl PartialEq for Exclusion {
+    fn eq(&self, other: &Self) -> bool {
+        self.package == other.package && self.arch == other.arch && self.pattern == other.pattern
+    }
+}
+
+impl Exclusion {
+    pub fn new(package: &str, arch: Option<&str>, pattern: ExclusionPattern) -> Self {
+        let matcher = match pattern {
+            ExclusionPattern::Exact => None,
+            ExclusionPattern::Glob => Some(glob_to_regex(package)),
+            ExclusionPattern::Regex => Some(package.to_owned()),
+        }
+        .map(|expression| regex::Regex::new(&format!("^(?:{})$", expression)));
+        Self {
+            package: package.to_owned(),
+            arch: arch.map(|t| t.to_owned()),
+            pattern,
+            matcher,
+        }
+    }
+
+    pub fn exact(package: &str, arch: Option<&str>) -> Self {
+        Self::new(package, arch, ExclusionPattern::Exact)
+    }
+
+    pub fn regex(expression: &str) -> Self {
+        Self::new(expression, None, ExclusionPattern::Regex)
+    }
+
+    /// Parses `name`, `name:arch` or a glob like `linux-image-*` as typed on the command line.
+    pub fn parse(spec: &str) -> Self {
+        let (package, arch) = match spec.rsplit_once(':') {
+            Some((package, arch)) if !arch.is_empty() && !is_glob(arch) => (package, Some(arch)),
+            _ => (spec, None),
+        };
+        let pattern = if is_glob(package) {
+            ExclusionPattern::Glob
+        } else {
+            ExclusionPattern::Exact
+        };
+        Self::new(package, arch, pattern)
+    }
+
+    /// Checks that a glob or regex exclusion actually compiles, a broken one matches nothing.
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.matcher {
+            Some(Err(e)) => Err(e.to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn matches(&self, package: &str, arch: &str) -> bool {
+        if let Some(exclusion_arch) = &self.arch {
+            if exclusion_arch != arch {
+                return false;
+            }
+        }
+        match &self.matcher {
+            None => self.package == package,
+            Some(Ok(t)) => t.is_match(package),
+            Some(Err(_)) => false,
+        }
+    }
+}
+
+fn is_glob(spec: &str) -> bool {
+    spec.contains(['*', '?', '['])
+}
+
+/// `*`, `?` and `[...]` classes like the shell has them, `[!...]` included. An unclosed
+/// class is left unclosed, so the pattern fails to compile.
+fn glob_to_regex(glob: &str) -> String {
+    let mut expression = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '*' => expression.push_str(".*"),
+            '?' => expression.push('.'),
+            '[' => {
+                expression.push('[');
+                if chars.next_if(|t| *t == '!' || *t == '^').is_some() {
+                    expression.push('^');
+                }
+                // A `]` right away is part of the class
+                if chars.next_if_eq(&']').is_some() {
+                    expression.push_str("\\]");
+                }
+                for ch in chars.by_ref() {
+                    match ch {
+                        ']' => {
+                            expression.push(']');
+                            break;
+                        }
+                        '-' => expression.push('-'),
+                        _ => expression.push_str(&regex::escape(&ch.to_string())),
+                    }
+                }
+            }
+            _ => expression.push_str(&regex::escape(&ch.to_string())),
+        }
+    }
+    expression
+}
+
+/// A kept-back package the user wants upgraded anyway.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ForcedUpgrade {
+    pub package: String,
+    pub arch: String,
+}
+
+impl ForcedUpgrade {
+    pub fn new(package: &str, arch: &str) -> Self {
+        Self {
+            package: package.to_owned(),
+            arch: arch.to_owned(),
+        }
+    }
+
+    pub fn matches(&self, package: &str, arch: &str) -> bool {
+        self.package == package && self.arch == arch
+    }
+}
+
+/// What the GUI hands to `apt_full_upgrade` through `--exclusions-file`, the holds are
+/// not part of it, the helper loads those itself.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ExclusionsFile {
+    #[serde(default)]
+    pub exclusions: Vec<Exclusion>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forced_upgrades: Vec<ForcedUpgrade>,
+}
+
+impl ExclusionsFile {
+    pub fn new(excluded_updates_vec: &[Exclusion], forced_upgrades_vec: &[ForcedUpgrade]) -> Self {
+        Self {
+            exclusions: excluded_updates_vec.to_vec(),
+            forced_upgrades: forced_upgrades_vec.to_vec(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exclusions.is_empty() && self.forced_upgrades.is_empty()
+    }
+
+    pub fn write_to(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusions_match_by_pattern() {
+        assert!(Exclusion::parse("gcc").matches("gcc", "amd64"));
+        assert!(!Exclusion::parse("gcc").matches("gcc-13", "amd64"));
+        assert!(!Exclusion::parse("gcc:i386").matches("gcc", "amd64"));
+        assert!(Exclusion::parse("linux-image-*").matches("linux-image-6.9", "amd64"));
+        assert!(!Exclusion::parse("linux-image-*").matches("xlinux-image-6.9", "amd64"));
+        assert!(Exclusion::regex("lib.*-dev").matches("libfoo-dev", "amd64"));
+        assert!(!Exclusion::regex("lib.*-dev").matches("libfoo-dev-doc", "amd64"));
+    }
+
+    #[test]
+    fn broken_regex_matches_nothing() {
+        let exclusion = Exclusion::regex("lib(");
+        assert!(exclusion.validate().is_err());
+        assert!(!exclusion.matches("lib(", "amd64"));
+        assert!(Exclusion::regex("lib.*").validate().is_ok());
+    }
+
+    #[test]
+    fn glob_classes() {
+        assert!(Exclusion::parse("linux-image-[0-9]*").matches("linux-image-6.9", "amd64"));
+        assert!(!Exclusion::parse("linux-image-[0-9]*").matches("linux-image-generic", "amd64"));
+        assert!(Exclusion::parse("lib[!x]*").matches("libfoo", "amd64"));
+        assert!(!Exclusion::parse("lib[!x]*").matches("libxml2", "amd64"));
+        assert!(Exclusion::parse("foo[.]bar").matches("foo.bar", "amd64"));
+        assert!(!Exclusion::parse("foo[.]bar").matches("fooxbar", "amd64"));
+        assert!(Exclusion::parse("gcc-[]1]").matches("gcc-]", "amd64"));
+    }
+
+    #[test]
+    fn broken_glob_is_reported() {
+        for glob in ["linux-image-[", "foo[", "lib[!x"] {
+            let exclusion = Exclusion::parse(glob);
+            assert_eq!(exclusion.pattern, ExclusionPattern::Glob);
+            assert!(exclusion.validate().is_err(), "{}", glob);
+        }
+        assert!(Exclusion::parse("linux-image-*").validate().is_ok());
+        assert!(Exclusion::parse("gcc").validate().is_ok());
+    }
+
+    #[test]
+    fn exclusions_are_compiled_when_read() {
+        let exclusions_file = ExclusionsFile::new(
+            &[Exclusion::regex("lib.*"), Exclusion::parse("gcc*:amd64")],
+            &[ForcedUpgrade::new("mesa", "amd64")],
+        );
+        let data = serde_json::to_string(&exclusions_file).unwrap();
+        let read: ExclusionsFile = serde_json::from_str(&data).unwrap();
+        assert_eq!(read, exclusions_file);
+        assert!(read.exclusions[0].matches("libfoo", "i386"));
+        assert!(read.exclusions[1].matches("gcc-13", "amd64"));
+        assert!(!read.exclusions[1].matches("gcc-13", "i386"));
+    }
+}